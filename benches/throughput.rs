@@ -0,0 +1,121 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::collections::BTreeSet;
+use tokeneer::{utok, Bpe, Lpe, Method};
+
+const PROSE: &str = "\
+The quick brown fox jumps over the lazy dog. Tokenizers split text into pieces \
+that a language model can understand, and the speed of this step matters when \
+millions of documents are processed every day. ";
+
+const CJK: &str = "\
+分词器把文本切分成语言模型可以理解的片段。每天要处理数百万篇文档时，这一步的速度非常重要。\
+自然语言处理的第一步通常就是分词。";
+
+const CODE: &str = "\
+fn main() {\n    let mut sum = 0usize;\n    for i in 0..100 {\n        if i % 3 == 0 {\n            \
+sum += i;\n        }\n    }\n    println!(\"{sum}\");\n}\n";
+
+const REPEATS: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaabababababababababababababab";
+
+/// 输入样例，每个样例重复到约 64 KiB。
+fn profiles() -> [(&'static str, String); 4] {
+    const SIZE: usize = 64 << 10;
+    let repeat = |s: &str| s.repeat(SIZE / s.len() + 1);
+    [
+        ("ascii-prose", repeat(PROSE)),
+        ("cjk", repeat(CJK)),
+        ("code", repeat(CODE)),
+        ("adversarial-repeats", repeat(REPEATS)),
+    ]
+}
+
+/// 从样例文本中收集所有长度不超过 4 个字符的子串作为词表，越长的词评分越高。
+fn synthetic_vocab() -> Vec<String> {
+    let mut pieces = BTreeSet::new();
+    for text in [PROSE, CJK, CODE, REPEATS] {
+        let chars = text.chars().collect::<Vec<_>>();
+        for len in 1..=4 {
+            for w in chars.windows(len) {
+                pieces.insert(w.iter().collect::<String>());
+            }
+        }
+    }
+    std::iter::once("<unk>".to_string()).chain(pieces).collect()
+}
+
+/// 从样例代码中收集所有长度不超过 64 字节的子串作为词表，模拟平均词长较长的代码词表。
+fn code_vocab() -> Vec<String> {
+    let mut pieces = BTreeSet::new();
+    let bounds = CODE
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([CODE.len()])
+        .collect::<Vec<_>>();
+    for &start in &bounds {
+        for &end in &bounds {
+            if (start + 1..=start + 64).contains(&end) {
+                pieces.insert(CODE[start..end].to_string());
+            }
+        }
+    }
+    std::iter::once("<unk>".to_string()).chain(pieces).collect()
+}
+
+fn bpe() -> Bpe {
+    if let Ok(buf) = std::fs::read("tokenizer.model") {
+        return Bpe::from_tokenizer_model(&buf);
+    }
+    let vocab = synthetic_vocab();
+    Bpe::new(
+        vocab.iter().map(String::as_str),
+        vocab.iter().map(|s| s.chars().count() as f32),
+        vec![false; vocab.len()],
+        0,
+    )
+}
+
+fn lpe() -> Lpe {
+    let vocab = synthetic_vocab();
+    Lpe::new(vocab.iter().map(String::as_bytes), 0)
+}
+
+fn bench_method(c: &mut Criterion, name: &str, method: &impl Method) {
+    let mut group = c.benchmark_group(name);
+    for (profile, text) in profiles() {
+        let tokens = method.encode(&text).into_iter().collect::<Vec<utok>>();
+
+        group.throughput(Throughput::Bytes(text.len() as _));
+        group.bench_with_input(BenchmarkId::new("encode", profile), &text, |b, text| {
+            b.iter(|| method.encode(text).into_iter().count())
+        });
+        group.throughput(Throughput::Elements(tokens.len() as _));
+        group.bench_with_input(BenchmarkId::new("decode", profile), &tokens, |b, tokens| {
+            b.iter(|| {
+                tokens
+                    .iter()
+                    .map(|&t| method.decode(t).len())
+                    .sum::<usize>()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn code_bpe() -> Bpe {
+    let vocab = code_vocab();
+    Bpe::new(
+        vocab.iter().map(String::as_str),
+        vocab.iter().map(|s| s.len() as f32),
+        vec![false; vocab.len()],
+        0,
+    )
+}
+
+fn throughput(c: &mut Criterion) {
+    bench_method(c, "bpe", &bpe());
+    bench_method(c, "lpe", &lpe());
+    bench_method(c, "bpe-code-vocab", &code_bpe());
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
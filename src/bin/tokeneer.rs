@@ -0,0 +1,183 @@
+//! 命令行工具。
+//!
+//! ```text
+//! tokeneer diff <base> <other> [--color | --no-color]
+//! tokeneer jsonl <in> <out> --vocab <path> [--field text] [--batch N] [--checkpoint path]
+//! ```
+//!
+//! `diff` 加载两个词表文件，以左右两栏列出新增、删除、序号改变和排名改变的词。
+//! 以 `.txt` 结尾的文件按 vocabs.txt 加载为 Lpe，其他文件按 tokenizer.model 加载为 Bpe。
+//! 默认在输出到终端且没有设置 `NO_COLOR` 时着色。没有差异时退出码为 0，有差异时为 1，出错时为 2。
+//!
+//! `jsonl` 用 `--vocab` 指定的词表编码 JSONL 语料中每个对象的 `--field` 字段（默认为 `text`），
+//! 每批 `--batch` 行（默认为 1024），进度输出到标准错误。指定 `--checkpoint` 时每批保存进度，中断后再次运行从检查点继续。
+//! 需要启用 `jsonl` 特性。成功时退出码为 0，出错时为 2。
+
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    process::ExitCode,
+};
+use tokeneer::{Bpe, Lpe, Tokeneer};
+
+const USAGE: &str = "\
+usage: tokeneer diff <base> <other> [--color | --no-color]
+       tokeneer jsonl <in> <out> --vocab <path> [--field text] [--batch N] [--checkpoint path]";
+
+enum Loaded {
+    Bpe(Tokeneer<Bpe>),
+    Lpe(Tokeneer<Lpe>),
+}
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        Some("diff") => diff(&args[1..]),
+        Some("jsonl") => jsonl(&args[1..]),
+        _ => Err(USAGE.into()),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// 比较两个词表并写出差异，返回两者是否相同。
+fn diff(args: &[String]) -> Result<bool, String> {
+    let mut color = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--color" => color = true,
+            "--no-color" => color = false,
+            _ => paths.push(arg),
+        }
+    }
+    let [base, other] = &*paths else {
+        return Err(USAGE.into());
+    };
+    let diff = match (load(base)?, load(other)?) {
+        (Loaded::Bpe(a), Loaded::Bpe(b)) => a.diff(&b),
+        (Loaded::Bpe(a), Loaded::Lpe(b)) => a.diff(&b),
+        (Loaded::Lpe(a), Loaded::Bpe(b)) => a.diff(&b),
+        (Loaded::Lpe(a), Loaded::Lpe(b)) => a.diff(&b),
+    };
+    diff.write_side_by_side(io::stdout().lock(), color)
+        .map_err(|e| e.to_string())?;
+    Ok(diff.is_empty())
+}
+
+/// 编码 JSONL 语料。
+#[cfg(feature = "jsonl")]
+fn jsonl(args: &[String]) -> Result<bool, String> {
+    let mut vocab = None;
+    let mut field = "text";
+    let mut batch = 1024;
+    let mut checkpoint = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} requires a value"));
+        match arg.as_str() {
+            "--vocab" => vocab = Some(value()?),
+            "--field" => field = value()?,
+            "--batch" => {
+                batch = value()?
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or("--batch requires a positive integer")?
+            }
+            "--checkpoint" => checkpoint = Some(value()?),
+            _ => paths.push(arg),
+        }
+    }
+    let ([input, output], Some(vocab)) = (&*paths, vocab) else {
+        return Err(USAGE.into());
+    };
+    let job = JsonlJob {
+        input,
+        output,
+        field,
+        batch,
+        checkpoint: checkpoint.map(String::as_str),
+    };
+    let progress = match load(vocab)? {
+        Loaded::Bpe(tokeneer) => job.run(&tokeneer),
+        Loaded::Lpe(tokeneer) => job.run(&tokeneer),
+    }?;
+    eprintln!();
+    eprintln!(
+        "done: {} documents, {} tokens",
+        progress.documents, progress.tokens
+    );
+    Ok(true)
+}
+
+#[cfg(not(feature = "jsonl"))]
+fn jsonl(_: &[String]) -> Result<bool, String> {
+    Err("tokeneer jsonl requires the `jsonl` feature".into())
+}
+
+/// `jsonl` 子命令的参数。
+#[cfg(feature = "jsonl")]
+struct JsonlJob<'a> {
+    input: &'a str,
+    output: &'a str,
+    field: &'a str,
+    batch: usize,
+    checkpoint: Option<&'a str>,
+}
+
+#[cfg(feature = "jsonl")]
+impl JsonlJob<'_> {
+    fn run<M: tokeneer::Method + Sync>(
+        &self,
+        tokeneer: &Tokeneer<M>,
+    ) -> Result<tokeneer::dataset::Progress, String> {
+        use tokeneer::dataset::{tokenize_jsonl, tokenize_jsonl_resumable, Progress};
+
+        let progress = |p: &Progress| {
+            eprint!(
+                "\r{} documents, {} tokens, {} bytes read",
+                p.documents, p.tokens, p.bytes
+            )
+        };
+        let open =
+            |path: &str, file: io::Result<fs::File>| file.map_err(|e| format!("{path}: {e}"));
+        match self.checkpoint {
+            Some(checkpoint) => tokenize_jsonl_resumable(
+                tokeneer,
+                self.input,
+                self.output,
+                checkpoint,
+                self.field,
+                self.batch,
+                progress,
+            ),
+            None => tokenize_jsonl(
+                tokeneer,
+                io::BufReader::new(open(self.input, fs::File::open(self.input))?),
+                io::BufWriter::new(open(self.output, fs::File::create(self.output))?),
+                self.field,
+                self.batch,
+                progress,
+            ),
+        }
+        .map_err(|e| format!("{}: {e}", self.input))
+    }
+}
+
+fn load(path: &str) -> Result<Loaded, String> {
+    let data = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    let loaded = if path.ends_with(".txt") {
+        Lpe::try_from_vocabs_txt(&data).map(|lpe| Loaded::Lpe(Tokeneer::new(lpe)))
+    } else {
+        Bpe::try_from_tokenizer_model(&data).map(|bpe| Loaded::Bpe(Tokeneer::new(bpe)))
+    };
+    loaded.map_err(|e| format!("{path}: {e}"))
+}
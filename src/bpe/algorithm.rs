@@ -1,9 +1,11 @@
-﻿use super::{utok, Bpe};
+use super::{offset, utok, Bpe, Offset};
 use std::{
+    cell::RefCell,
     cmp::Ordering::{self, Equal},
     collections::BinaryHeap,
     fmt,
     iter::zip,
+    mem::take,
     ops::Range,
 };
 
@@ -12,23 +14,83 @@ pub struct MergeState<'v, 't> {
     bpe: &'v Bpe,
     marks: Vec<Mark>,
     merges: BinaryHeap<Merge>,
+    /// 结束时将缓冲区归还线程内的工作区
+    reuse: bool,
 }
 
-pub struct IntoIter<'v> {
-    bpe: &'v Bpe,
+pub struct IntoIter {
     marks: Vec<Mark>,
     i: usize,
+    reuse: bool,
+}
+
+/// 线程内复用的合并缓冲区。
+#[derive(Default)]
+struct Workspace {
+    marks: Vec<Mark>,
+    merges: Vec<Merge>,
+}
+
+/// 归还工作区的缓冲区的最大容量，避免编码一次长文本后线程长期占用大量内存
+const WORKSPACE_CAPACITY: usize = 1 << 16;
+
+thread_local! {
+    static WORKSPACE: RefCell<Workspace> = RefCell::default();
+}
+
+impl Workspace {
+    /// 取出缓冲区，工作区正被使用（例如嵌套编码）时返回空的缓冲区。
+    fn take() -> (Vec<Mark>, Vec<Merge>) {
+        WORKSPACE
+            .try_with(|w| {
+                w.try_borrow_mut()
+                    .map(|mut w| (take(&mut w.marks), take(&mut w.merges)))
+                    .unwrap_or_default()
+            })
+            .unwrap_or_default()
+    }
+
+    /// 清空并归还缓冲区，只保留容量较大且不超过上限的缓冲区。
+    fn give_back(mut marks: Vec<Mark>, mut merges: Vec<Merge>) {
+        marks.clear();
+        merges.clear();
+        let _ = WORKSPACE.try_with(|w| {
+            if let Ok(mut w) = w.try_borrow_mut() {
+                if marks.capacity() <= WORKSPACE_CAPACITY && marks.capacity() > w.marks.capacity() {
+                    w.marks = marks
+                }
+                if merges.capacity() <= WORKSPACE_CAPACITY
+                    && merges.capacity() > w.merges.capacity()
+                {
+                    w.merges = merges
+                }
+            }
+        });
+    }
 }
 
 pub struct Iter<'a> {
-    bpe: &'a Bpe,
     marks: &'a [Mark],
 }
 
 impl Bpe {
     pub fn begin_merge<'v, 't>(&'v self, text: &'t str) -> MergeState<'v, 't> {
-        let mut marks = vec![Mark::unk(self.unk); text.len()];
-        let mut merges = BinaryHeap::new();
+        self.begin_merge_with(text, false)
+    }
+
+    /// 开始合并，`reuse` 为 `true` 时从线程内的工作区取出缓冲区，合并结束后归还。
+    pub(super) fn begin_merge_with<'v, 't>(
+        &'v self,
+        text: &'t str,
+        reuse: bool,
+    ) -> MergeState<'v, 't> {
+        let (mut marks, merges) = if reuse {
+            Workspace::take()
+        } else {
+            Default::default()
+        };
+        marks.resize(text.len(), Mark::VACANT);
+        let mut merges = BinaryHeap::from(merges);
 
         let mut buf = [0u8; 4];
         let mut last = None;
@@ -36,8 +98,9 @@ impl Bpe {
             let c = c.encode_utf8(&mut buf).as_bytes();
             last = if let Some(token) = self.find_piece(c) {
                 marks[i].token = token;
+                marks[i].len = offset(c.len());
                 if let Some(pos) = last.take() {
-                    marks[i].back_distance = (i - pos) as _;
+                    marks[i].back_distance = offset(i - pos);
                     if let Some(merge) = self.build_merge(
                         text.as_bytes(),
                         pos..i + c.len(),
@@ -50,6 +113,7 @@ impl Bpe {
             } else {
                 for (&b, mark) in zip(c, &mut marks[i..]) {
                     mark.token = self.bytes[b as usize];
+                    mark.len = 1;
                 }
                 None
             };
@@ -60,6 +124,34 @@ impl Bpe {
             bpe: self,
             marks,
             merges,
+            reuse,
+        }
+    }
+
+    /// 执行完整的合并过程，并记录初始状态、每一次合并和最终结果。
+    pub fn trace(&self, text: &str) -> MergeTrace {
+        let mut state = self.begin_merge(text);
+        let initial = state.iter().collect();
+        let merges = std::iter::from_fn(|| state.merge_once())
+            .map(
+                |Merge {
+                     pos,
+                     pair,
+                     merge,
+                     rank,
+                 }| AppliedMerge {
+                    pos,
+                    pair,
+                    merge,
+                    rank,
+                },
+            )
+            .collect();
+        MergeTrace {
+            text: text.into(),
+            initial,
+            merges,
+            tokens: state.into_iter().collect(),
         }
     }
 
@@ -68,24 +160,78 @@ impl Bpe {
             pos: range.start,
             pair,
             merge: merged,
-            rank: self.token(merged).rank,
+            rank: self.tokens[merged as usize].rank,
         })
     }
 }
 
+/// 合并队列中的一项，按 [`MergeState::pending_merges`] 返回的顺序依次尝试。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PendingMerge {
+    /// 合并的左侧 token 在文本中的字节位置
+    pub pos: usize,
+    /// 参与合并的两个 token
+    pub pair: (utok, utok),
+    /// 合并产生的 token
+    pub merge: utok,
+    /// 合并产生的 token 的排名，越小越优先
+    pub rank: u32,
+    /// 此项是否仍然有效，已失效的项出队时将被跳过
+    pub valid: bool,
+}
+
+/// 完整合并过程的记录，可以序列化为 JSON 以便在版本之间或与其他实现对比。
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MergeTrace {
+    /// 输入文本
+    pub text: String,
+    /// 合并开始前的 token 序列
+    pub initial: Vec<utok>,
+    /// 按执行顺序记录的每一次合并
+    pub merges: Vec<AppliedMerge>,
+    /// 合并结束后的 token 序列
+    pub tokens: Vec<utok>,
+}
+
+/// [`MergeTrace`] 中记录的一次合并。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AppliedMerge {
+    /// 合并的左侧 token 在文本中的字节位置
+    pub pos: usize,
+    /// 参与合并的两个 token
+    pub pair: (utok, utok),
+    /// 合并产生的 token
+    pub merge: utok,
+    /// 合并产生的 token 的排名
+    pub rank: u32,
+}
+
+/// 文本中每个字节位置的标记。
+///
+/// 只有词起点的标记有效，其 `len` 是这个词覆盖的字节数，不依赖词的内容，
+/// 因此 <unk> 等内容长度与所代替的文本不同的词也能正确定位。
 #[derive(Clone, Copy, Debug)]
 struct Mark {
     token: utok,
-    back_distance: u32,
+    back_distance: Offset,
+    /// 从此位置开始的词覆盖的字节数，为 0 表示此位置不是词的起点
+    len: Offset,
 }
 
 impl Mark {
+    /// 不是任何词起点的位置
+    const VACANT: Self = Self {
+        token: 0,
+        back_distance: 0,
+        len: 0,
+    };
+
+    /// 此位置是否是词序号为 `token` 的词的起点。
     #[inline(always)]
-    const fn unk(unk: utok) -> Self {
-        Self {
-            token: unk,
-            back_distance: 0,
-        }
+    fn is(&self, token: utok) -> bool {
+        self.len != 0 && self.token == token
     }
 }
 
@@ -121,7 +267,27 @@ impl PartialOrd for Merge {
 
 impl MergeState<'_, '_> {
     /// 尝试执行一次合并，返回是否成功执行了一次合并。
+    #[inline]
     pub fn merge(&mut self) -> bool {
+        self.merge_once().is_some()
+    }
+
+    /// 以 BPE-dropout 尝试执行一次合并，每个合并项以概率 `p` 被跳过，返回是否成功执行了一次合并。
+    ///
+    /// 被跳过的合并项在下一次成功合并后重新参与竞争。
+    #[cfg(feature = "rand")]
+    pub fn merge_with_dropout(&mut self, p: f32, rng: &mut (impl rand::Rng + ?Sized)) -> bool {
+        self.merge_once_with(|| rng.random::<f32>() < p).is_some()
+    }
+
+    /// 尝试执行一次合并，返回执行的合并项。
+    #[inline]
+    fn merge_once(&mut self) -> Option<Merge> {
+        self.merge_once_with(|| false)
+    }
+
+    /// 尝试执行一次合并，`skip` 返回 `true` 的有效合并项将被暂时跳过。
+    fn merge_once_with(&mut self, mut skip: impl FnMut() -> bool) -> Option<Merge> {
         // 一次合并将涉及至多 4 个 token：
         //
         // t0 t1 t2 t3
@@ -137,28 +303,35 @@ impl MergeState<'_, '_> {
         //    --------
         // --------
 
+        // 被跳过的合并项
+        let mut skipped = Vec::new();
         // 从合并队列消费
-        while let Some(Merge {
-            pos: p1,
-            pair: (t1, t2),
-            merge,
-            ..
-        }) = self.merges.pop()
-        {
+        while let Some(applied) = self.merges.pop() {
+            let Merge {
+                pos: p1,
+                pair: (t1, t2),
+                merge,
+                ..
+            } = applied;
             // 确认合并项有效性
-            if self.marks[p1].token != t1 {
+            if !self.marks[p1].is(t1) {
                 continue;
             }
-            let l1 = self.bpe.token(t1).len();
+            let l1 = self.marks[p1].len as usize;
             let p2 = p1 + l1;
-            if self.marks[p2].token != t2 {
+            if !self.marks.get(p2).is_some_and(|m| m.is(t2)) {
+                continue;
+            }
+            if skip() {
+                skipped.push(applied);
                 continue;
             }
             // 合并
+            let l2 = self.marks[p2].len as usize;
             self.marks[p1].token = merge;
-            self.marks[p2].token = self.bpe.unk;
+            self.marks[p1].len = offset(l1 + l2);
+            self.marks[p2].len = 0;
 
-            let l2 = self.bpe.token(t2).len();
             let p3 = p2 + l2;
             // 创建 merge + t3 合并项
             match self.marks.get_mut(p3) {
@@ -166,12 +339,12 @@ impl MergeState<'_, '_> {
                 Some(Mark {
                     token,
                     back_distance,
+                    len,
                 }) => {
-                    *back_distance = (l1 + l2) as _;
+                    *back_distance = offset(l1 + l2);
 
                     let t3 = *token;
-                    let l3 = self.bpe.token(t3).len();
-                    let p4 = p3 + l3;
+                    let p4 = p3 + *len as usize;
                     if let Some(merge) = self.bpe.build_merge(self.text, p1..p4, (merge, t3)) {
                         self.merges.push(merge);
                     }
@@ -188,49 +361,86 @@ impl MergeState<'_, '_> {
                     }
                 }
             }
-            // 成功合并
-            return true;
+            // 成功合并，被跳过的项重新入队
+            self.merges.extend(skipped);
+            return Some(applied);
         }
-        false
+        None
+    }
+
+    /// 按出队顺序列出合并队列中的所有项，包括已失效的项。
+    pub fn pending_merges(&self) -> impl Iterator<Item = PendingMerge> + '_ {
+        self.merges
+            .clone()
+            .into_sorted_vec()
+            .into_iter()
+            .rev()
+            .map(|merge| PendingMerge {
+                pos: merge.pos,
+                pair: merge.pair,
+                merge: merge.merge,
+                rank: merge.rank,
+                valid: self.is_valid(&merge),
+            })
+    }
+
+    /// 判断合并项涉及的两个 token 是否仍在原位。
+    fn is_valid(&self, merge: &Merge) -> bool {
+        let Merge {
+            pos: p1,
+            pair: (t1, t2),
+            ..
+        } = *merge;
+        self.marks[p1].is(t1)
+            && self
+                .marks
+                .get(p1 + self.marks[p1].len as usize)
+                .is_some_and(|m| m.is(t2))
     }
 
     #[inline]
-    pub fn iter(&self) -> Iter {
-        Iter {
-            bpe: self.bpe,
-            marks: &self.marks,
-        }
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { marks: &self.marks }
     }
 }
 
-impl<'v> IntoIterator for MergeState<'v, '_> {
+impl IntoIterator for MergeState<'_, '_> {
     type Item = utok;
-    type IntoIter = IntoIter<'v>;
+    type IntoIter = IntoIter;
     #[inline]
-    fn into_iter(self) -> Self::IntoIter {
+    fn into_iter(mut self) -> Self::IntoIter {
         Self::IntoIter {
-            bpe: self.bpe,
-            marks: self.marks,
+            marks: take(&mut self.marks),
             i: 0,
+            reuse: self.reuse,
         }
     }
 }
 
-impl Iterator for IntoIter<'_> {
+impl Drop for MergeState<'_, '_> {
+    fn drop(&mut self) {
+        if self.reuse {
+            Workspace::give_back(take(&mut self.marks), take(&mut self.merges).into_vec())
+        }
+    }
+}
+
+impl Drop for IntoIter {
+    fn drop(&mut self) {
+        if self.reuse {
+            Workspace::give_back(take(&mut self.marks), Vec::new())
+        }
+    }
+}
+
+impl Iterator for IntoIter {
     type Item = utok;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match &self.marks[self.i..] {
-            &[Mark { token, .. }, ..] => {
-                self.i += if token == self.bpe.unk {
-                    1
-                } else {
-                    self.bpe.token(token).len()
-                };
-                Some(token)
-            }
-            [] => None,
-        }
+        let &Mark { token, len, .. } = self.marks.get(self.i)?;
+        // 标记不一致时至少前进一个位置，保证迭代终止
+        self.i = self.i.saturating_add(len.max(1) as usize);
+        Some(token)
     }
 }
 
@@ -238,36 +448,26 @@ impl Iterator for Iter<'_> {
     type Item = utok;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.marks {
-            &[Mark { token, .. }, ref tail @ ..] => {
-                self.marks = if token == self.bpe.unk {
-                    tail
-                } else {
-                    &tail[self.bpe.token(token).len() - 1..]
-                };
-                Some(token)
-            }
-            [] => None,
-        }
+        let &Mark { token, len, .. } = self.marks.first()?;
+        // 标记不一致时至少前进一个位置，并且不越过文本末尾
+        self.marks = self.marks.get(len.max(1) as usize..).unwrap_or_default();
+        Some(token)
     }
 }
 
 impl fmt::Display for MergeState<'_, '_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use std::str::{from_utf8, from_utf8_unchecked};
-
         writeln!(f, "---------------------------")?;
         {
             writeln!(f, "text:")?;
-            writeln!(f, "  {}", unsafe { from_utf8_unchecked(self.text) })?;
+            writeln!(f, "  {}", Escape(self.text))?;
         }
         writeln!(f, "---------------------------")?;
         {
             writeln!(f, "tokens:")?;
             write!(f, "  ")?;
             for token in self.iter() {
-                let text = unsafe { from_utf8_unchecked(self.bpe.token(token)) };
-                write!(f, "{text}")?;
+                write!(f, "{}", Escape(self.bpe.token(token)))?;
             }
             writeln!(f)?;
         }
@@ -275,11 +475,7 @@ impl fmt::Display for MergeState<'_, '_> {
         {
             writeln!(f, "tokens:")?;
             for token in self.iter() {
-                write!(f, "  {token:>6}: ")?;
-                match from_utf8(self.bpe.token(token)) {
-                    Ok(s) => writeln!(f, "{s}")?,
-                    Err(_) => writeln!(f, "{token:?}")?,
-                }
+                writeln!(f, "  {token:>6}: {}", Escape(self.bpe.token(token)))?;
             }
         }
         writeln!(f, "---------------------------")?;
@@ -292,10 +488,66 @@ impl fmt::Display for MergeState<'_, '_> {
                 ..
             }) = merges.pop()
             {
-                let text = unsafe { from_utf8_unchecked(self.bpe.token(merged)) };
-                writeln!(f, "  {rank:>6} | {text}")?;
+                writeln!(f, "  {rank:>6} | {}", Escape(self.bpe.token(merged)))?;
             }
         }
         writeln!(f, "---------------------------")
     }
 }
+
+/// 单行的紧凑格式，形如 `MergeState { text: "abd", tokens: [1:"a" 8:"bd"], merges: 1 }`。
+impl fmt::Debug for MergeState<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MergeState {{ text: \"{}\", tokens: [",
+            Escape(self.text)
+        )?;
+        for (i, token) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{token}:\"{}\"", Escape(self.bpe.token(token)))?;
+        }
+        write!(f, "], merges: {} }}", self.merges.len())
+    }
+}
+
+/// 按 utf-8 显示字节序列，无法解码的字节转义为 `\xAB`。
+struct Escape<'a>(&'a [u8]);
+
+impl fmt::Display for Escape<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in self.0.utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            for b in chunk.invalid() {
+                write!(f, "\\x{b:02X}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod algorithm_tests {
+    use super::*;
+
+    #[test]
+    fn test_inconsistent_marks() {
+        let mark = |token, len| Mark {
+            token,
+            back_distance: 0,
+            len,
+        };
+        // 长度为 0 的起点和越过末尾的长度都不会使迭代 panic 或不终止
+        let marks = [mark(1, 0), mark(2, 5), mark(3, 1)];
+        assert_eq!(Iter { marks: &marks }.collect::<Vec<_>>(), [1, 2]);
+        let iter = IntoIter {
+            marks: marks.to_vec(),
+            i: 0,
+            reuse: false,
+        };
+        assert_eq!(iter.collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(Iter { marks: &[] }.next(), None);
+    }
+}
@@ -0,0 +1,155 @@
+use super::{as_str, utok, AppliedMerge, Bpe};
+use std::collections::BTreeMap;
+
+/// 词的推导树，记录词是由哪些词逐步合并得到的。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Derivation {
+    /// 合并开始前的初始词
+    Leaf(utok),
+    /// 由左右两个词合并得到的词
+    Merge(utok, Box<[Derivation; 2]>),
+}
+
+impl Derivation {
+    /// 树根对应的词。
+    #[inline]
+    pub fn token(&self) -> utok {
+        match *self {
+            Self::Leaf(t) | Self::Merge(t, _) => t,
+        }
+    }
+}
+
+impl Bpe {
+    /// 找出最后一步合并得到 `token` 的两个词。
+    ///
+    /// 单字符词、单字节词和合词规则不可达的词不由合并产生，返回 `None`。
+    pub fn decompose(&self, token: utok) -> Option<(utok, utok)> {
+        let (_, merges) = self.derive(token)?;
+        merges.last().map(|m| m.pair)
+    }
+
+    /// 构造 `token` 完整的推导树。
+    ///
+    /// 推导过程与编码 `token` 自身内容的合并过程相同，合词规则不可达的词返回 `None`。
+    pub fn derivation(&self, token: utok) -> Option<Derivation> {
+        let (initial, merges) = self.derive(token)?;
+        // 以字节位置索引尚未合并的子树
+        let mut pos = 0;
+        let mut nodes = initial
+            .into_iter()
+            .map(|t| {
+                let node = (pos, Derivation::Leaf(t));
+                pos += self.token(t).len();
+                node
+            })
+            .collect::<BTreeMap<_, _>>();
+        for AppliedMerge {
+            pos,
+            pair: (left, _),
+            merge,
+            ..
+        } in merges
+        {
+            let l = nodes.remove(&pos).unwrap();
+            let r = nodes.remove(&(pos + self.token(left).len())).unwrap();
+            nodes.insert(pos, Derivation::Merge(merge, Box::new([l, r])));
+        }
+        nodes.pop_first().map(|(_, node)| node)
+    }
+
+    /// 给出 [`inaccessible`](Self::inaccessible) 中每个词不可达的原因，按词序号排列。
+    ///
+    /// 词的内容可以拆成词表中的两个词时，只报告拆分点最靠前的一种拆法。
+    pub fn inaccessible_report(&self) -> Vec<InaccessibleToken> {
+        let mut ans = self
+            .inaccessible
+            .iter()
+            .map(|&token| self.explain_inaccessible(token))
+            .collect::<Vec<_>>();
+        ans.sort_unstable_by_key(|t| t.token);
+        ans
+    }
+
+    fn explain_inaccessible(&self, token: utok) -> InaccessibleToken {
+        let text = as_str(self.token(token));
+        let trace = self.trace(text);
+        let pair = text.char_indices().skip(1).find_map(|(p, _)| {
+            let (left, right) = text.split_at(p);
+            Some((
+                p,
+                self.find_piece(left.as_bytes())?,
+                self.find_piece(right.as_bytes())?,
+            ))
+        });
+        let reason = match pair {
+            None => InaccessibleReason::NoPair,
+            Some((p, left, right)) => {
+                let len = |t: utok| self.token(t).len();
+                match trace
+                    .merges
+                    .iter()
+                    .find(|m| m.pos < p && p < m.pos + len(m.merge))
+                {
+                    Some(&blocker) => InaccessibleReason::Blocked {
+                        pair: (left, right),
+                        blocker,
+                    },
+                    None => {
+                        // 没有合并跨过拆分点，左右两段各自编码，至少有一段没有合并成一个词
+                        let mut pos = 0;
+                        let left_formed = trace.tokens.iter().any(|&t| {
+                            pos += len(t);
+                            pos == p && t == left
+                        });
+                        InaccessibleReason::MissingPart {
+                            pair: (left, right),
+                            missing: if left_formed { right } else { left },
+                        }
+                    }
+                }
+            }
+        };
+        InaccessibleToken {
+            token,
+            encoded: trace.tokens,
+            reason,
+        }
+    }
+
+    /// 编码词自身的内容，编码结果恰为这个词时返回初始词序列和合并过程。
+    fn derive(&self, token: utok) -> Option<(Vec<utok>, Vec<AppliedMerge>)> {
+        if Some(token) == self.unk {
+            return None;
+        }
+        let text =
+            std::str::from_utf8(self.tokens.get(token as usize)?.piece(&self.vocabs)).ok()?;
+        let trace = self.trace(text);
+        (trace.tokens == [token]).then_some((trace.initial, trace.merges))
+    }
+}
+
+/// 不可达的词及其原因，见 [`Bpe::inaccessible_report`]。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InaccessibleToken {
+    /// 不可达的词
+    pub token: utok,
+    /// 编码这个词的内容实际得到的词序列
+    pub encoded: Vec<utok>,
+    /// 不可达的原因
+    pub reason: InaccessibleReason,
+}
+
+/// 词不可达的原因。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InaccessibleReason {
+    /// 词的内容无法拆成词表中的两个词，没有合并能产生它
+    NoPair,
+    /// 词可以由 `pair` 合并得到，但排名更高的合并 `blocker` 先跨过了两者的分界
+    Blocked {
+        pair: (utok, utok),
+        blocker: AppliedMerge,
+    },
+    /// 词可以由 `pair` 合并得到，但其中的 `missing` 在这段文本中无法合并得到
+    MissingPart { pair: (utok, utok), missing: utok },
+}
@@ -0,0 +1,153 @@
+use super::Bpe;
+use crate::{utok, Method};
+
+/// sentencepiece 中词的类型
+const NORMAL: u64 = 1;
+const UNKNOWN: u64 = 2;
+const CONTROL: u64 = 3;
+const BYTE: u64 = 6;
+
+impl Bpe {
+    /// 导出时使用的评分，由合并排名换算，与原评分的顺序和并列关系相同。
+    ///
+    /// 裁剪后的词表按保留的词重新计算排名，因此导出的评分仍然连续，重新加载后排名不变。
+    #[inline]
+    pub fn export_score(&self, token: utok) -> f32 {
+        // 避免排名 0 导出为 -0
+        0. - self.tokens[token as usize].rank as f32
+    }
+
+    /// 序列化为 sentencepiece 的 tokenizer.model 格式，重新加载后编码结果不变。
+    ///
+    /// 评分见 [`export_score`](Self::export_score)；单字节词写为 `<0xAB>`，合并不可达的词标记为控制词。
+    /// 词按保存的形式导出，用 [`new_with_transform`](Self::new_with_transform) 转换过的词不会还原。
+    /// 归一化设置为 `identity`，与直接调用分词算法的行为一致。
+    pub fn to_tokenizer_model(&self) -> Vec<u8> {
+        let inaccessible = self
+            .inaccessible
+            .iter()
+            .collect::<std::collections::HashSet<_>>();
+        let mut ans = Vec::new();
+        for token in 0..self.tokens.len() as utok {
+            let (piece, ty) = match self.export_piece(token) {
+                (piece, _) if Some(token) == self.unk => (piece, UNKNOWN),
+                (piece, true) => (piece, BYTE),
+                (piece, false) if inaccessible.contains(&token) => (piece, CONTROL),
+                (piece, false) => (piece, NORMAL),
+            };
+            let mut record = Vec::new();
+            bytes_field(&mut record, 1, piece.as_bytes());
+            record.push(2 << 3 | 5);
+            record.extend_from_slice(&self.export_score(token).to_le_bytes());
+            varint_field(&mut record, 3, ty);
+            bytes_field(&mut ans, 1, &record);
+        }
+        // trainer_spec：bpe 模型，没有 <s> 和 </s>
+        let mut trainer = Vec::new();
+        varint_field(&mut trainer, 3, 2);
+        varint_field(&mut trainer, 4, self.tokens.len() as u64);
+        varint_field(
+            &mut trainer,
+            35,
+            self.bytes.iter().any(|&t| Some(t) != self.unk) as u64,
+        );
+        if let Some(unk) = self.unk {
+            varint_field(&mut trainer, 40, unk as u64);
+        }
+        varint_field(&mut trainer, 41, -1i64 as u64);
+        varint_field(&mut trainer, 42, -1i64 as u64);
+        bytes_field(&mut ans, 2, &trainer);
+        // normalizer_spec：不做任何归一化
+        let mut normalizer = Vec::new();
+        bytes_field(&mut normalizer, 1, b"identity");
+        varint_field(&mut normalizer, 3, 0);
+        varint_field(&mut normalizer, 4, 0);
+        varint_field(&mut normalizer, 5, 0);
+        bytes_field(&mut ans, 3, &normalizer);
+        ans
+    }
+
+    /// 序列化为 HF tokenizers 的 tokenizer.json 格式，只包含 `model` 一节。
+    ///
+    /// 每个由两个词拼接而成的词按排名依次生成合并规则，排名相同时按词序号排列；
+    /// 单字节词只作为回退，不参与合并。内容重复的词只保留序号最小的一个。
+    #[cfg(feature = "tokenizer-json")]
+    pub fn to_tokenizer_json(&self) -> serde_json::Value {
+        use serde_json::{json, Map, Value};
+
+        let mut vocab = Map::new();
+        for token in 0..self.tokens.len() as utok {
+            let (piece, _) = self.export_piece(token);
+            vocab.entry(piece).or_insert(Value::from(token));
+        }
+        let mut merged = (0..self.tokens.len() as utok)
+            .filter(|&t| Some(t) != self.unk && !self.export_piece(t).1)
+            .collect::<Vec<_>>();
+        merged.sort_by_key(|&t| (self.tokens[t as usize].rank, t));
+        let mut merges = Vec::new();
+        for t in merged {
+            let Ok(piece) = std::str::from_utf8(self.token(t)) else {
+                continue;
+            };
+            let half = |s: &str| {
+                self.find_piece(s.as_bytes())
+                    .filter(|&t| self.fallback_byte(t).is_none())
+            };
+            for (i, _) in piece.char_indices().skip(1) {
+                let (left, right) = piece.split_at(i);
+                if half(left).is_some() && half(right).is_some() {
+                    merges.push(json!([left, right]))
+                }
+            }
+        }
+        let unk = self.unk.map(|t| self.export_piece(t).0);
+        json!({
+            "version": "1.0",
+            "model": {
+                "type": "BPE",
+                "dropout": null,
+                "unk_token": unk,
+                "fuse_unk": true,
+                "byte_fallback": self.bytes.iter().any(|&t| Some(t) != self.unk),
+                "vocab": vocab,
+                "merges": merges,
+            }
+        })
+    }
+
+    /// 导出的词的内容和是否是单字节词，单字节词写为 `<0xAB>`。
+    fn export_piece(&self, token: utok) -> (String, bool) {
+        let shadowed = || {
+            self.byte_error
+                .iter()
+                .any(|e| e.duplicates.iter().any(|&(_, t)| t == token))
+        };
+        match *self.token(token) {
+            [b] if self.fallback_byte(token).is_some() || shadowed() => {
+                (format!("<0x{b:02X}>"), true)
+            }
+            ref piece => (String::from_utf8_lossy(piece).into_owned(), false),
+        }
+    }
+}
+
+/// 写入 protobuf 的变长整数字段。
+fn varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    varint(buf, (field as u64) << 3);
+    varint(buf, value)
+}
+
+/// 写入 protobuf 的长度前缀字段。
+fn bytes_field(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    varint(buf, (field as u64) << 3 | 2);
+    varint(buf, value.len() as u64);
+    buf.extend_from_slice(value)
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7
+    }
+    buf.push(value as u8)
+}
@@ -0,0 +1,126 @@
+//! 从词的内容查找词序号的索引。
+
+use crate::{hash::StableHasher, memory::hash_map_size, utok};
+use std::{collections::HashMap, hash::Hasher, ops::Range};
+
+/// 平均词长超过此值（字节）时使用前缀哈希索引。
+///
+/// 词越长，二分查找中每次比较的代价越高，而哈希只读取固定长度的前缀。
+pub(super) const HASH_THRESHOLD: usize = 16;
+
+/// 参与哈希的前缀长度。
+const PREFIX: usize = 16;
+
+/// 以词长和前缀哈希为键的索引，键相同的词连续存放，查找时逐个比较完整内容。
+pub(super) struct PrefixHashIndex {
+    buckets: HashMap<u64, Range<u32>>,
+    pieces: Box<[utok]>,
+}
+
+impl PrefixHashIndex {
+    /// 按 `piece` 给出的内容为 `pieces` 中的词建立索引。
+    pub fn new<'a>(piece: impl Fn(utok) -> &'a [u8], pieces: &[utok]) -> Self {
+        let mut keyed = pieces
+            .iter()
+            .map(|&t| (key(piece(t)), t))
+            .collect::<Vec<_>>();
+        keyed.sort_unstable();
+
+        let mut buckets = HashMap::new();
+        let mut start = 0;
+        for (i, w) in keyed.windows(2).enumerate() {
+            if w[0].0 != w[1].0 {
+                buckets.insert(w[0].0, start..i as u32 + 1);
+                start = i as u32 + 1;
+            }
+        }
+        if let Some(&(k, _)) = keyed.last() {
+            buckets.insert(k, start..keyed.len() as u32);
+        }
+        Self {
+            buckets,
+            pieces: keyed.into_iter().map(|(_, t)| t).collect(),
+        }
+    }
+
+    /// 索引占用的字节数。
+    pub fn memory_size(&self) -> usize {
+        hash_map_size(&self.buckets) + size_of_val(&*self.pieces)
+    }
+
+    /// 查找内容为 `piece` 的词。
+    #[inline]
+    pub fn get<'a>(&self, token: impl Fn(utok) -> &'a [u8], piece: &[u8]) -> Option<utok> {
+        let range = self.buckets.get(&key(piece))?;
+        self.pieces[range.start as usize..range.end as usize]
+            .iter()
+            .copied()
+            .find(|&t| token(t) == piece)
+    }
+}
+
+#[inline]
+fn key(piece: &[u8]) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write_usize(piece.len());
+    hasher.write(&piece[..piece.len().min(PREFIX)]);
+    hasher.finish()
+}
+
+/// 查找词之前的预筛选，以词长和首尾字节为键的单哈希布隆过滤器。
+///
+/// 合并过程中大部分查找都会失败，筛选能以一次位运算排除其中的绝大多数，省去完整的查找。
+pub(super) struct PieceFilter {
+    bits: Box<[u64]>,
+    /// 哈希值右移的位数，使结果落在位数组范围内
+    shift: u32,
+    max_len: usize,
+}
+
+impl PieceFilter {
+    /// 为 `pieces` 中的词建立过滤器，每个词占用约 8 位。
+    pub fn new<'a>(piece: impl Fn(utok) -> &'a [u8], pieces: &[utok]) -> Self {
+        let n = (pieces.len() * 8).next_power_of_two().max(64);
+        let mut ans = Self {
+            bits: vec![0; n / 64].into(),
+            shift: 64 - n.trailing_zeros(),
+            max_len: 0,
+        };
+        for &t in pieces {
+            let piece = piece(t);
+            let i = ans.bit(piece);
+            ans.bits[i / 64] |= 1 << (i % 64);
+            ans.max_len = ans.max_len.max(piece.len());
+        }
+        ans
+    }
+
+    /// 过滤器占用的字节数。
+    #[inline]
+    pub fn memory_size(&self) -> usize {
+        size_of_val(&*self.bits)
+    }
+
+    /// 判断 `piece` 是否可能是词，返回 `false` 时一定不是。
+    #[inline]
+    pub fn may_contain(&self, piece: &[u8]) -> bool {
+        if piece.len() > self.max_len {
+            return false;
+        }
+        let i = self.bit(piece);
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    #[inline]
+    fn bit(&self, piece: &[u8]) -> usize {
+        let (first, last) = match piece {
+            [] => (0, 0),
+            [first, .., last] => (*first, *last),
+            [b] => (*b, *b),
+        };
+        let key = (piece.len() as u64) << 16 | (first as u64) << 8 | last as u64;
+        // 乘法哈希取高位
+        let hash = key.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        (hash >> self.shift) as usize
+    }
+}
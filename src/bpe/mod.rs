@@ -1,101 +1,168 @@
 //! b-p-e for Byte Pair Encoding
 
 mod algorithm;
+mod derivation;
+mod export;
+mod index;
+mod prune;
+
+pub use algorithm::{AppliedMerge, MergeState, MergeTrace, PendingMerge};
+pub use derivation::{Derivation, InaccessibleReason, InaccessibleToken};
+pub use prune::{FertilityChange, PruneOptions, TokenFrequency, VocabRemap};
+
+use index::{PieceFilter, PrefixHashIndex, HASH_THRESHOLD};
 
 use crate::{
     utok,
-    vocab::{CollectedVocab, CompressedVocab},
-    Method,
+    vocab::{
+        as_byte_token, as_str, offset, ByteTokenError, CollectedVocab, CompressStats,
+        CompressedVocab, LoadError, Offset, ReverseMap, ReverseTable,
+    },
+    MemoryUsage, Method,
 };
 use std::{
-    collections::{HashMap, HashSet},
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
     iter::zip,
-    ops::Deref,
+    ops::{Bound, RangeBounds},
     pin::Pin,
-    ptr::NonNull,
 };
 
+#[cfg(not(feature = "forbid-unsafe"))]
+use std::ptr::NonNull;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
 pub struct Bpe {
     /// 保存所有词的字符串内容，以 u8 为单位所以不需要对齐，占用空间少
-    _vocabs: Pin<Box<[u8]>>,
+    vocabs: Pin<Box<[u8]>>,
     /// 按 token 顺序保存元信息
     tokens: Box<[TokenMeta]>,
     /// 按字符串的字典序排序的 token 索引，用于从字符串二分查找 token。
     /// 建立索引时直接剔除了不可能从 piece 构造的所有单字节
     sorted_pieces: Box<[utok]>,
+    /// 平均词长较长时代替二分查找的前缀哈希索引
+    piece_index: Option<PrefixHashIndex>,
+    /// 查找前排除不可能是词的 piece
+    piece_filter: PieceFilter,
     /// 用于索引单字节 token，因此不需要其他元信息
     bytes: Box<[utok; 256]>,
-    /// token: <unk>
-    unk: utok,
+    /// 合词规则不可达的 token，构造时计算一次
+    inaccessible: Box<[utok]>,
+    /// 从词的内容反查词序号，首次使用时构造
+    reverse: ReverseMap,
+    /// 单字节词的定义问题
+    byte_error: Option<ByteTokenError>,
+    /// 词表压缩的统计信息
+    stats: CompressStats,
+    /// token: <unk>，没有 <unk> 的词表由单字节词保证任何文本都能编码
+    unk: Option<utok>,
 }
 
 struct TokenMeta {
     /// 指向字符串内容的指针
+    #[cfg(not(feature = "forbid-unsafe"))]
     ptr: NonNull<u8>,
+    /// 字符串内容在词表中的偏移
+    #[cfg(feature = "forbid-unsafe")]
+    off: Offset,
     /// 字符串长度
-    len: u32,
+    len: Offset,
     /// 字符串的合并排名，从 0 开始
     rank: u32,
 }
 
 // SAFETY: TokenMeta 中的指针是指向 Bpe 内容的自引用指针，且仅用于不可变引用。
+#[cfg(not(feature = "forbid-unsafe"))]
 unsafe impl Send for TokenMeta {}
+#[cfg(not(feature = "forbid-unsafe"))]
 unsafe impl Sync for TokenMeta {}
 
-impl Deref for TokenMeta {
-    type Target = [u8];
-    #[inline]
-    fn deref(&self) -> &Self::Target {
-        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len as _) }
+impl TokenMeta {
+    /// 字符串内容，`vocabs` 是保存内容的词表；直接保存指针时不需要读取词表。
+    #[inline(always)]
+    fn piece<'a>(&'a self, vocabs: &'a [u8]) -> &'a [u8] {
+        #[cfg(not(feature = "forbid-unsafe"))]
+        {
+            let _ = vocabs;
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len as _) }
+        }
+        #[cfg(feature = "forbid-unsafe")]
+        {
+            &vocabs[self.off as usize..][..self.len as usize]
+        }
     }
 }
 
 impl Bpe {
     /// 解析 tokenizer.model 文件并构造一个 bpe 分词器。
+    ///
+    /// 文件格式错误时 panic，需要处理错误时使用 [`try_from_tokenizer_model`](Self::try_from_tokenizer_model)。
     pub fn from_tokenizer_model(model: &[u8]) -> Self {
-        // 遍历文件，标记所有词汇的位置
-        let offsets = (0..)
-            .scan(0usize, |offset, _| match &model[*offset..] {
-                [10, total_len, 10, content @ ..] => {
-                    let total_len = *total_len as usize;
-                    *offset += total_len + 2;
-                    Some(&content[..total_len - 2])
-                }
-                [..] => None,
-            })
-            .collect::<Vec<_>>();
-        // 产生词迭代器
-        let vocabs = offsets.iter().map(|slice| {
-            let &&[len, ref content @ ..] = slice else {
-                unreachable!()
-            };
-            std::str::from_utf8(&content[..len as usize]).unwrap()
-        });
-        // 产生评分迭代器
-        let scores = offsets.iter().map(|slice| {
-            let len = slice[0] as usize;
-            let ptr = slice[len + 2..].as_ptr().cast::<f32>();
-            unsafe { ptr.read_unaligned() }
-        });
-        // 构造分词器
+        let pieces = parse_tokenizer_model(model).unwrap_or_else(|e| panic!("{e}"));
+        Self::from_pieces(&pieces)
+    }
+
+    /// 解析 tokenizer.model 文件并构造一个 bpe 分词器，文件格式错误或不含任何词时返回错误。
+    ///
+    /// 任意输入都不会 panic，可以用于加载不可信的文件。
+    pub fn try_from_tokenizer_model(model: &[u8]) -> Result<Self, LoadError> {
+        match parse_tokenizer_model(model)? {
+            pieces if pieces.is_empty() => Err(LoadError::Empty),
+            pieces => Ok(Self::from_pieces(&pieces)),
+        }
+    }
+
+    fn from_pieces(pieces: &[(&str, f32)]) -> Self {
         Self::from_collected_vocab(
-            CollectedVocab::collect(vocabs.into_iter().map(|s| s.as_bytes()), 0),
-            scores,
-            0,
+            CollectedVocab::collect(pieces.iter().map(|(s, _)| s.as_bytes()), Some(0)),
+            pieces.iter().map(|&(_, score)| score),
+            Some(0),
         )
     }
 
+    /// 构造分词器，`unk` 为 `None` 时词表必须定义所有 256 个单字节词，否则 panic。
     pub fn new<'a>(
         vocabs: impl IntoIterator<Item = &'a str>,
         scores: impl IntoIterator<Item = f32>,
         is_byte: impl IntoIterator<Item = bool>,
-        unk: utok,
+        unk: impl Into<Option<utok>>,
+    ) -> Self {
+        Self::new_with_transform(vocabs, scores, is_byte, unk, as_byte_token, Cow::Borrowed)
+    }
+
+    /// 构造分词器，由 `as_byte` 解析标记为单字节词的词，用于单字节词不是 `<0xAB>` 形式的词表。
+    pub fn new_with_byte_token<'a>(
+        vocabs: impl IntoIterator<Item = &'a str>,
+        scores: impl IntoIterator<Item = f32>,
+        is_byte: impl IntoIterator<Item = bool>,
+        unk: impl Into<Option<utok>>,
+        as_byte: impl Fn(&[u8]) -> Option<u8>,
+    ) -> Self {
+        Self::new_with_transform(vocabs, scores, is_byte, unk, as_byte, Cow::Borrowed)
+    }
+
+    /// 构造分词器，由 `as_byte` 解析标记为单字节词的词，其他词由 `transform` 转换为解码后的形式再保存，
+    /// 例如 [`replace_metaspace`](crate::replace_metaspace)。
+    ///
+    /// 转换后的词直接用于合并和解码，因此解码不需要再做后处理。
+    pub fn new_with_transform<'a>(
+        vocabs: impl IntoIterator<Item = &'a str>,
+        scores: impl IntoIterator<Item = f32>,
+        is_byte: impl IntoIterator<Item = bool>,
+        unk: impl Into<Option<utok>>,
+        as_byte: impl Fn(&[u8]) -> Option<u8>,
+        transform: impl Fn(&'a [u8]) -> Cow<'a, [u8]>,
     ) -> Self {
+        let unk = unk.into();
         Self::from_collected_vocab(
             CollectedVocab::collect_with_hint(
                 vocabs.into_iter().map(|s| s.as_bytes()),
                 is_byte,
                 unk,
+                as_byte,
+                transform,
             ),
             scores,
             unk,
@@ -105,14 +172,19 @@ impl Bpe {
     fn from_collected_vocab(
         vocab: CollectedVocab,
         scores: impl IntoIterator<Item = f32>,
-        unk: utok,
+        unk: Option<utok>,
     ) -> Self {
         let CollectedVocab {
             vocabs,
             total_len,
             bytes,
+            byte_error,
         } = vocab;
-        let CompressedVocab { vocabs, slices } = CompressedVocab::new(&vocabs, total_len);
+        let CompressedVocab {
+            vocabs,
+            slices,
+            stats,
+        } = CompressedVocab::new(&vocabs, total_len);
         // 收集合词评分
         let scores = scores.into_iter().collect::<Vec<_>>();
         assert_eq!(
@@ -123,18 +195,44 @@ impl Bpe {
         // tokens 中直接引用字符串位置，绑定重新赋权并转换为整型的分词评分
         let tokens = zip(slices, rank(&scores))
             .map(|((off, len), rank)| TokenMeta {
+                #[cfg(not(feature = "forbid-unsafe"))]
                 ptr: unsafe { NonNull::new_unchecked(vocabs[off..].as_ptr().cast_mut()) },
-                len: len as _,
+                #[cfg(feature = "forbid-unsafe")]
+                off: offset(off),
+                len: offset(len),
                 rank,
             })
             .collect::<Box<_>>();
         // 对 token 按字符串的字典序排序，用于从字符串二分查找 token
         // <unk> 和 <0xyz> 不应该通过 piece 搜索到，使用 set 排除
-        let bytes_set = bytes.iter().chain(&[unk]).cloned().collect::<HashSet<_>>();
+        // 被重复定义覆盖的单字节词的内容可能不是合法的 utf-8，同样排除
+        let shadowed = byte_error
+            .iter()
+            .flat_map(|e| e.duplicates.iter().map(|(_, t)| t));
+        let bytes_set = bytes
+            .iter()
+            .chain(&unk)
+            .chain(shadowed)
+            .cloned()
+            .collect::<HashSet<_>>();
         let mut sorted_pieces = (0..tokens.len() as utok)
             .filter(|i| !bytes_set.contains(i))
             .collect::<Box<_>>();
-        sorted_pieces.sort_unstable_by_key(|&i| &*tokens[i as usize]);
+        let piece = |t: utok| tokens[t as usize].piece(&vocabs);
+        #[cfg(feature = "rayon")]
+        sorted_pieces.par_sort_unstable_by_key(|&i| piece(i));
+        #[cfg(not(feature = "rayon"))]
+        sorted_pieces.sort_unstable_by_key(|&i| piece(i));
+
+        // 词较长时二分查找的比较代价高，改用前缀哈希索引
+        let piece_index = (!sorted_pieces.is_empty()
+            && sorted_pieces
+                .iter()
+                .map(|&i| tokens[i as usize].len as usize)
+                .sum::<usize>()
+                > HASH_THRESHOLD * sorted_pieces.len())
+        .then(|| PrefixHashIndex::new(piece, &sorted_pieces));
+        let piece_filter = PieceFilter::new(piece, &sorted_pieces);
 
         // println!(
         //     "Building BPE vocab, detected {} tokens, compressed to {} bytes from {total_len} bytes",
@@ -142,55 +240,239 @@ impl Bpe {
         //     vocabs.len(),
         // );
 
-        Self {
-            _vocabs: vocabs,
+        let mut ans = Self {
+            vocabs,
             tokens,
             sorted_pieces,
+            piece_index,
+            piece_filter,
             bytes,
+            inaccessible: Box::new([]),
+            reverse: ReverseMap::default(),
+            byte_error,
+            stats,
             unk,
-        }
+        };
+        ans.inaccessible = ans.detect_inaccessible();
+        ans
     }
 
     /// BPE 词表中，并非所有词都是合词规则可达的。此算法可识别“内部不可达”的 token。
-    pub fn inaccessible(&self) -> HashMap<&str, utok> {
-        self.sorted_pieces
+    ///
+    /// 结果在构造时计算并缓存，调用此方法不会重新编码词表。按词的内容排序，遍历顺序是确定的。
+    /// 不可达的原因见 [`inaccessible_report`](Self::inaccessible_report)。
+    pub fn inaccessible(&self) -> BTreeMap<&str, utok> {
+        self.inaccessible
             .iter()
-            .filter_map(|&t| {
-                let s = unsafe { std::str::from_utf8_unchecked(self.token(t)) };
-                if self.encode(s).into_iter().nth(1).is_some() {
-                    Some((s, t))
-                } else {
-                    None
-                }
-            })
+            .map(|&t| (as_str(self.token(t)), t))
             .collect()
     }
 
-    /// piece -> token
-    #[inline]
-    fn find_piece(&self, piece: &[u8]) -> Option<utok> {
-        match self
+    /// 检查每个可以由文本匹配的一般词能否由合并得到，报告永远不会被编码产生的词。
+    ///
+    /// 除 [`inaccessible`](Self::inaccessible) 中编码为多个词的词外，还报告与其他词内容相同、
+    /// 查找时被其他词遮蔽的词。复用构造时缓存的结果，只需重新编码不可达的词；
+    /// <unk>、单字节词和弃用的词不在检查范围内。
+    pub fn validate_reachability(&self) -> Result<(), Vec<UnreachableToken>> {
+        let mut ans = self
+            .inaccessible
+            .iter()
+            .map(|&token| UnreachableToken {
+                token,
+                encoded: self.encode(as_str(self.token(token))).into_iter().collect(),
+            })
+            .collect::<Vec<_>>();
+        // 内容相同的词在排序后相邻，只有查找到的词可达
+        for group in self
             .sorted_pieces
-            .binary_search_by_key(&piece, |&i| self.token(i))
+            .chunk_by(|&a, &b| self.token(a) == self.token(b))
+            .filter(|group| group.len() > 1)
         {
-            Ok(i) => Some(self.sorted_pieces[i]),
-            Err(_) => match *piece {
-                [b] => Some(self.bytes[b as usize]),
-                [..] => None,
-            },
+            let found = self.find_piece(self.token(group[0]));
+            ans.extend(
+                group
+                    .iter()
+                    .filter(|&&t| Some(t) != found)
+                    .map(|&token| UnreachableToken {
+                        token,
+                        encoded: found.into_iter().collect(),
+                    }),
+            )
         }
+        if ans.is_empty() {
+            Ok(())
+        } else {
+            ans.sort_unstable_by_key(|u| u.token);
+            Err(ans)
+        }
+    }
+
+    /// 逐个编码词表中的词，找出无法编码为自身的 token。
+    ///
+    /// 启用 `rayon` 特性时并行编码。
+    fn detect_inaccessible(&self) -> Box<[utok]> {
+        let inaccessible = |&t: &utok| {
+            self.encode(as_str(self.token(t)))
+                .into_iter()
+                .nth(1)
+                .is_some()
+        };
+        #[cfg(feature = "rayon")]
+        {
+            self.sorted_pieces
+                .par_iter()
+                .copied()
+                .filter(inaccessible)
+                .collect::<Vec<_>>()
+                .into()
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            self.sorted_pieces
+                .iter()
+                .copied()
+                .filter(inaccessible)
+                .collect()
+        }
+    }
+
+    /// 检查构造时词表中单字节词的定义，报告重复定义的字节和部分定义时缺失的字节。
+    pub fn check_byte_tokens(&self) -> Result<(), ByteTokenError> {
+        self.byte_error.clone().map_or(Ok(()), Err)
     }
 
-    /// token id -> token meta
+    /// 弃用词表中的词：编码不再产生这些词，但仍可以解码，词序号保持不变。
+    ///
+    /// 用于保留模型的嵌入行而停止产生某些词，例如词表中意外包含的敏感字符串。
+    /// 弃用的单字节词回退为 <unk>；没有 <unk> 时单字节词仍作为回退保留。弃用后重新检测不可达的词。
+    pub fn deprecate(&mut self, tokens: impl IntoIterator<Item = utok>) {
+        let tokens = tokens.into_iter().collect::<HashSet<_>>();
+        self.sorted_pieces = self
+            .sorted_pieces
+            .iter()
+            .copied()
+            .filter(|t| !tokens.contains(t))
+            .collect();
+        if self.piece_index.is_some() {
+            self.piece_index = Some(PrefixHashIndex::new(|t| self.token(t), &self.sorted_pieces));
+        }
+        self.piece_filter = PieceFilter::new(|t| self.token(t), &self.sorted_pieces);
+        if let Some(unk) = self.unk {
+            for b in self.bytes.iter_mut() {
+                if tokens.contains(b) {
+                    *b = unk
+                }
+            }
+        }
+        self.reverse = ReverseMap::default();
+        self.inaccessible = self.detect_inaccessible();
+    }
+
+    /// 构造时词表压缩的统计信息。
+    #[inline]
+    pub fn compress_stats(&self) -> CompressStats {
+        self.stats
+    }
+
+    /// 查找内容为 `piece` 的词。
+    ///
+    /// 多个词内容相同时，优先返回编码时使用的词，其次返回序号最小的词。
+    /// 首次调用时构造反查表，此后每次查找的复杂度为 O(1)。
+    #[inline]
+    pub fn token_to_id(&self, piece: &str) -> Option<utok> {
+        self.reverse_map().get(piece.as_bytes()).copied()
+    }
+
+    /// 预先构造 [`token_to_id`](Self::token_to_id) 使用的反查表，避免首次查找时的延迟。
+    #[inline]
+    pub fn warm_token_to_id(&self) {
+        self.reverse_map();
+    }
+
+    fn reverse_map(&self) -> &ReverseTable<'_> {
+        let init = || {
+            (0..self.tokens.len() as utok)
+                .rev()
+                .chain(self.sorted_pieces.iter().copied())
+                .map(|t| (self.token(t), t))
+        };
+        // SAFETY: 词的内容保存在固定位置的 vocabs 中，与 self 的生命周期相同。
+        #[cfg(not(feature = "forbid-unsafe"))]
+        unsafe {
+            self.reverse.get_or_init(init)
+        }
+        #[cfg(feature = "forbid-unsafe")]
+        {
+            self.reverse.get_or_init(init)
+        }
+    }
+
+    /// 按内容的字典序遍历可以由文本编码得到的词及其词序号。
+    ///
+    /// 不包括 <unk>、单字节词和弃用的词；多个词内容相同时都会出现，相邻排列。
+    /// 顺序只由词表决定，可用于导出可复现的词表或在外部二分查找。
+    pub fn sorted_pieces(
+        &self,
+    ) -> impl ExactSizeIterator<Item = (&[u8], utok)> + DoubleEndedIterator + '_ {
+        self.sorted_pieces.iter().map(|&t| (self.token(t), t))
+    }
+
+    /// 按字典序遍历内容落在 `range` 中的词，例如 `&b"app"[..]..&b"apq"[..]` 得到所有以 `app` 开头的词。
+    pub fn pieces_in_range<'q>(
+        &self,
+        range: impl RangeBounds<&'q [u8]>,
+    ) -> impl ExactSizeIterator<Item = (&[u8], utok)> + DoubleEndedIterator + '_ {
+        let start = self
+            .sorted_pieces
+            .partition_point(|&t| match range.start_bound() {
+                Bound::Included(start) => self.token(t) < *start,
+                Bound::Excluded(start) => self.token(t) <= *start,
+                Bound::Unbounded => false,
+            });
+        let end = self
+            .sorted_pieces
+            .partition_point(|&t| match range.end_bound() {
+                Bound::Included(end) => self.token(t) <= *end,
+                Bound::Excluded(end) => self.token(t) < *end,
+                Bound::Unbounded => true,
+            });
+        self.sorted_pieces[start..end.max(start)]
+            .iter()
+            .map(|&t| (self.token(t), t))
+    }
+
+    /// piece -> token
+    #[inline]
+    fn find_piece(&self, piece: &[u8]) -> Option<utok> {
+        let found = if !self.piece_filter.may_contain(piece) {
+            None
+        } else {
+            match &self.piece_index {
+                Some(index) => index.get(|t| self.token(t), piece),
+                None => self
+                    .sorted_pieces
+                    .binary_search_by_key(&piece, |&i| self.token(i))
+                    .ok()
+                    .map(|i| self.sorted_pieces[i]),
+            }
+        };
+        // 没有对应单字节词的字节回退为 <unk>，其长度与字节不同，不能参与合并
+        found.or_else(|| match *piece {
+            [b] if Some(self.bytes[b as usize]) != self.unk => Some(self.bytes[b as usize]),
+            [..] => None,
+        })
+    }
+
+    /// token id -> token content
     #[inline(always)]
-    fn token(&self, token: utok) -> &TokenMeta {
-        &self.tokens[token as usize]
+    fn token(&self, token: utok) -> &[u8] {
+        self.tokens[token as usize].piece(&self.vocabs)
     }
 }
 
 impl Method for Bpe {
     #[inline]
-    fn unk_token(&self) -> utok {
+    fn unk_token(&self) -> Option<utok> {
         self.unk
     }
     #[inline]
@@ -202,60 +484,195 @@ impl Method for Bpe {
         self.inaccessible()
     }
     #[inline]
-    fn encode(&self, text: &str) -> impl IntoIterator<Item = utok> + '_ {
+    fn encode<'a>(&'a self, text: &'a str) -> impl IntoIterator<Item = utok> + 'a {
         let mut tokenizer = self.begin_merge(text);
         while tokenizer.merge() {}
         tokenizer.into_iter()
     }
+    fn encode_with<'a>(
+        &'a self,
+        text: &'a str,
+        options: crate::EncodeOptions,
+    ) -> impl IntoIterator<Item = utok> + 'a {
+        let cancel = options.cancel();
+        let cancelled = || cancel.is_some_and(|cancel| cancel());
+        let mut tokenizer = self.begin_merge_with(text, options.workspace());
+        #[cfg(feature = "rand")]
+        let mut options = options;
+        #[cfg(feature = "rand")]
+        if let Some((p, rng)) = options.dropout() {
+            while !cancelled() && tokenizer.merge_with_dropout(p, rng) {}
+            return tokenizer.into_iter();
+        }
+        while !cancelled() && tokenizer.merge() {}
+        tokenizer.into_iter()
+    }
     #[inline]
     fn decode(&self, token: utok) -> &[u8] {
         self.token(token)
     }
+    #[inline]
+    fn fallback_byte(&self, token: utok) -> Option<u8> {
+        match *self.token(token) {
+            [b] if Some(token) != self.unk && self.bytes[b as usize] == token => Some(b),
+            _ => None,
+        }
+    }
+    #[inline]
+    fn rank(&self, token: utok) -> Option<u32> {
+        Some(self.tokens[token as usize].rank)
+    }
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            vocab: self.vocabs.len(),
+            metadata: size_of_val(&*self.tokens)
+                + size_of_val(&*self.bytes)
+                + size_of_val(&*self.inaccessible),
+            index: size_of_val(&*self.sorted_pieces)
+                + self
+                    .piece_index
+                    .as_ref()
+                    .map_or(0, PrefixHashIndex::memory_size)
+                + self.piece_filter.memory_size(),
+            special: 0,
+            cache: self.reverse.memory_size(),
+        }
+    }
 }
 
-/// 对一组评分排序、去重并重新赋权，转换为保持相同顺序的整型序列
-fn rank(scores: &[f32]) -> impl IntoIterator<Item = u32> + '_ {
-    use std::{
-        cmp::Ordering,
-        collections::{BTreeMap, BTreeSet},
-    };
-
-    #[derive(Debug)]
-    struct FloatOrd(f32);
-    impl Eq for FloatOrd {}
-    impl PartialEq for FloatOrd {
-        #[inline]
-        fn eq(&self, other: &Self) -> bool {
-            self.0.total_cmp(&other.0).is_eq()
+/// 解析 tokenizer.model 文件，按词序返回每个词的内容和评分。
+///
+/// 解析在文件末尾或词表之后的 `trainer_spec`、`normalizer_spec` 等字段处停止，不会越界读取。
+/// 其他位置出现无法解析的内容时返回错误，不会静默截断词表。
+/// 字段标记和长度按 protobuf 的变长整数解码，因此词和记录可以超过 127 字节。
+pub(crate) fn parse_tokenizer_model(model: &[u8]) -> Result<Vec<(&str, f32)>, LoadError> {
+    let mut ans = Vec::new();
+    let mut offset = 0;
+    while offset < model.len() {
+        let mut pos = offset;
+        let tag = read_varint(model, &mut pos).ok_or(LoadError::Truncated(offset))?;
+        let field = read_bytes(model, &mut pos);
+        match (tag >> 3, tag & 7) {
+            // 每条记录是 pieces 字段（1 号，长度前缀），其中含有词（1 号）和评分（2 号，f32）
+            (1, 2) => {}
+            // 词表之后的 trainer_spec、normalizer_spec、self_test_data 和 denormalizer_spec
+            (2..=5, 2) => {
+                field.ok_or(LoadError::Truncated(offset))?;
+                break;
+            }
+            _ => return Err(LoadError::MalformedRecord(offset)),
         }
+        let record = field.ok_or(LoadError::Truncated(offset))?;
+        let (piece, score) = parse_piece(record).ok_or(LoadError::MalformedRecord(offset))?;
+        // 词的内容
+        let piece = std::str::from_utf8(piece).map_err(|_| LoadError::InvalidUtf8(offset))?;
+        // 词的评分
+        ans.push((piece, score));
+        offset = pos;
     }
-    impl PartialOrd for FloatOrd {
-        #[inline]
-        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-            Some(self.cmp(other))
+    Ok(ans)
+}
+
+/// 解析一条词的记录，返回词的内容和评分，跳过其他字段。
+fn parse_piece(record: &[u8]) -> Option<(&[u8], f32)> {
+    let mut piece = None;
+    let mut score = None;
+    let mut pos = 0;
+    while pos < record.len() {
+        let tag = read_varint(record, &mut pos)?;
+        match (tag >> 3, tag & 7) {
+            (1, 2) => piece = Some(read_bytes(record, &mut pos)?),
+            (2, 5) => score = Some(f32::from_le_bytes(read_fixed(record, &mut pos)?)),
+            (_, 0) => _ = read_varint(record, &mut pos)?,
+            (_, 1) => _ = read_fixed::<8>(record, &mut pos)?,
+            (_, 2) => _ = read_bytes(record, &mut pos)?,
+            (_, 5) => _ = read_fixed::<4>(record, &mut pos)?,
+            _ => return None,
         }
     }
-    impl Ord for FloatOrd {
-        #[inline]
-        fn cmp(&self, other: &Self) -> Ordering {
-            self.0.total_cmp(&other.0)
+    Some((piece?, score?))
+}
+
+/// 读取 protobuf 的变长整数。
+fn read_varint(buf: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let &b = buf.get(*pos)?;
+        *pos += 1;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Some(value);
         }
     }
+    None
+}
 
-    let map = scores
-        // 排序 + 去重
-        .iter()
-        .copied()
-        .map(FloatOrd)
-        .collect::<BTreeSet<_>>()
-        // 重新赋权
-        .into_iter()
-        .rev()
-        .enumerate()
-        .map(|(i, f)| (f, i as u32))
-        .collect::<BTreeMap<_, _>>();
-
-    scores.iter().map(move |&f| map[&FloatOrd(f)])
+/// 读取 protobuf 的长度前缀字段的内容。
+fn read_bytes<'a>(buf: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len = usize::try_from(read_varint(buf, pos)?).ok()?;
+    let ans = buf.get(*pos..)?.get(..len)?;
+    *pos += len;
+    Some(ans)
+}
+
+/// 读取 protobuf 的定长字段。
+fn read_fixed<const N: usize>(buf: &[u8], pos: &mut usize) -> Option<[u8; N]> {
+    let ans = buf.get(*pos..)?.get(..N)?.try_into().ok()?;
+    *pos += N;
+    Some(ans)
+}
+
+/// 永远不会被编码产生的词，见 [`Bpe::validate_reachability`]。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnreachableToken {
+    /// 不可达的词
+    pub token: utok,
+    /// 编码这个词的内容实际得到的词序列
+    pub encoded: Vec<utok>,
+}
+
+impl crate::Tokeneer<Bpe> {
+    /// 将编码为多个词的不可达词注册为特殊词，使其内容总是编码为这个词，返回新注册的个数。
+    ///
+    /// [`Tokeneer::new`](crate::Tokeneer::new) 只注册其中内容为 ascii 的词；
+    /// 被其他词遮蔽的词不注册，否则相同内容的文本将不再编码为原来的词。
+    pub fn register_unreachable(&mut self) -> usize {
+        let Err(unreachable) = self.internal().validate_reachability() else {
+            return 0;
+        };
+        let patterns = unreachable
+            .into_iter()
+            .filter(|u| u.encoded.len() > 1)
+            .map(|u| (as_str(self.internal().token(u.token)), u.token))
+            .filter(|(piece, _)| self.special_tokens().all(|(k, _)| k != *piece))
+            .map(|(piece, token)| (piece.to_string(), vec![token]))
+            .collect::<Vec<_>>();
+        let n = patterns.len();
+        self.extend_special(patterns);
+        n
+    }
+}
+
+/// 对一组评分排序、去重并重新赋权，转换为保持相同顺序的整型序列
+fn rank(scores: &[f32]) -> Vec<u32> {
+    // 从高到低排序 + 去重
+    let mut sorted = scores.to_vec();
+    #[cfg(feature = "rayon")]
+    sorted.par_sort_unstable_by(|a, b| b.total_cmp(a));
+    #[cfg(not(feature = "rayon"))]
+    sorted.sort_unstable_by(|a, b| b.total_cmp(a));
+    sorted.dedup_by(|a, b| a.total_cmp(b).is_eq());
+
+    // 重新赋权
+    let rank = |f: &f32| sorted.binary_search_by(|x| f.total_cmp(x)).unwrap() as u32;
+    #[cfg(feature = "rayon")]
+    {
+        scores.par_iter().map(rank).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        scores.iter().map(rank).collect()
+    }
 }
 
 #[cfg(test)]
@@ -270,7 +687,7 @@ mod bpe_tests {
             println!(
                 "bpe: detected {} tokens, compressed to {} bytes",
                 bpe.vocab_size(),
-                bpe._vocabs.len(),
+                bpe.vocabs.len(),
             );
             println!("inaccessible: {inaccessible:#?}");
         }
@@ -295,6 +712,11 @@ mod bpe_tests {
         )
     }
 
+    #[test]
+    fn test_rank() {
+        assert_eq!(rank(&[0., 1., -1., 1., 2.5, -0.]), [2, 1, 4, 1, 0, 3]);
+    }
+
     #[test]
     fn test_bpe_new() {
         let bpe = test_bpe();
@@ -304,7 +726,27 @@ mod bpe_tests {
     #[test]
     fn test_bpe_unk_token() {
         let bpe = test_bpe();
-        assert_eq!(bpe.unk_token(), 0);
+        assert_eq!(bpe.unk_token(), Some(0));
+    }
+
+    #[test]
+    fn test_bpe_without_unk() {
+        let bytes = (0..=255u8)
+            .map(|b| format!("<0x{b:02X}>"))
+            .collect::<Vec<_>>();
+        let pieces = bytes.iter().map(String::as_str).chain(["a", "b", "ab"]);
+        let scores = (0..256).map(|_| 0.).chain([1., 1., 2.]);
+        let is_byte = (0..259).map(|i| i < 256);
+        let mut bpe = Bpe::new(pieces, scores, is_byte, None);
+        assert_eq!(bpe.unk_token(), None);
+        // 不在词表中的字符回退为单字节词，任何文本都能编码
+        let tokens = bpe.encode("abé\u{0}").into_iter().collect::<Vec<_>>();
+        assert_eq!(tokens, [258, 0xC3, 0xA9, 0]);
+        assert_eq!(bpe.fallback_byte(0), Some(0));
+        // 没有 <unk> 时弃用单字节词不影响回退
+        bpe.deprecate([0xC3, 258]);
+        let tokens = bpe.encode("abé").into_iter().collect::<Vec<_>>();
+        assert_eq!(tokens, [256, 257, 0xC3, 0xA9]);
     }
 
     #[test]
@@ -362,6 +804,546 @@ mod bpe_tests {
             !inaccessible.contains_key("ab"),
             "Token 'ab' should be accessible"
         );
+        // 遍历顺序与构造顺序无关
+        let keys = inaccessible.keys().copied().collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+        assert_eq!(keys, sorted);
+    }
+
+    #[test]
+    fn test_bpe_inaccessible_report() {
+        let bpe = Bpe::new(
+            [
+                "<unk>", "a", "b", "c", "d", "e", "ab", "cd", "bc", "abcd", "abcde", "ace",
+            ],
+            [0., 1., 1., 1., 1., 1., 2., 2., 3., 1., 1., 1.],
+            [false; 12],
+            0,
+        );
+        let report = bpe.inaccessible_report();
+        assert_eq!(
+            report.iter().map(|t| t.token).collect::<Vec<_>>(),
+            [9, 10, 11]
+        );
+        // bc 先于 ab 和 cd 合并，跨过了 ab|cd 的分界
+        assert_eq!(report[0].encoded, [1, 8, 4]);
+        assert_eq!(
+            report[0].reason,
+            InaccessibleReason::Blocked {
+                pair: (6, 7),
+                blocker: AppliedMerge {
+                    pos: 1,
+                    pair: (2, 3),
+                    merge: 8,
+                    rank: 0,
+                },
+            }
+        );
+        // abcd 本身不可达
+        assert_eq!(
+            report[1].reason,
+            InaccessibleReason::MissingPart {
+                pair: (9, 5),
+                missing: 9,
+            }
+        );
+        assert_eq!(report[2].reason, InaccessibleReason::NoPair);
+    }
+
+    #[test]
+    fn test_bpe_pending_merges() {
+        let bpe = test_bpe();
+        let mut state = bpe.begin_merge("abd");
+        let merges = state.pending_merges().collect::<Vec<_>>();
+        // "bd" 的排名高于 "ab"
+        assert_eq!(
+            merges
+                .iter()
+                .map(|m| (m.merge, m.valid))
+                .collect::<Vec<_>>(),
+            [(8, true), (5, true)]
+        );
+
+        assert!(state.merge());
+        let merges = state.pending_merges().collect::<Vec<_>>();
+        assert_eq!(
+            merges
+                .iter()
+                .map(|m| (m.merge, m.valid))
+                .collect::<Vec<_>>(),
+            [(5, false)]
+        );
+    }
+
+    #[test]
+    fn test_bpe_trace() {
+        let bpe = test_bpe();
+        let trace = bpe.trace("abd");
+        assert_eq!(trace.initial, [1, 2, 4]);
+        assert_eq!(
+            trace.merges,
+            [AppliedMerge {
+                pos: 1,
+                pair: (2, 4),
+                merge: 8,
+                rank: 1,
+            }]
+        );
+        assert_eq!(trace.tokens, [1, 8]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_bpe_trace_json() {
+        let trace = test_bpe().trace("abd");
+        let json = serde_json::to_string(&trace).unwrap();
+        assert_eq!(
+            json,
+            r#"{"text":"abd","initial":[1,2,4],"merges":[{"pos":1,"pair":[2,4],"merge":8,"rank":1}],"tokens":[1,8]}"#
+        );
+        assert_eq!(serde_json::from_str::<MergeTrace>(&json).unwrap(), trace);
+    }
+
+    #[test]
+    fn test_merge_state_fmt() {
+        let bpe = Bpe::new(
+            ["<unk>", "a", "<0xC3>", "<0xBF>"],
+            [0., 1., 1., 1.],
+            [false, false, true, true],
+            0,
+        );
+        let mut state = bpe.begin_merge("a");
+        while state.merge() {}
+        assert_eq!(
+            format!("{state:?}"),
+            r#"MergeState { text: "a", tokens: [1:"a"], merges: 0 }"#
+        );
+        let state = bpe.begin_merge("ÿ");
+        assert_eq!(
+            format!("{state:?}"),
+            r#"MergeState { text: "ÿ", tokens: [2:"\xC3" 3:"\xBF"], merges: 0 }"#
+        );
+    }
+
+    #[test]
+    fn test_bpe_valid_continuations() {
+        let bpe = Bpe::new(
+            ["<unk>", "a", "<0xE4>", "<0xBD>", "<0xA0>", "你"],
+            [0., 1., 1., 1., 1., 1.],
+            [false, false, true, true, true, false],
+            0,
+        );
+        // 未完成的字符之后只能接续延续字节
+        assert_eq!(bpe.valid_continuations(&[0xE4]), [3, 4]);
+        assert_eq!(bpe.valid_continuations(&[0xE4, 0xBD]), [3, 4]);
+        assert_eq!(bpe.valid_continuations(&[]), [0, 1, 2, 5]);
+    }
+
+    #[test]
+    fn test_bpe_piece_index() {
+        let pieces = [
+            "<unk>",
+            "f",
+            "n",
+            " ",
+            "fn",
+            "fn ",
+            "fn main() {\n    let mut sum = 0usize;",
+            "fn main() {\n    let mut sum = 1usize;",
+            "fn main() {\n    let mut sum = 0usize;\n",
+            "    println!(\"{sum}\");\n}\n",
+        ];
+        let mut bpe = Bpe::new(pieces, (0..pieces.len()).map(|i| i as f32), [false; 10], 0);
+        assert!(bpe.piece_index.is_some());
+        let text = "fn main() {\n    let mut sum = 1usize;fn fn";
+        let hashed = bpe.encode(text).into_iter().collect::<Vec<_>>();
+        for (i, piece) in pieces.iter().enumerate().skip(1) {
+            assert_eq!(bpe.find_piece(piece.as_bytes()), Some(i as utok));
+        }
+        assert_eq!(
+            bpe.find_piece(b"fn main() {\n    let mut sum = 2usize;"),
+            None
+        );
+
+        bpe.piece_index = None;
+        assert_eq!(bpe.encode(text).into_iter().collect::<Vec<_>>(), hashed);
+    }
+
+    #[test]
+    fn test_bpe_piece_filter() {
+        let bpe = test_bpe();
+        // 所有词都能通过筛选，超过最长词的 piece 直接排除
+        for &t in &*bpe.sorted_pieces {
+            assert!(bpe.piece_filter.may_contain(bpe.token(t)));
+        }
+        assert!(!bpe.piece_filter.may_contain(b"abcdefgh"));
+        assert_eq!(bpe.find_piece(b"abcdefgh"), None);
+    }
+
+    #[test]
+    fn test_bpe_cancel() {
+        use crate::EncodeOptions;
+
+        let bpe = test_bpe();
+        // 取消后不再合并，结果仍然可以解码为原文
+        let options = EncodeOptions::default().with_cancel(&|| true);
+        let tokens = bpe
+            .encode_with("abd", options)
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, [1, 2, 4]);
+        let options = EncodeOptions::default().with_cancel(&|| false);
+        let tokens = bpe
+            .encode_with("abd", options)
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, [1, 8]);
+    }
+
+    #[test]
+    fn test_bpe_unk_no_merge() {
+        // 没有单字节词时，不在词表中的字节编码为 <unk>，不与相邻的词合并
+        let bpe = Bpe::new(["<unk>", "a", "xa"], [0., 1., 2.], [false; 3], 0);
+        assert_eq!(bpe.encode("xa").into_iter().collect::<Vec<_>>(), [0, 1]);
+    }
+
+    #[test]
+    fn test_bpe_sorted_pieces() {
+        let bpe = test_bpe();
+        let pieces = bpe.sorted_pieces().collect::<Vec<_>>();
+        assert_eq!(pieces.len(), 9);
+        assert!(pieces.is_sorted());
+        assert_eq!(pieces[0], (&b"a"[..], 1));
+        let ids = |pieces: &mut dyn Iterator<Item = (&[u8], utok)>| {
+            pieces.map(|(_, t)| t).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(&mut bpe.pieces_in_range(&b"b"[..]..&b"c"[..])),
+            [2, 9, 8]
+        );
+        assert_eq!(
+            ids(&mut bpe.pieces_in_range(&b"ab"[..]..=&b"ad"[..])),
+            [5, 6, 7]
+        );
+        assert_eq!(ids(&mut bpe.pieces_in_range(&b"c"[..]..)), [3, 4]);
+        assert!(ids(&mut bpe.pieces_in_range(&b"d"[..]..&b"a"[..])).is_empty());
+        assert!(bpe.pieces_in_range(..).eq(bpe.sorted_pieces()));
+    }
+
+    #[test]
+    fn test_bpe_marks() {
+        // 每个词按覆盖的字节数定位，与 <unk> 的内容和词序号无关
+        let bpe = test_bpe();
+        let mut state = bpe.begin_merge("abé\u{0}bd");
+        while state.merge() {}
+        let tokens = state.iter().collect::<Vec<_>>();
+        assert_eq!(tokens, [5, 0, 0, 0, 8]);
+        assert_eq!(state.into_iter().collect::<Vec<_>>(), tokens);
+        // <unk> 是第一个词也不影响合并
+        let bpe = Bpe::new(["a", "b", "ab"], [1., 1., 2.], [false; 3], 0);
+        assert_eq!(bpe.encode("bab").into_iter().collect::<Vec<_>>(), [1, 0, 1]);
+    }
+
+    #[test]
+    fn test_bpe_overlapping_pieces() {
+        // 空词、重复的词和相互重叠的词
+        let bpe = Bpe::new(
+            [
+                "<unk>", "", "a", "b", "c", "ab", "bc", "abc", "", "bc", "cab",
+            ],
+            [0., 9., 1., 1., 1., 3., 4., 2., 9., 4., 5.],
+            [false; 11],
+            0,
+        );
+        for text in [
+            "", "a", "ab", "abc", "abca", "bcab", "cabcab", "abx", "xa", "ccc",
+        ] {
+            let mut state = bpe.begin_merge(text);
+            while state.merge() {}
+            let tokens = state.iter().collect::<Vec<_>>();
+            assert_eq!(state.into_iter().collect::<Vec<_>>(), tokens);
+            assert!(!tokens.iter().any(|&t| bpe.decode(t).is_empty()));
+            if !tokens.contains(&0) {
+                let decoded = tokens.iter().flat_map(|&t| bpe.decode(t)).copied();
+                assert_eq!(decoded.collect::<Vec<_>>(), text.as_bytes());
+            }
+        }
+        // 文本以不完整的匹配结尾
+        let tokens = bpe.encode("cabca").into_iter().collect::<Vec<_>>();
+        assert_eq!(tokens.last(), Some(&2));
+    }
+
+    #[test]
+    fn test_bpe_prune() {
+        let mut model = Vec::new();
+        for (piece, score) in [
+            ("<unk>", 0.),
+            ("<0x61>", 0.),
+            ("a", -1.),
+            ("b", -2.),
+            ("ab", -3.),
+            ("ba", -4.),
+        ] {
+            model.extend([10, piece.len() as u8 + 7, 10, piece.len() as u8]);
+            model.extend_from_slice(piece.as_bytes());
+            model.push(0x15);
+            model.extend_from_slice(&f32::to_le_bytes(score));
+        }
+        let options = PruneOptions {
+            min_score: Some(-3.),
+            max_rank: None,
+        };
+        let (bpe, remap) = Bpe::try_from_tokenizer_model_pruned(&model, options).unwrap();
+        assert_eq!(bpe.vocab_size(), 5);
+        assert_eq!(remap.dropped().collect::<Vec<_>>(), [5]);
+        assert_eq!((remap.old_size(), remap.new_size()), (6, 5));
+        assert_eq!(bpe.encode("aba").into_iter().collect::<Vec<_>>(), [4, 2]);
+        // 排名按全部评分计算，<unk> 和单字节词总是保留
+        let options = PruneOptions {
+            min_score: None,
+            max_rank: Some(2),
+        };
+        let (bpe, remap) = Bpe::try_from_tokenizer_model_pruned(&model, options).unwrap();
+        assert_eq!(bpe.vocab_size(), 3);
+        assert_eq!(remap.new_id(2), Some(2));
+        assert_eq!(remap.new_id(3), None);
+        assert_eq!(remap.old_id(1), Some(1));
+        assert_eq!(bpe.encode("ab").into_iter().collect::<Vec<_>>(), [2, 0]);
+    }
+
+    #[test]
+    fn test_bpe_prune_by_frequency() {
+        let bpe = Bpe::new(
+            [
+                "<unk>", "<0x61>", "a", "b", "c", "ab", "abc", "<s>", "ca", "bb", " ",
+            ],
+            [0., 0., -1., -1., -1., -2., -3., 0., -4., -5., -1.],
+            [
+                false, true, false, false, false, false, false, false, false, false, false,
+            ],
+            0,
+        );
+        let tokeneer = crate::Tokeneer::new(bpe);
+        let frequency = TokenFrequency::collect(&tokeneer, ["abc abc", "ca abc c"]);
+        assert_eq!(frequency.count(6), 3);
+        assert_eq!((frequency.tokens(), frequency.words()), (8, 5));
+        // <unk>、单字节词和 <s> 总是保留
+        let (pruned, remap, fertility) = Bpe::try_from_tokenizer_model_by_frequency(
+            &tokeneer.internal().to_tokenizer_model(),
+            &frequency,
+            9,
+            [7],
+        )
+        .unwrap();
+        assert_eq!(remap.dropped().collect::<Vec<_>>(), [8, 9]);
+        assert_eq!(pruned.vocab_size(), 9);
+        assert_eq!(pruned.encode("abc").into_iter().collect::<Vec<_>>(), [6]);
+        // ca 出现一次，改为 c a 两个词
+        assert_eq!(fertility.before, 1.6);
+        assert_eq!(fertility.after, 1.8);
+    }
+
+    #[test]
+    fn test_bpe_export() {
+        let bpe = Bpe::new(
+            [
+                "<unk>", "<0x61>", "a", "b", "c", "ab", "bc", "abc", "<s>", "ca",
+            ],
+            [0., 0., -1., -1., -2., -3., -3., -5., 0., -9.],
+            [
+                false, true, false, false, false, false, false, false, false, false,
+            ],
+            0,
+        );
+        let (pruned, remap) = Bpe::try_from_tokenizer_model_pruned(
+            &bpe.to_tokenizer_model(),
+            PruneOptions {
+                min_score: None,
+                max_rank: Some(5),
+            },
+        )
+        .unwrap();
+        assert_eq!(remap.dropped().collect::<Vec<_>>(), [9]);
+        // 裁剪后导出再加载，排名和编码结果不变
+        let reloaded = Bpe::try_from_tokenizer_model(&pruned.to_tokenizer_model()).unwrap();
+        assert_eq!(reloaded.vocab_size(), 9);
+        for t in 0..9 {
+            assert_eq!(reloaded.rank(t), pruned.rank(t));
+            assert_eq!(reloaded.decode(t), pruned.decode(t));
+        }
+        assert_eq!(pruned.export_score(7), -4.);
+        for text in ["abca", "cab", "<s>", "xbc"] {
+            let expected = pruned.encode(text).into_iter().collect::<Vec<_>>();
+            assert_eq!(
+                reloaded.encode(text).into_iter().collect::<Vec<_>>(),
+                expected
+            );
+        }
+        // 单字节词写为 <0xAB>，不可达的词标记为控制词
+        let model = pruned.to_tokenizer_model();
+        assert_eq!(model[..16], *b"\x0a\x0e\x0a\x05<unk>\x15\0\0\0\0\x18\x02");
+        assert!(model.windows(8).any(|w| w == b"\x0a\x06<0x61>"));
+        assert!(model.windows(10).any(|w| w == b"<s>\x15\0\0\0\0\x18\x03"));
+    }
+
+    #[test]
+    fn test_bpe_export_long_piece() {
+        // 超过 127 字节的词和记录使用多字节的变长长度
+        let long = "ab".repeat(65);
+        let bpe = Bpe::new(
+            ["<unk>", "a", "b", &long, "ab"],
+            [0., -1., -1., -2., -3.],
+            [false; 5],
+            0,
+        );
+        let model = bpe.to_tokenizer_model();
+        let reloaded = Bpe::try_from_tokenizer_model(&model).unwrap();
+        assert_eq!(reloaded.vocab_size(), 5);
+        assert_eq!(reloaded.decode(3), long.as_bytes());
+        for text in [&*long, "abab", "ba"] {
+            assert_eq!(
+                reloaded.encode(text).into_iter().collect::<Vec<_>>(),
+                bpe.encode(text).into_iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[cfg(feature = "tokenizer-json")]
+    #[test]
+    fn test_bpe_export_json() {
+        let bpe = Bpe::new(
+            ["<unk>", "<0x61>", "a", "b", "c", "ab", "bc", "abc"],
+            [0., 0., -1., -1., -1., -3., -2., -5.],
+            [false, true, false, false, false, false, false, false],
+            0,
+        );
+        let json = bpe.to_tokenizer_json();
+        let model = &json["model"];
+        assert_eq!(model["unk_token"], "<unk>");
+        assert_eq!(model["byte_fallback"], true);
+        assert_eq!(model["vocab"]["<0x61>"], 1);
+        assert_eq!(
+            model["merges"],
+            serde_json::json!([["b", "c"], ["a", "b"], ["a", "bc"], ["ab", "c"]])
+        );
+    }
+
+    #[test]
+    fn test_bpe_validate_reachability() {
+        let bpe = Bpe::new(["<unk>", "a", "b", "ab"], [0., 1., 1., 2.], [false; 4], 0);
+        assert_eq!(bpe.validate_reachability(), Ok(()));
+        // "bça" 没有合并路径，两个 "ab" 中只有一个可达
+        let bpe = Bpe::new(
+            ["<unk>", "a", "b", "ç", "ab", "bça", "ab"],
+            [0., 1., 1., 1., 2., 3., 2.],
+            [false; 7],
+            0,
+        );
+        let found = bpe.token_to_id("ab").unwrap();
+        let mut expected = vec![
+            UnreachableToken {
+                token: 5,
+                encoded: vec![2, 3, 1],
+            },
+            UnreachableToken {
+                token: 4 + 6 - found,
+                encoded: vec![found],
+            },
+        ];
+        expected.sort_unstable_by_key(|u| u.token);
+        assert_eq!(bpe.validate_reachability(), Err(expected));
+        let mut tokeneer = crate::Tokeneer::new(bpe);
+        assert_eq!(tokeneer.encode("bça"), [2, 3, 1]);
+        assert_eq!(tokeneer.register_unreachable(), 1);
+        assert_eq!(tokeneer.register_unreachable(), 0);
+        assert_eq!(tokeneer.encode("bçaab"), [5, found]);
+    }
+
+    #[test]
+    fn test_bpe_memory_usage() {
+        let bpe = test_bpe();
+        let usage = bpe.memory_usage();
+        assert_eq!(usage.vocab, bpe.compress_stats().compressed_len);
+        assert!(usage.metadata >= 10 * size_of::<TokenMeta>());
+        assert!(usage.index > 0);
+        assert_eq!(usage.cache, 0);
+    }
+
+    #[test]
+    fn test_bpe_deprecate() {
+        let mut bpe = test_bpe();
+        bpe.deprecate([8]);
+        assert_eq!(bpe.encode("abd").into_iter().collect::<Vec<_>>(), [5, 4]);
+        assert_eq!(bpe.decode(8), b"bd");
+        assert!(!bpe.inaccessible().contains_key("bd"));
+    }
+
+    #[test]
+    fn test_bpe_token_to_id() {
+        let bpe = test_bpe();
+        bpe.warm_token_to_id();
+        assert_eq!(bpe.token_to_id("bcd"), Some(9));
+        assert_eq!(bpe.token_to_id("<unk>"), Some(0));
+        assert_eq!(bpe.token_to_id("x"), None);
+
+        // 单字节词与普通词内容相同时，返回编码时使用的普通词
+        let bpe = Bpe::new(
+            ["<unk>", "<0x41>", "A"],
+            [0., 1., 1.],
+            [false, true, false],
+            0,
+        );
+        assert_eq!(bpe.token_to_id("A"), Some(2));
+    }
+
+    #[test]
+    fn test_bpe_derivation() {
+        let bpe = test_bpe();
+        assert_eq!(bpe.decompose(8), Some((2, 4)));
+        assert_eq!(bpe.decompose(1), None);
+        assert_eq!(bpe.decompose(9), None); // 不可达
+
+        let bpe = Bpe::new(
+            ["<unk>", "a", "b", "c", "ab", "abc"],
+            [0., 1., 1., 1., 3., 2.],
+            [false; 6],
+            0,
+        );
+        assert_eq!(bpe.decompose(5), Some((4, 3)));
+        assert_eq!(
+            bpe.derivation(5),
+            Some(Derivation::Merge(
+                5,
+                Box::new([
+                    Derivation::Merge(4, Box::new([Derivation::Leaf(1), Derivation::Leaf(2)])),
+                    Derivation::Leaf(3),
+                ])
+            ))
+        );
+        assert_eq!(bpe.derivation(3), Some(Derivation::Leaf(3)));
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_bpe_dropout() {
+        use crate::EncodeOptions;
+        use rand::{rngs::SmallRng, SeedableRng};
+
+        let bpe = test_bpe();
+        let encode = |p, seed| {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let options = EncodeOptions::default().with_dropout(p, &mut rng);
+            bpe.encode_with("abdabd", options)
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(encode(0., 0), [1, 8, 1, 8]);
+        assert_eq!(encode(1., 0), [1, 2, 4, 1, 2, 4]);
+        // 相同的种子得到相同的结果
+        for seed in 0..8 {
+            assert_eq!(encode(0.5, seed), encode(0.5, seed));
+        }
     }
 
     #[test]
@@ -0,0 +1,256 @@
+use super::{parse_tokenizer_model, rank, Bpe, Derivation};
+use crate::{tokeneer::or_panic, utok, vocab::LoadError, InputTooLong, Method, Tokeneer};
+
+/// 加载词表时按评分或排名裁剪词的选项，默认不裁剪。
+///
+/// <unk> 和单字节词总是保留，以保证任何文本仍能编码。
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct PruneOptions {
+    /// 丢弃评分低于此值的词
+    pub min_score: Option<f32>,
+    /// 丢弃合并排名不小于此值的词，排名从 0 开始，评分相同的词排名相同
+    pub max_rank: Option<u32>,
+}
+
+/// 裁剪词表前后词序号的对应关系。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct VocabRemap {
+    /// 原词序号到新词序号，被丢弃的词为 `None`
+    new_ids: Box<[Option<utok>]>,
+    /// 新词序号到原词序号
+    old_ids: Box<[utok]>,
+}
+
+impl VocabRemap {
+    /// 原词序号对应的新词序号，被丢弃或超出原词表时返回 `None`。
+    #[inline]
+    pub fn new_id(&self, old: utok) -> Option<utok> {
+        self.new_ids.get(old as usize).copied().flatten()
+    }
+
+    /// 新词序号对应的原词序号，超出新词表时返回 `None`。
+    #[inline]
+    pub fn old_id(&self, new: utok) -> Option<utok> {
+        self.old_ids.get(new as usize).copied()
+    }
+
+    /// 被丢弃的原词序号，升序排列。
+    pub fn dropped(&self) -> impl Iterator<Item = utok> + '_ {
+        (0..self.new_ids.len() as utok).filter(|&t| self.new_ids[t as usize].is_none())
+    }
+
+    /// 原词表的大小。
+    #[inline]
+    pub fn old_size(&self) -> usize {
+        self.new_ids.len()
+    }
+
+    /// 裁剪后词表的大小。
+    #[inline]
+    pub fn new_size(&self) -> usize {
+        self.old_ids.len()
+    }
+}
+
+impl Bpe {
+    /// 解析 tokenizer.model 文件，按 `options` 丢弃评分过低或排名过靠后的词后构造分词器，
+    /// 并给出新旧词序号的对应关系，用于内存受限、可以接受略差压缩率的部署。
+    ///
+    /// 保留的词按原顺序重新编号。丢弃合并的中间词可能使更长的词不可达，
+    /// 这些词可以通过 [`inaccessible`](Self::inaccessible) 查询。
+    pub fn try_from_tokenizer_model_pruned(
+        model: &[u8],
+        options: PruneOptions,
+    ) -> Result<(Self, VocabRemap), LoadError> {
+        let pieces = parse_tokenizer_model(model)?;
+        if pieces.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        let ranks = rank(&pieces.iter().map(|&(_, score)| score).collect::<Vec<_>>());
+        let keep = |i: usize| {
+            let (piece, score) = pieces[i];
+            i == 0
+                || crate::as_byte_token(piece.as_bytes()).is_some()
+                || (options.min_score.is_none_or(|min| score >= min)
+                    && options.max_rank.is_none_or(|max| ranks[i] < max))
+        };
+        Ok(Self::prune_pieces(&pieces, keep))
+    }
+
+    /// 解析 tokenizer.model 文件，按语料中的词频选出 `target_size` 个词构造分词器，
+    /// 给出新旧词序号的对应关系和预计的繁殖率变化。
+    ///
+    /// `frequency` 必须由按同一个词表编码的语料统计得到。<unk>、单字节词和 `keep` 中的词
+    /// （例如注册为特殊词的词）总是保留，即使总数超过 `target_size`。
+    /// 其余的词按词频从高到低选取，词频计入用这个词合并出的所有更长的词的出现次数，
+    /// 因此保留的词的合并路径不会被切断；词频相同时优先保留排名靠前的词。
+    pub fn try_from_tokenizer_model_by_frequency(
+        model: &[u8],
+        frequency: &TokenFrequency,
+        target_size: usize,
+        keep: impl IntoIterator<Item = utok>,
+    ) -> Result<(Self, VocabRemap, FertilityChange), LoadError> {
+        let pieces = parse_tokenizer_model(model)?;
+        if pieces.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        let mut required = vec![false; pieces.len()];
+        required[0] = true;
+        for (i, &(piece, _)) in pieces.iter().enumerate() {
+            if crate::as_byte_token(piece.as_bytes()).is_some() {
+                required[i] = true
+            }
+        }
+        for t in keep {
+            if let Some(r) = required.get_mut(t as usize) {
+                *r = true
+            }
+        }
+        // 词频沿推导树向下累加到合并出这个词的每个中间词
+        let full = Self::from_pieces(&pieces);
+        let mut demand = vec![0u64; pieces.len()];
+        for (t, &count) in frequency.counts.iter().enumerate().take(pieces.len()) {
+            if count == 0 {
+                continue;
+            }
+            match full.derivation(t as utok) {
+                Some(tree) => {
+                    let mut stack = vec![&tree];
+                    while let Some(node) = stack.pop() {
+                        demand[node.token() as usize] += count;
+                        if let Derivation::Merge(_, children) = node {
+                            stack.extend(children.iter())
+                        }
+                    }
+                }
+                None => demand[t] += count,
+            }
+        }
+        let mut candidates = (0..pieces.len())
+            .filter(|&i| !required[i])
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|&i| (std::cmp::Reverse(demand[i]), full.tokens[i].rank, i));
+        let quota = target_size.saturating_sub(required.iter().filter(|&&r| r).count());
+        for i in candidates.into_iter().take(quota) {
+            required[i] = true
+        }
+        let (bpe, remap) = Self::prune_pieces(&pieces, |i| required[i]);
+
+        // 被丢弃的词的每次出现改为用保留的词编码它的内容
+        let mut after = frequency.tokens;
+        for old in remap.dropped() {
+            let count = frequency.count(old);
+            if count > 0 {
+                let len = bpe.encode(pieces[old as usize].0).into_iter().count() as u64;
+                after += count * len.saturating_sub(1)
+            }
+        }
+        let fertility = FertilityChange {
+            before: frequency.fertility(),
+            after: after as f64 / frequency.words.max(1) as f64,
+        };
+        Ok((bpe, remap, fertility))
+    }
+
+    /// 保留 `keep` 选中的词，按原顺序重新编号。
+    fn prune_pieces(pieces: &[(&str, f32)], keep: impl Fn(usize) -> bool) -> (Self, VocabRemap) {
+        let mut new_ids = vec![None; pieces.len()].into_boxed_slice();
+        let mut old_ids = Vec::new();
+        let mut kept = Vec::new();
+        for (i, &piece) in pieces.iter().enumerate() {
+            if keep(i) {
+                new_ids[i] = Some(old_ids.len() as utok);
+                old_ids.push(i as utok);
+                kept.push(piece);
+            }
+        }
+        let remap = VocabRemap {
+            new_ids,
+            old_ids: old_ids.into(),
+        };
+        (Self::from_pieces(&kept), remap)
+    }
+}
+
+/// 语料中每个词出现的次数，用于按词频裁剪词表。
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct TokenFrequency {
+    /// 词序号 -> 出现次数
+    counts: Vec<u64>,
+    /// 词的总数
+    tokens: u64,
+    /// 按空白分隔的单词总数
+    words: u64,
+}
+
+/// 裁剪前后的繁殖率，即平均每个单词编码出的词数。
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FertilityChange {
+    pub before: f64,
+    pub after: f64,
+}
+
+impl TokenFrequency {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 用分词器编码语料并统计词频。
+    ///
+    /// 任一段文本超过 [`InputLimit::Reject`](crate::InputLimit::Reject) 的上限时 panic。
+    pub fn collect<'a, M: Method>(
+        tokeneer: &Tokeneer<M>,
+        texts: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        or_panic(Self::try_collect(tokeneer, texts))
+    }
+
+    /// 同 [`collect`](Self::collect)，任一段文本超过上限时返回错误。
+    pub fn try_collect<'a, M: Method>(
+        tokeneer: &Tokeneer<M>,
+        texts: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, InputTooLong> {
+        let mut ans = Self::new();
+        for text in texts {
+            ans.add(text, &tokeneer.try_encode(text)?)
+        }
+        Ok(ans)
+    }
+
+    /// 计入一段文本及其编码结果。
+    pub fn add(&mut self, text: &str, tokens: &[utok]) {
+        for &t in tokens {
+            let t = t as usize;
+            if t >= self.counts.len() {
+                self.counts.resize(t + 1, 0)
+            }
+            self.counts[t] += 1
+        }
+        self.tokens += tokens.len() as u64;
+        self.words += text.split_whitespace().count() as u64
+    }
+
+    /// 词出现的次数。
+    #[inline]
+    pub fn count(&self, token: utok) -> u64 {
+        self.counts.get(token as usize).copied().unwrap_or(0)
+    }
+
+    /// 词的总数。
+    #[inline]
+    pub fn tokens(&self) -> u64 {
+        self.tokens
+    }
+
+    /// 按空白分隔的单词总数。
+    #[inline]
+    pub fn words(&self) -> u64 {
+        self.words
+    }
+
+    /// 平均每个单词编码出的词数，没有单词时为 0。
+    pub fn fertility(&self) -> f64 {
+        self.tokens as f64 / self.words.max(1) as f64
+    }
+}
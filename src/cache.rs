@@ -0,0 +1,163 @@
+//! 这个模块提供对完整文本编码结果的记忆化缓存。
+
+use crate::{utok, InputTooLong, Method, Tokeneer};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+/// 带有编码缓存的分词器，适用于反复编码相同文本的场景。
+///
+/// 缓存按最近最少使用的顺序淘汰，可以在多个线程间共享。
+pub struct CachedTokeneer<M> {
+    tokeneer: Tokeneer<M>,
+    capacity: usize,
+    cache: Mutex<Lru>,
+}
+
+/// 缓存命中统计。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    /// 文本 -> (编码结果, 最近一次使用的时刻)
+    map: HashMap<String, (Arc<[utok]>, u64)>,
+    /// 使用时刻 -> 文本，用于找到最久未使用的项
+    order: BTreeMap<u64, String>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl<M: Method> CachedTokeneer<M> {
+    /// 包装一个分词器，至多缓存 `capacity` 条文本的编码结果。
+    pub fn new(tokeneer: Tokeneer<M>, capacity: usize) -> Self {
+        Self {
+            tokeneer,
+            capacity,
+            cache: Default::default(),
+        }
+    }
+
+    /// 编码文本，优先使用缓存的结果。
+    ///
+    /// 文本超过 [`InputLimit::Reject`](crate::InputLimit::Reject) 的上限时 panic。
+    pub fn encode(&self, text: &str) -> Arc<[utok]> {
+        match self.try_encode(text) {
+            Ok(tokens) => tokens,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// 同 [`encode`](Self::encode)，文本超过上限时返回错误，错误不会被缓存。
+    pub fn try_encode(&self, text: &str) -> Result<Arc<[utok]>, InputTooLong> {
+        if let Some(tokens) = self.cache.lock().unwrap().get(text) {
+            return Ok(tokens);
+        }
+        // 编码过程不持有锁，其他线程可以并发访问缓存
+        let tokens = Arc::<[utok]>::from(self.tokeneer.try_encode(text)?);
+        if self.capacity > 0 {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(text, tokens.clone(), self.capacity);
+        }
+        Ok(tokens)
+    }
+}
+
+impl<M> CachedTokeneer<M> {
+    #[inline]
+    pub fn tokeneer(&self) -> &Tokeneer<M> {
+        &self.tokeneer
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> Tokeneer<M> {
+        self.tokeneer
+    }
+
+    /// 当前缓存的条目数。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().map.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats
+    }
+
+    /// 清空缓存和统计。
+    #[inline]
+    pub fn clear(&self) {
+        *self.cache.lock().unwrap() = Default::default();
+    }
+}
+
+impl Lru {
+    fn get(&mut self, text: &str) -> Option<Arc<[utok]>> {
+        let Some((tokens, time)) = self.map.get_mut(text) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        self.clock += 1;
+        let key = self.order.remove(time).unwrap();
+        *time = self.clock;
+        self.order.insert(self.clock, key);
+        Some(tokens.clone())
+    }
+
+    fn insert(&mut self, text: &str, tokens: Arc<[utok]>, capacity: usize) {
+        // 其他线程可能已经插入了相同的文本
+        if self.map.contains_key(text) {
+            return;
+        }
+        while self.map.len() >= capacity {
+            let (_, key) = self.order.pop_first().unwrap();
+            self.map.remove(&key);
+        }
+        self.clock += 1;
+        self.map.insert(text.to_string(), (tokens, self.clock));
+        self.order.insert(self.clock, text.to_string());
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_lru() {
+        let lpe = Lpe::new(["<unk>", "a", "b"].map(str::as_bytes), 0);
+        let cached = CachedTokeneer::new(Tokeneer::new(lpe), 2);
+
+        assert_eq!(&*cached.encode("ab"), [1, 2]);
+        assert_eq!(&*cached.encode("ba"), [2, 1]);
+        assert_eq!(&*cached.encode("ab"), [1, 2]);
+        // 淘汰最久未使用的 "ba"
+        assert_eq!(&*cached.encode("aa"), [1, 1]);
+        assert_eq!(cached.len(), 2);
+        assert_eq!(&*cached.encode("ab"), [1, 2]);
+        assert_eq!(cached.stats(), CacheStats { hits: 2, misses: 3 });
+
+        let lpe = Lpe::new(["<unk>", "a", "b"].map(str::as_bytes), 0);
+        let mut tokeneer = Tokeneer::new(lpe);
+        tokeneer.set_input_limit(Some(crate::InputLimit::Reject(2)));
+        let cached = CachedTokeneer::new(tokeneer, 2);
+        assert_eq!(&*cached.try_encode("ab").unwrap(), [1, 2]);
+        // 超过上限的文本不进入缓存
+        assert!(cached.try_encode("aba").is_err());
+        assert_eq!(cached.len(), 1);
+    }
+}
@@ -0,0 +1,66 @@
+//! 这个模块生成静态词表的 Rust 源码，供构建脚本使用。
+//!
+//! 生成的源码包含三个静态数组 `{NAME}_PIECES: &[&str]`、`{NAME}_SCORES: &[f32]` 和 `{NAME}_IS_BYTE: &[bool]`，
+//! 通过 `include!` 引入后即可在运行时不读取任何文件地构造分词器：
+//!
+//! ```ignore
+//! // build.rs
+//! let model = std::fs::read("tokenizer.model").unwrap();
+//! let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("vocab.rs");
+//! tokeneer::codegen::write_tokenizer_model(std::fs::File::create(out).unwrap(), "VOCAB", &model).unwrap();
+//!
+//! // main.rs
+//! include!(concat!(env!("OUT_DIR"), "/vocab.rs"));
+//! let bpe = tokeneer::Bpe::new(
+//!     VOCAB_PIECES.iter().copied(),
+//!     VOCAB_SCORES.iter().copied(),
+//!     VOCAB_IS_BYTE.iter().copied(),
+//!     0,
+//! );
+//! ```
+
+use crate::{bpe::parse_tokenizer_model, vocab::as_byte_token};
+use std::io::{self, Write};
+
+/// 生成静态词表的源码。
+pub fn write_static_vocab<'a>(
+    mut w: impl Write,
+    name: &str,
+    pieces: impl IntoIterator<Item = &'a str>,
+    scores: impl IntoIterator<Item = f32>,
+    is_byte: impl IntoIterator<Item = bool>,
+) -> io::Result<()> {
+    writeln!(w, "pub static {name}_PIECES: &[&str] = &[")?;
+    for piece in pieces {
+        writeln!(w, "    {piece:?},")?;
+    }
+    writeln!(w, "];")?;
+
+    writeln!(w, "pub static {name}_SCORES: &[f32] = &[")?;
+    for score in scores {
+        // 按位表示评分，保证生成的值与原值完全一致
+        writeln!(w, "    f32::from_bits({:#010x}),", score.to_bits())?;
+    }
+    writeln!(w, "];")?;
+
+    writeln!(w, "pub static {name}_IS_BYTE: &[bool] = &[")?;
+    for is_byte in is_byte {
+        writeln!(w, "    {is_byte},")?;
+    }
+    writeln!(w, "];")
+}
+
+/// 解析 tokenizer.model 文件并生成静态词表的源码，形如 `<0xAB>` 的词被标记为单字节词。
+pub fn write_tokenizer_model(w: impl Write, name: &str, model: &[u8]) -> io::Result<()> {
+    let pieces =
+        parse_tokenizer_model(model).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_static_vocab(
+        w,
+        name,
+        pieces.iter().map(|&(piece, _)| piece),
+        pieces.iter().map(|&(_, score)| score),
+        pieces
+            .iter()
+            .map(|&(piece, _)| as_byte_token(piece.as_bytes()).is_some()),
+    )
+}
@@ -0,0 +1,181 @@
+//! 这个模块分析词表对目标字符集的覆盖情况，用于在采用词表之前评估其多语言适用性。
+//!
+//! 字符按 Unicode 区块分组。每个字符单独编码，不需要回退到单字节词或 <unk> 即视为被覆盖。
+
+use crate::{utok, Method};
+use std::ops::RangeInclusive;
+
+/// 常用的 Unicode 区块，按起始码点排列。
+pub const BLOCKS: &[(&str, RangeInclusive<char>)] = &[
+    ("Basic Latin", '\u{0000}'..='\u{007F}'),
+    ("Latin-1 Supplement", '\u{0080}'..='\u{00FF}'),
+    ("Latin Extended-A", '\u{0100}'..='\u{017F}'),
+    ("Latin Extended-B", '\u{0180}'..='\u{024F}'),
+    ("IPA Extensions", '\u{0250}'..='\u{02AF}'),
+    ("Combining Diacritical Marks", '\u{0300}'..='\u{036F}'),
+    ("Greek and Coptic", '\u{0370}'..='\u{03FF}'),
+    ("Cyrillic", '\u{0400}'..='\u{04FF}'),
+    ("Armenian", '\u{0530}'..='\u{058F}'),
+    ("Hebrew", '\u{0590}'..='\u{05FF}'),
+    ("Arabic", '\u{0600}'..='\u{06FF}'),
+    ("Devanagari", '\u{0900}'..='\u{097F}'),
+    ("Bengali", '\u{0980}'..='\u{09FF}'),
+    ("Tamil", '\u{0B80}'..='\u{0BFF}'),
+    ("Telugu", '\u{0C00}'..='\u{0C7F}'),
+    ("Thai", '\u{0E00}'..='\u{0E7F}'),
+    ("Georgian", '\u{10A0}'..='\u{10FF}'),
+    ("Hangul Jamo", '\u{1100}'..='\u{11FF}'),
+    ("Latin Extended Additional", '\u{1E00}'..='\u{1EFF}'),
+    ("General Punctuation", '\u{2000}'..='\u{206F}'),
+    ("Currency Symbols", '\u{20A0}'..='\u{20CF}'),
+    ("Arrows", '\u{2190}'..='\u{21FF}'),
+    ("Mathematical Operators", '\u{2200}'..='\u{22FF}'),
+    ("Box Drawing", '\u{2500}'..='\u{257F}'),
+    ("Miscellaneous Symbols", '\u{2600}'..='\u{26FF}'),
+    ("Dingbats", '\u{2700}'..='\u{27BF}'),
+    ("CJK Symbols and Punctuation", '\u{3000}'..='\u{303F}'),
+    ("Hiragana", '\u{3040}'..='\u{309F}'),
+    ("Katakana", '\u{30A0}'..='\u{30FF}'),
+    (
+        "CJK Unified Ideographs Extension A",
+        '\u{3400}'..='\u{4DBF}',
+    ),
+    ("CJK Unified Ideographs", '\u{4E00}'..='\u{9FFF}'),
+    ("Hangul Syllables", '\u{AC00}'..='\u{D7AF}'),
+    ("Private Use Area", '\u{E000}'..='\u{F8FF}'),
+    ("CJK Compatibility Ideographs", '\u{F900}'..='\u{FAFF}'),
+    ("Halfwidth and Fullwidth Forms", '\u{FF00}'..='\u{FFEF}'),
+    (
+        "Miscellaneous Symbols and Pictographs",
+        '\u{1F300}'..='\u{1F5FF}',
+    ),
+    ("Emoticons", '\u{1F600}'..='\u{1F64F}'),
+    (
+        "Supplemental Symbols and Pictographs",
+        '\u{1F900}'..='\u{1F9FF}',
+    ),
+    (
+        "CJK Unified Ideographs Extension B",
+        '\u{20000}'..='\u{2A6DF}',
+    ),
+];
+
+/// 不属于 [`BLOCKS`] 中任何区块的字符所在的分组。
+pub const OTHER: &str = "Other";
+
+/// 一个区块的覆盖情况。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockCoverage {
+    /// 区块名
+    pub block: &'static str,
+    /// 目标字符集中属于这个区块的字符数
+    pub chars: usize,
+    /// 不需要回退即可编码的字符数
+    pub covered: usize,
+    /// 逐字符编码得到的总词数
+    pub tokens: usize,
+}
+
+impl BlockCoverage {
+    /// 被覆盖的字符比例。
+    #[inline]
+    pub fn ratio(&self) -> f64 {
+        self.covered as f64 / self.chars as f64
+    }
+
+    /// 平均每个字符编码得到的词数。
+    #[inline]
+    pub fn tokens_per_char(&self) -> f64 {
+        self.tokens as f64 / self.chars as f64
+    }
+}
+
+/// 分析词表对目标字符集的覆盖情况，按区块在 [`BLOCKS`] 中的顺序返回，[`OTHER`] 排在最后。
+///
+/// 目标字符集中重复的字符会被重复统计；不包含任何目标字符的区块不出现在结果中。
+pub fn analyze(method: &impl Method, chars: impl IntoIterator<Item = char>) -> Vec<BlockCoverage> {
+    let new = |block| BlockCoverage {
+        block,
+        chars: 0,
+        covered: 0,
+        tokens: 0,
+    };
+    let mut blocks = BLOCKS
+        .iter()
+        .map(|&(name, _)| new(name))
+        .chain([new(OTHER)])
+        .collect::<Vec<_>>();
+
+    let unk = method.unk_token();
+    let mut buf = [0u8; 4];
+    for c in chars {
+        let i = BLOCKS
+            .binary_search_by(|(_, range)| {
+                use std::cmp::Ordering::*;
+                if *range.end() < c {
+                    Less
+                } else if *range.start() > c {
+                    Greater
+                } else {
+                    Equal
+                }
+            })
+            .unwrap_or(BLOCKS.len());
+        let tokens = method
+            .encode(c.encode_utf8(&mut buf))
+            .into_iter()
+            .collect::<Vec<utok>>();
+        let block = &mut blocks[i];
+        block.chars += 1;
+        block.tokens += tokens.len();
+        if tokens
+            .iter()
+            .all(|&t| Some(t) != unk && method.fallback_byte(t).is_none())
+        {
+            block.covered += 1
+        }
+    }
+    blocks.retain(|b| b.chars > 0);
+    blocks
+}
+
+#[cfg(test)]
+mod coverage_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_analyze() {
+        assert!(BLOCKS.windows(2).all(|w| w[0].1.end() < w[1].1.start()));
+
+        let lpe = Lpe::new(
+            ["<unk>", "a", "b", "<0xE4>", "<0xB8>", "<0x80>", "中"].map(str::as_bytes),
+            0,
+        );
+        let report = analyze(&lpe, "ab中一c\u{10FFFF}".chars());
+        assert_eq!(
+            report,
+            [
+                BlockCoverage {
+                    block: "Basic Latin",
+                    chars: 3,
+                    covered: 2,
+                    tokens: 3,
+                },
+                BlockCoverage {
+                    block: "CJK Unified Ideographs",
+                    chars: 2,
+                    covered: 1,
+                    tokens: 4,
+                },
+                BlockCoverage {
+                    block: OTHER,
+                    chars: 1,
+                    covered: 0,
+                    tokens: 4,
+                },
+            ]
+        );
+        assert_eq!(report[1].tokens_per_char(), 2.);
+    }
+}
@@ -0,0 +1,134 @@
+//! 将批量编码的结果直接写入 Arrow 数组，供基于 Arrow 或 Polars 的数据管线使用。
+
+use crate::{utok, EncodeOptions, InputTooLong, Method, Tokeneer};
+use arrow_array::{
+    builder::BooleanBufferBuilder, cast::AsArray, types::UInt32Type, Array, ArrayRef, ListArray,
+    PrimitiveArray,
+};
+use arrow_buffer::{NullBuffer, OffsetBuffer, ScalarBuffer};
+use arrow_schema::{ArrowError, DataType, Field};
+use std::sync::Arc;
+
+impl<M: Method> Tokeneer<M> {
+    /// 编码一列文本，结果为元素类型是 `UInt32` 的 `ListArray`，第 `i` 个列表是第 `i` 个文本的词序列。
+    ///
+    /// 所有文本的词序号直接追加到同一个值缓冲区，不构造中间的词序列。
+    /// 空值编码为空值，因此可以直接传入 `StringArray::iter()` 等 Arrow 字符串数组的迭代器。
+    /// 设置了 [`InputLimit::Reject`](crate::InputLimit::Reject) 且文本超过上限时 panic。
+    pub fn encode_arrow<'a>(&self, texts: impl IntoIterator<Item = Option<&'a str>>) -> ListArray {
+        match self.try_encode_arrow(texts) {
+            Ok(lists) => lists,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    fn try_encode_arrow<'a>(
+        &self,
+        texts: impl IntoIterator<Item = Option<&'a str>>,
+    ) -> Result<ListArray, InputTooLong> {
+        let texts = texts.into_iter();
+        let mut values = Vec::<utok>::new();
+        let mut offsets = Vec::with_capacity(texts.size_hint().0 + 1);
+        let mut validity = BooleanBufferBuilder::new(texts.size_hint().0);
+        offsets.push(0i32);
+        for text in texts {
+            if let Some(text) = text {
+                self.try_encode_into(text, EncodeOptions::default(), &mut values)?
+            }
+            validity.append(text.is_some());
+            offsets.push(i32::try_from(values.len()).expect("too many tokens for a ListArray"));
+        }
+        let nulls = NullBuffer::new(validity.finish());
+        Ok(ListArray::new(
+            list_field(),
+            OffsetBuffer::new(ScalarBuffer::from(offsets)),
+            Arc::new(PrimitiveArray::<UInt32Type>::from(values)),
+            (nulls.null_count() > 0).then_some(nulls),
+        ))
+    }
+}
+
+/// 由分词器构造向量化的分词函数，将字符串列编码为词序列列，类型见 [`tokenize_return_type`]。
+///
+/// 接受 `Utf8`、`LargeUtf8` 和 `Utf8View` 列，其他类型和超过输入长度上限的文本返回错误。
+/// 函数只依赖 arrow-rs 的数组，可以包装为 DataFusion 等基于 arrow-rs 的引擎的标量函数；
+/// Polars 使用自己的 Arrow 实现，需要经 Arrow C 数据接口转换列。
+pub fn tokenize_udf<M>(
+    tokeneer: Arc<Tokeneer<M>>,
+) -> impl Fn(&dyn Array) -> Result<ArrayRef, ArrowError> + Send + Sync + 'static
+where
+    M: Method + Send + Sync + 'static,
+{
+    move |column| {
+        let lists = match column.data_type() {
+            DataType::Utf8 => tokeneer.try_encode_arrow(column.as_string::<i32>()),
+            DataType::LargeUtf8 => tokeneer.try_encode_arrow(column.as_string::<i64>()),
+            DataType::Utf8View => tokeneer.try_encode_arrow(column.as_string_view()),
+            ty => {
+                return Err(ArrowError::InvalidArgumentError(format!(
+                    "tokenize expects a string column, got {ty}"
+                )))
+            }
+        };
+        match lists {
+            Ok(lists) => Ok(Arc::new(lists)),
+            Err(e) => Err(ArrowError::ComputeError(e.to_string())),
+        }
+    }
+}
+
+/// [`tokenize_udf`] 输出列的类型，即元素类型为 `UInt32` 的 `List`。
+pub fn tokenize_return_type() -> DataType {
+    DataType::List(list_field())
+}
+
+fn list_field() -> Arc<Field> {
+    Arc::new(Field::new_list_field(DataType::UInt32, false))
+}
+
+#[cfg(test)]
+mod arrow_tests {
+    use super::*;
+    use crate::{InputLimit, Lpe};
+    use arrow_array::{Int32Array, LargeStringArray, StringArray};
+
+    #[test]
+    fn test_encode_arrow() {
+        let tokeneer = Tokeneer::new(Lpe::new(["<unk>", "a", "b", "ab"].map(str::as_bytes), 0));
+        let texts = StringArray::from(vec![Some("aba"), None, Some(""), Some("b")]);
+        let lists = tokeneer.encode_arrow(texts.iter());
+        assert_eq!(lists.len(), 4);
+        assert_eq!(lists.value_offsets(), [0, 2, 2, 2, 3]);
+        assert!(lists.is_null(1));
+        assert!(lists.is_valid(2));
+        assert_eq!(
+            lists.values().as_primitive::<UInt32Type>().values(),
+            &[3, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_udf() {
+        let mut tokeneer = Tokeneer::new(Lpe::new(["<unk>", "a", "b", "ab"].map(str::as_bytes), 0));
+        tokeneer.set_input_limit(Some(InputLimit::Reject(4)));
+        let udf = tokenize_udf(Arc::new(tokeneer));
+        let lists = udf(&LargeStringArray::from(vec!["ab", "ba"])).unwrap();
+        assert_eq!(lists.data_type(), &tokenize_return_type());
+        assert_eq!(
+            lists
+                .as_list::<i32>()
+                .value(1)
+                .as_primitive::<UInt32Type>()
+                .values(),
+            &[2, 1]
+        );
+        assert!(matches!(
+            udf(&Int32Array::from(vec![1])),
+            Err(ArrowError::InvalidArgumentError(_))
+        ));
+        assert!(matches!(
+            udf(&StringArray::from(vec!["ababa"])),
+            Err(ArrowError::ComputeError(_))
+        ));
+    }
+}
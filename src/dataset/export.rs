@@ -0,0 +1,171 @@
+//! 将批量编码的结果导出为 NumPy 或 safetensors 文件。
+
+use crate::{tokeneer::or_panic, utok, InputTooLong, Method, Tokeneer};
+use std::io::{self, Write};
+
+/// 填充到相同长度的一批词序列及其注意力掩码，元素类型为 i64，与 PyTorch 的默认整型一致。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PaddedBatch {
+    rows: usize,
+    cols: usize,
+    ids: Vec<i64>,
+    mask: Vec<i64>,
+}
+
+impl PaddedBatch {
+    /// 在每个序列右侧填充 `pad` 直到与最长的序列等长，填充位置的掩码为 0。
+    pub fn new<S: AsRef<[utok]>>(seqs: impl IntoIterator<Item = S>, pad: utok) -> Self {
+        let seqs = seqs.into_iter().collect::<Vec<_>>();
+        let rows = seqs.len();
+        let cols = seqs.iter().map(|s| s.as_ref().len()).max().unwrap_or(0);
+        let mut ids = Vec::with_capacity(rows * cols);
+        let mut mask = Vec::with_capacity(rows * cols);
+        for seq in &seqs {
+            let seq = seq.as_ref();
+            ids.extend(seq.iter().map(|&t| t as i64));
+            ids.extend(std::iter::repeat_n(pad as i64, cols - seq.len()));
+            mask.extend(std::iter::repeat_n(1, seq.len()));
+            mask.extend(std::iter::repeat_n(0, cols - seq.len()));
+        }
+        Self {
+            rows,
+            cols,
+            ids,
+            mask,
+        }
+    }
+
+    /// 编码每个文本并填充。
+    ///
+    /// 任一个文本超过 [`InputLimit::Reject`](crate::InputLimit::Reject) 的上限时 panic。
+    pub fn encode<'a, M: Method>(
+        tokeneer: &Tokeneer<M>,
+        texts: impl IntoIterator<Item = &'a str>,
+        pad: utok,
+    ) -> Self {
+        or_panic(Self::try_encode(tokeneer, texts, pad))
+    }
+
+    /// 同 [`encode`](Self::encode)，任一个文本超过上限时返回错误。
+    pub fn try_encode<'a, M: Method>(
+        tokeneer: &Tokeneer<M>,
+        texts: impl IntoIterator<Item = &'a str>,
+        pad: utok,
+    ) -> Result<Self, InputTooLong> {
+        let sequences = texts
+            .into_iter()
+            .map(|t| tokeneer.try_encode(t))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(sequences, pad))
+    }
+
+    /// 矩阵的形状，即序列数和填充后的长度。
+    #[inline]
+    pub fn shape(&self) -> [usize; 2] {
+        [self.rows, self.cols]
+    }
+
+    /// 按行优先排列的词序号。
+    #[inline]
+    pub fn ids(&self) -> &[i64] {
+        &self.ids
+    }
+
+    /// 按行优先排列的注意力掩码。
+    #[inline]
+    pub fn mask(&self) -> &[i64] {
+        &self.mask
+    }
+
+    /// 将词序号写为 `.npy` 文件。
+    pub fn write_npy_ids(&self, w: impl Write) -> io::Result<()> {
+        write_npy(w, self.shape(), &self.ids)
+    }
+
+    /// 将注意力掩码写为 `.npy` 文件。
+    pub fn write_npy_mask(&self, w: impl Write) -> io::Result<()> {
+        write_npy(w, self.shape(), &self.mask)
+    }
+
+    /// 将词序号和注意力掩码写为 safetensors 文件，张量名为 `input_ids` 和 `attention_mask`。
+    pub fn write_safetensors(&self, mut w: impl Write) -> io::Result<()> {
+        let [rows, cols] = self.shape();
+        let size = size_of_val(self.ids.as_slice());
+        let mut header = format!(
+            "{{\"input_ids\":{{\"dtype\":\"I64\",\"shape\":[{rows},{cols}],\"data_offsets\":[0,{size}]}},\
+             \"attention_mask\":{{\"dtype\":\"I64\",\"shape\":[{rows},{cols}],\"data_offsets\":[{size},{}]}}}}",
+            size * 2
+        );
+        // 头部以空格填充到 8 字节对齐，使数据部分对齐
+        header.extend(std::iter::repeat_n(
+            ' ',
+            header.len().next_multiple_of(8) - header.len(),
+        ));
+
+        w.write_all(&(header.len() as u64).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        write_i64(&mut w, &self.ids)?;
+        write_i64(&mut w, &self.mask)
+    }
+}
+
+/// 写出 NumPy 1.0 格式的二维 i64 数组。
+fn write_npy(mut w: impl Write, [rows, cols]: [usize; 2], data: &[i64]) -> io::Result<()> {
+    const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+    let mut header =
+        format!("{{'descr': '<i8', 'fortran_order': False, 'shape': ({rows}, {cols}), }}");
+    // 魔数、版本、头部长度和头部的总长度对齐到 64 字节，头部以换行结尾
+    let total = (MAGIC.len() + 2 + header.len() + 1).next_multiple_of(64);
+    header.extend(std::iter::repeat_n(
+        ' ',
+        total - MAGIC.len() - 2 - header.len() - 1,
+    ));
+    header.push('\n');
+
+    w.write_all(MAGIC)?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    write_i64(w, data)
+}
+
+fn write_i64(mut w: impl Write, data: &[i64]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(size_of_val(data));
+    for x in data {
+        buf.extend_from_slice(&x.to_le_bytes())
+    }
+    w.write_all(&buf)
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let batch = PaddedBatch::new([&[1, 2, 3][..], &[4]], 0);
+        assert_eq!(batch.shape(), [2, 3]);
+        assert_eq!(batch.ids(), [1, 2, 3, 4, 0, 0]);
+        assert_eq!(batch.mask(), [1, 1, 1, 1, 0, 0]);
+
+        let mut npy = Vec::new();
+        batch.write_npy_ids(&mut npy).unwrap();
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        assert!(header.contains("'shape': (2, 3)") && header.ends_with('\n'));
+        assert_eq!(npy.len(), 10 + header_len + 6 * 8);
+        assert_eq!(&npy[10 + header_len..][..8], &1i64.to_le_bytes());
+
+        let mut st = Vec::new();
+        batch.write_safetensors(&mut st).unwrap();
+        let n = u64::from_le_bytes(st[..8].try_into().unwrap()) as usize;
+        assert_eq!(n % 8, 0);
+        let header = serde_json::from_slice::<serde_json::Value>(&st[8..8 + n]).unwrap();
+        assert_eq!(
+            header["attention_mask"]["data_offsets"],
+            serde_json::json!([48, 96])
+        );
+        assert_eq!(st.len(), 8 + n + 96);
+        assert_eq!(&st[8 + n + 48..][..8], &1i64.to_le_bytes());
+    }
+}
@@ -0,0 +1,304 @@
+//! 批量编码 JSONL 格式的语料。
+
+use crate::{Method, Tokeneer};
+use serde_json::{Map, Value};
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// 批量编码的进度。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Progress {
+    /// 已处理的文档数
+    pub documents: u64,
+    /// 已产生的词数
+    pub tokens: u64,
+    /// 已读取的输入字节数，包括换行符和空行
+    pub bytes: u64,
+    /// 已写出的字节数
+    pub written: u64,
+}
+
+impl Progress {
+    /// 从检查点文件读取进度，文件不存在时返回 `None`。
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid checkpoint");
+        let obj = serde_json::from_str::<Map<String, Value>>(&text).map_err(|_| invalid())?;
+        let get = |key: &str| obj.get(key).and_then(Value::as_u64).ok_or_else(invalid);
+        Ok(Some(Self {
+            documents: get("documents")?,
+            tokens: get("tokens")?,
+            bytes: get("bytes")?,
+            written: get("written")?,
+        }))
+    }
+
+    /// 将进度写入检查点文件。
+    ///
+    /// 先写入同目录下的临时文件再重命名，因此中断时检查点文件保持旧内容或新内容之一。
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let json = serde_json::json!({
+            "documents": self.documents,
+            "tokens": self.tokens,
+            "bytes": self.bytes,
+            "written": self.written,
+        });
+        let mut file = File::create(&tmp)?;
+        file.write_all(json.to_string().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(tmp, path)
+    }
+}
+
+/// 逐行读取 JSONL 语料，编码每个对象的 `field` 字段，再逐行写出。
+///
+/// 写出的对象去掉 `field` 字段，并增加 `ids` 字段保存词序列，其他字段原样保留作为元数据；空行被跳过。
+/// 每次读入至多 `batch_size` 行一起编码，因此内存占用与批大小成正比，
+/// 启用 `rayon` 特性时批内并行编码。每处理完一批调用一次 `progress`。
+///
+/// 行不是 JSON 对象或缺少字符串类型的 `field` 字段时返回 [`io::ErrorKind::InvalidData`] 错误，
+/// 错误信息包含行号（从 1 开始）。
+pub fn tokenize_jsonl<M: Method + Sync>(
+    tokeneer: &Tokeneer<M>,
+    input: impl BufRead,
+    mut output: impl Write,
+    field: &str,
+    batch_size: usize,
+    mut progress: impl FnMut(&Progress),
+) -> io::Result<Progress> {
+    let state = run(
+        tokeneer,
+        input,
+        &mut output,
+        field,
+        batch_size,
+        Progress::default(),
+        |state, _| {
+            progress(state);
+            Ok(())
+        },
+    )?;
+    output.flush()?;
+    Ok(state)
+}
+
+/// 与 [`tokenize_jsonl`] 相同，但在每批处理完后将进度保存到 `checkpoint` 文件，中断后可以从检查点继续。
+///
+/// 如果检查点文件存在，从其记录的输入位置继续读取，并将输出文件截断到记录的长度，
+/// 丢弃最后一个检查点之后写出的内容；否则从头开始并清空输出文件。
+/// 对已完成的任务再次调用不会重复编码。行号从恢复位置重新计数。
+pub fn tokenize_jsonl_resumable<M: Method + Sync>(
+    tokeneer: &Tokeneer<M>,
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    checkpoint: impl AsRef<Path>,
+    field: &str,
+    batch_size: usize,
+    mut progress: impl FnMut(&Progress),
+) -> io::Result<Progress> {
+    let checkpoint = checkpoint.as_ref();
+    let state = Progress::load(checkpoint)?.unwrap_or_default();
+
+    let mut input = File::open(input)?;
+    input.seek(SeekFrom::Start(state.bytes))?;
+    let mut output = File::options()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(output)?;
+    output.set_len(state.written)?;
+    output.seek(SeekFrom::End(0))?;
+
+    run(
+        tokeneer,
+        BufReader::new(input),
+        io::BufWriter::new(output),
+        field,
+        batch_size,
+        state,
+        |state, output| {
+            // 输出先落盘，检查点才能指向完整的内容
+            output.flush()?;
+            output.get_ref().sync_data()?;
+            state.save(checkpoint)?;
+            progress(state);
+            Ok(())
+        },
+    )
+}
+
+/// 从 `state` 记录的进度开始处理，每处理完一批调用一次 `on_batch`。
+fn run<M: Method + Sync, W: Write>(
+    tokeneer: &Tokeneer<M>,
+    mut input: impl BufRead,
+    mut output: W,
+    field: &str,
+    batch_size: usize,
+    mut state: Progress,
+    mut on_batch: impl FnMut(&Progress, &mut W) -> io::Result<()>,
+) -> io::Result<Progress> {
+    assert!(batch_size > 0, "batch size must be positive");
+
+    let mut line_no = 0usize;
+    let mut batch = Vec::with_capacity(batch_size);
+    loop {
+        // 读入一批非空行
+        batch.clear();
+        while batch.len() < batch_size {
+            let mut line = String::new();
+            let n = input.read_line(&mut line)?;
+            if n == 0 {
+                break;
+            }
+            line_no += 1;
+            state.bytes += n as u64;
+            if !line.trim().is_empty() {
+                batch.push((line_no, line));
+            }
+        }
+        if batch.is_empty() {
+            break;
+        }
+
+        let encode =
+            |(line_no, line): &(usize, String)| encode_line(tokeneer, field, *line_no, line);
+        #[cfg(feature = "rayon")]
+        let lines = batch.par_iter().map(encode).collect::<Vec<_>>();
+        #[cfg(not(feature = "rayon"))]
+        let lines = batch.iter().map(encode).collect::<Vec<_>>();
+
+        for line in lines {
+            let (line, tokens) = line?;
+            output.write_all(line.as_bytes())?;
+            output.write_all(b"\n")?;
+            state.documents += 1;
+            state.tokens += tokens as u64;
+            state.written += line.len() as u64 + 1;
+        }
+        on_batch(&state, &mut output)?;
+    }
+    Ok(state)
+}
+
+/// 编码一行，返回写出的内容和词数。
+fn encode_line<M: Method>(
+    tokeneer: &Tokeneer<M>,
+    field: &str,
+    line_no: usize,
+    line: &str,
+) -> io::Result<(String, usize)> {
+    let invalid =
+        |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {line_no}: {msg}"));
+
+    let mut obj =
+        serde_json::from_str::<Map<String, Value>>(line).map_err(|e| invalid(e.to_string()))?;
+    let Some(Value::String(text)) = obj.remove(field) else {
+        return Err(invalid(format!("missing string field {field:?}")));
+    };
+    let ids = tokeneer
+        .try_encode(&text)
+        .map_err(|e| invalid(e.to_string()))?;
+    let n = ids.len();
+    obj.insert("ids".into(), ids.into());
+    Ok((Value::Object(obj).to_string(), n))
+}
+
+#[cfg(test)]
+mod jsonl_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_tokenize_jsonl() {
+        let lpe = Lpe::new(["<unk>", "a", "b", "ab"].map(str::as_bytes), 0);
+        let tokeneer = Tokeneer::new(lpe);
+        let input = "{\"text\":\"ab\",\"id\":7}\n\n{\"text\":\"ba\"}\n{\"text\":\"a\"}\n";
+
+        let mut output = Vec::new();
+        let mut reports = Vec::new();
+        let progress = tokenize_jsonl(&tokeneer, input.as_bytes(), &mut output, "text", 2, |p| {
+            reports.push(*p)
+        })
+        .unwrap();
+        let expected = "{\"id\":7,\"ids\":[3]}\n{\"ids\":[2,1]}\n{\"ids\":[1]}\n";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+        assert_eq!(
+            progress,
+            Progress {
+                documents: 3,
+                tokens: 4,
+                bytes: input.len() as u64,
+                written: expected.len() as u64,
+            }
+        );
+        assert_eq!(reports.len(), 2);
+
+        let err = tokenize_jsonl(&tokeneer, "{}\n".as_bytes(), io::sink(), "text", 2, |_| {})
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // 超过输入长度上限的文本返回错误而不是 panic
+        let mut tokeneer = tokeneer;
+        tokeneer.set_input_limit(Some(crate::InputLimit::Reject(2)));
+        assert!(tokenize_jsonl(&tokeneer, input.as_bytes(), io::sink(), "text", 2, |_| {}).is_ok());
+        let input = "{\"text\":\"ab\"}\n{\"text\":\"aba\"}\n";
+        let err =
+            tokenize_jsonl(&tokeneer, input.as_bytes(), io::sink(), "text", 2, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"));
+    }
+
+    #[test]
+    fn test_tokenize_jsonl_resumable() {
+        let lpe = Lpe::new(["<unk>", "a", "b", "ab"].map(str::as_bytes), 0);
+        let tokeneer = Tokeneer::new(lpe);
+        let dir = std::env::temp_dir().join(format!("tokeneer-jsonl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.jsonl");
+        let output = dir.join("output.jsonl");
+        let checkpoint = dir.join("checkpoint.json");
+        fs::write(
+            &input,
+            "{\"text\":\"ab\"}\n{\"text\":\"b\"}\n{\"text\":\"a\"}\n",
+        )
+        .unwrap();
+
+        // 模拟处理完第一批后中断：检查点之后写出的内容应被丢弃
+        let first = Progress {
+            documents: 1,
+            tokens: 1,
+            bytes: 14,
+            written: 12,
+        };
+        fs::write(&output, "{\"ids\":[3]}\ngarbage").unwrap();
+        first.save(&checkpoint).unwrap();
+        assert_eq!(Progress::load(&checkpoint).unwrap(), Some(first));
+
+        let run = || {
+            tokenize_jsonl_resumable(&tokeneer, &input, &output, &checkpoint, "text", 1, |_| {})
+                .unwrap()
+        };
+        let state = run();
+        assert_eq!(state.documents, 3);
+        let expected = "{\"ids\":[3]}\n{\"ids\":[2]}\n{\"ids\":[1]}\n";
+        assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+
+        // 已完成的任务不会重复编码
+        assert_eq!(run(), state);
+        assert_eq!(fs::read_to_string(&output).unwrap(), expected);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
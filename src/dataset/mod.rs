@@ -0,0 +1,219 @@
+//! 这个模块以 GPT 类训练数据加载器使用的格式读写编码后的语料。
+//!
+//! 数据文件是所有文档的词序号依次拼接而成的小端序 u16 或 u32 数组，不含文件头。
+//! 索引文件是小端序 u64 数组，记录每个文档在数据文件中的起止位置（以词为单位）：
+//! 第一项为 0，第 `i + 1` 项为第 `i` 个文档的结束位置，因此 n 个文档对应 n + 1 项。
+
+#[cfg(feature = "arrow")]
+mod arrow;
+#[cfg(feature = "export")]
+mod export;
+#[cfg(feature = "jsonl")]
+mod jsonl;
+
+#[cfg(feature = "arrow")]
+pub use arrow::{tokenize_return_type, tokenize_udf};
+#[cfg(feature = "export")]
+pub use export::PaddedBatch;
+#[cfg(feature = "jsonl")]
+pub use jsonl::{tokenize_jsonl, tokenize_jsonl_resumable, Progress};
+
+use crate::utok;
+use std::io::{self, Write};
+
+/// 数据文件中每个词序号的宽度。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenWidth {
+    U16,
+    U32,
+}
+
+impl TokenWidth {
+    /// 能表示词表中所有词的最窄宽度。
+    #[inline]
+    pub fn for_vocab(vocab_size: usize) -> Self {
+        if vocab_size <= 1 << 16 {
+            Self::U16
+        } else {
+            Self::U32
+        }
+    }
+
+    /// 每个词序号占用的字节数。
+    #[inline]
+    pub const fn bytes(self) -> usize {
+        match self {
+            Self::U16 => 2,
+            Self::U32 => 4,
+        }
+    }
+}
+
+/// 将编码后的文档写入数据文件和索引文件。
+pub struct TokenWriter<D: Write, I: Write> {
+    data: D,
+    index: I,
+    width: TokenWidth,
+    len: u64,
+}
+
+impl<D: Write, I: Write> TokenWriter<D, I> {
+    /// 创建写入器，并写出索引文件的第一项。
+    pub fn new(data: D, mut index: I, width: TokenWidth) -> io::Result<Self> {
+        index.write_all(&0u64.to_le_bytes())?;
+        Ok(Self {
+            data,
+            index,
+            width,
+            len: 0,
+        })
+    }
+
+    /// 写入一个文档的词序列。
+    ///
+    /// 词序号超出宽度范围时返回 [`io::ErrorKind::InvalidInput`] 错误，此时不写入任何内容。
+    pub fn write_document(&mut self, tokens: &[utok]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(tokens.len() * self.width.bytes());
+        match self.width {
+            TokenWidth::U16 => {
+                for &t in tokens {
+                    let t = u16::try_from(t).map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("token {t} does not fit in u16"),
+                        )
+                    })?;
+                    buf.extend_from_slice(&t.to_le_bytes())
+                }
+            }
+            TokenWidth::U32 => {
+                for &t in tokens {
+                    buf.extend_from_slice(&t.to_le_bytes())
+                }
+            }
+        }
+        self.data.write_all(&buf)?;
+        self.len += tokens.len() as u64;
+        self.index.write_all(&self.len.to_le_bytes())
+    }
+
+    /// 已写入的词数。
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 刷新并取回数据文件和索引文件。
+    pub fn finish(mut self) -> io::Result<(D, I)> {
+        self.data.flush()?;
+        self.index.flush()?;
+        Ok((self.data, self.index))
+    }
+}
+
+/// 读取由 [`TokenWriter`] 写出的语料。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TokenReader {
+    tokens: Vec<utok>,
+    offsets: Vec<u64>,
+}
+
+impl TokenReader {
+    /// 解析数据文件和索引文件的内容。
+    ///
+    /// 文件长度与宽度不符或索引与数据不一致时返回 [`io::ErrorKind::InvalidData`] 错误。
+    pub fn new(data: &[u8], index: &[u8], width: TokenWidth) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        if !data.len().is_multiple_of(width.bytes()) {
+            return Err(invalid("data length is not a multiple of the token width"));
+        }
+        let tokens = match width {
+            TokenWidth::U16 => data
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]) as utok)
+                .collect::<Vec<_>>(),
+            TokenWidth::U32 => data
+                .chunks_exact(4)
+                .map(|b| utok::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        };
+
+        if !index.len().is_multiple_of(8) {
+            return Err(invalid("index length is not a multiple of 8"));
+        }
+        let offsets = index
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        if offsets.first() != Some(&0)
+            || offsets.windows(2).any(|w| w[0] > w[1])
+            || offsets.last() != Some(&(tokens.len() as u64))
+        {
+            return Err(invalid("index does not match data"));
+        }
+        Ok(Self { tokens, offsets })
+    }
+
+    /// 文档数。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 所有文档拼接而成的词序列。
+    #[inline]
+    pub fn tokens(&self) -> &[utok] {
+        &self.tokens
+    }
+
+    /// 第 `i` 个文档的词序列。
+    #[inline]
+    pub fn document(&self, i: usize) -> &[utok] {
+        &self.tokens[self.offsets[i] as usize..self.offsets[i + 1] as usize]
+    }
+
+    /// 依次遍历每个文档的词序列。
+    pub fn documents(&self) -> impl Iterator<Item = &[utok]> + '_ {
+        (0..self.len()).map(|i| self.document(i))
+    }
+}
+
+#[cfg(test)]
+mod dataset_tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let docs: [&[utok]; 3] = [&[1, 2, 3], &[], &[65535, 0]];
+        for width in [TokenWidth::U16, TokenWidth::U32] {
+            let mut writer = TokenWriter::new(Vec::new(), Vec::new(), width).unwrap();
+            for doc in docs {
+                writer.write_document(doc).unwrap();
+            }
+            assert_eq!(writer.len(), 5);
+            let (data, index) = writer.finish().unwrap();
+            assert_eq!(data.len(), 5 * width.bytes());
+            assert_eq!(index.len(), 4 * 8);
+
+            let reader = TokenReader::new(&data, &index, width).unwrap();
+            assert_eq!(reader.documents().collect::<Vec<_>>(), docs);
+            assert!(TokenReader::new(&data[1..], &index, width).is_err());
+        }
+
+        let mut writer = TokenWriter::new(Vec::new(), Vec::new(), TokenWidth::U16).unwrap();
+        assert!(writer.write_document(&[1 << 16]).is_err());
+        assert!(writer.is_empty());
+        assert_eq!(TokenWidth::for_vocab(65537), TokenWidth::U32);
+    }
+}
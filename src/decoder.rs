@@ -0,0 +1,124 @@
+//! 这个模块提供按模型族组合的解码后处理。
+
+use crate::{as_byte_level_token, replace_metaspace};
+
+/// 解码预设，对每个词的内容做其模型族需要的后处理，默认不做任何处理。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum DecodePreset {
+    /// 原样输出词的内容
+    #[default]
+    Raw,
+    /// `▁` 还原为空格，并去掉输出开头由编码时添加的一个空格
+    LlamaSentencepiece,
+    /// 字节级字母表中的每个字符还原为其表示的字节
+    Gpt2ByteLevel,
+    /// `##` 开头的词去掉前缀后直接拼接，其他词之前插入空格
+    BertWordpiece,
+}
+
+impl DecodePreset {
+    /// 所有预设及其名字。
+    pub const ALL: [(&'static str, Self); 4] = [
+        ("raw", Self::Raw),
+        ("llama-sentencepiece", Self::LlamaSentencepiece),
+        ("gpt2-bytelevel", Self::Gpt2ByteLevel),
+        ("bert-wordpiece", Self::BertWordpiece),
+    ];
+
+    /// 按名字查找预设，名字见 [`ALL`](Self::ALL)。
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, preset)| preset)
+    }
+
+    /// 预设的名字。
+    pub fn name(self) -> &'static str {
+        Self::ALL.iter().find(|(_, p)| *p == self).unwrap().0
+    }
+
+    /// 将词序列中第 `index` 个词的内容 `piece` 处理后追加到 `out`。
+    pub(crate) fn apply(self, index: usize, piece: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Self::Raw => out.extend_from_slice(piece),
+            Self::LlamaSentencepiece => {
+                let piece = replace_metaspace(piece);
+                match &*piece {
+                    [b' ', rest @ ..] if index == 0 => out.extend_from_slice(rest),
+                    piece => out.extend_from_slice(piece),
+                }
+            }
+            Self::Gpt2ByteLevel => match std::str::from_utf8(piece) {
+                Ok(piece) => {
+                    let mut buf = [0u8; 4];
+                    for c in piece.chars() {
+                        let c = c.encode_utf8(&mut buf).as_bytes();
+                        match as_byte_level_token(c) {
+                            Some(b) => out.push(b),
+                            None => out.extend_from_slice(c),
+                        }
+                    }
+                }
+                Err(_) => out.extend_from_slice(piece),
+            },
+            Self::BertWordpiece => match piece.strip_prefix(b"##") {
+                Some(rest) => out.extend_from_slice(rest),
+                None => {
+                    if index > 0 {
+                        out.push(b' ')
+                    }
+                    out.extend_from_slice(piece)
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod decoder_tests {
+    use super::*;
+
+    fn decode(preset: DecodePreset, pieces: &[&str]) -> String {
+        let mut out = Vec::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            preset.apply(i, piece.as_bytes(), &mut out)
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_presets() {
+        use DecodePreset::*;
+        assert_eq!(
+            decode(LlamaSentencepiece, &["▁Hello", "▁world"]),
+            "Hello world"
+        );
+        assert_eq!(
+            decode(Gpt2ByteLevel, &["Hello", "Ġworld", "Ċ"]),
+            "Hello world\n"
+        );
+        assert_eq!(
+            decode(BertWordpiece, &["token", "##izer", "works"]),
+            "tokenizer works"
+        );
+        assert_eq!(decode(Raw, &["▁a", "##b"]), "▁a##b");
+
+        // decode_token 返回原始内容，不应用预设
+        let mut tokeneer = crate::Tokeneer::new(crate::Lpe::new(
+            ["<unk>", "▁Hello", "Ġworld"].map(str::as_bytes),
+            0,
+        ));
+        tokeneer.set_decode_preset(LlamaSentencepiece);
+        assert_eq!(tokeneer.decode(&[1]), "Hello");
+        assert_eq!(tokeneer.decode_token(1), Some("▁Hello"));
+        tokeneer.set_decode_preset(Gpt2ByteLevel);
+        assert_eq!(tokeneer.decode(&[2]), " world");
+        assert_eq!(tokeneer.decode_token(2), Some("Ġworld"));
+        for (name, preset) in DecodePreset::ALL {
+            assert_eq!(DecodePreset::from_name(name), Some(preset));
+            assert_eq!(preset.name(), name);
+        }
+        assert_eq!(DecodePreset::from_name("unknown"), None);
+    }
+}
@@ -0,0 +1,420 @@
+//! 这个模块比较两个分词器的差异，用于审计微调或扩展词表带来的变化。
+
+use crate::{utok, Method, Tokeneer};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Write},
+    ops::Range,
+};
+
+/// 两个分词器之间的差异报告，由 [`Tokeneer::diff`] 生成。
+///
+/// 词按内容对应，内容相同的多个词只取序号最小的一个参与比较。
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenizerDiff {
+    /// 只在新分词器中出现的词，按序号升序排列
+    pub added: Vec<TokenEntry>,
+    /// 只在原分词器中出现的词，按序号升序排列
+    pub removed: Vec<TokenEntry>,
+    /// 内容相同但序号改变的词，按原序号升序排列
+    pub changed_ids: Vec<IdChange>,
+    /// 内容相同但合并排名改变的词，按原序号升序排列
+    pub changed_ranks: Vec<RankChange>,
+    /// 新增、删除或映射改变的特殊词，按名字排列
+    pub changed_special: Vec<SpecialChange>,
+}
+
+/// 词表中的一个词。
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenEntry {
+    pub piece: Vec<u8>,
+    pub id: utok,
+}
+
+/// 序号改变的词。
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IdChange {
+    pub piece: Vec<u8>,
+    pub base: utok,
+    pub other: utok,
+}
+
+/// 合并排名改变的词，排名见 [`Method::rank`]。
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RankChange {
+    pub piece: Vec<u8>,
+    pub base: Option<u32>,
+    pub other: Option<u32>,
+}
+
+/// 改变的特殊词，`None` 表示该侧没有注册这个特殊词。
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpecialChange {
+    pub name: String,
+    pub base: Option<Vec<utok>>,
+    pub other: Option<Vec<utok>>,
+}
+
+impl TokenizerDiff {
+    /// 两个分词器是否完全相同。
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed_ids.is_empty()
+            && self.changed_ranks.is_empty()
+            && self.changed_special.is_empty()
+    }
+
+    /// 以左右两栏写出差异，左栏为原分词器，右栏为新分词器，用于评审词表更新。
+    ///
+    /// 每行以一个符号开头：`-` 删除，`+` 新增，`~` 序号改变，`^` 排名改变，`@` 特殊词改变；
+    /// 最后一行是各类差异的数量。`color` 为 `true` 时以 ANSI 转义序列着色。
+    pub fn write_side_by_side(&self, mut w: impl Write, color: bool) -> io::Result<()> {
+        let piece = |p: &[u8]| format!("{:?}", String::from_utf8_lossy(p));
+        let rank = |r: Option<u32>| r.map_or("-".into(), |r| r.to_string());
+        let special = |t: &Option<Vec<utok>>| t.as_ref().map_or("-".into(), |t| format!("{t:?}"));
+
+        // (符号, 颜色, 左栏, 右栏)
+        let mut rows = Vec::<(char, &str, String, String)>::new();
+        for e in &self.removed {
+            rows.push((
+                '-',
+                RED,
+                format!("{} {}", e.id, piece(&e.piece)),
+                String::new(),
+            ))
+        }
+        for e in &self.added {
+            rows.push((
+                '+',
+                GREEN,
+                String::new(),
+                format!("{} {}", e.id, piece(&e.piece)),
+            ))
+        }
+        for c in &self.changed_ids {
+            let p = piece(&c.piece);
+            rows.push((
+                '~',
+                YELLOW,
+                format!("{} {p}", c.base),
+                format!("{} {p}", c.other),
+            ))
+        }
+        for c in &self.changed_ranks {
+            let p = piece(&c.piece);
+            rows.push((
+                '^',
+                CYAN,
+                format!("{p} rank {}", rank(c.base)),
+                format!("{p} rank {}", rank(c.other)),
+            ))
+        }
+        for c in &self.changed_special {
+            let p = format!("{:?}", c.name);
+            rows.push((
+                '@',
+                MAGENTA,
+                format!("{p} {}", special(&c.base)),
+                format!("{p} {}", special(&c.other)),
+            ))
+        }
+
+        let width = rows
+            .iter()
+            .map(|(_, _, left, _)| left.chars().count())
+            .max()
+            .unwrap_or(0);
+        let (reset, paint) = if color { (RESET, true) } else { ("", false) };
+        for (sign, c, left, right) in rows {
+            let c = if paint { c } else { "" };
+            let line = format!("{sign} {left:<width$} | {right}");
+            writeln!(w, "{c}{}{reset}", line.trim_end())?
+        }
+        writeln!(
+            w,
+            "{} added, {} removed, {} moved, {} rank changes, {} special changes",
+            self.added.len(),
+            self.removed.len(),
+            self.changed_ids.len(),
+            self.changed_ranks.len(),
+            self.changed_special.len(),
+        )
+    }
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+impl<M: Method> Tokeneer<M> {
+    /// 比较 `self` 与 `other` 的词表和特殊词，`self` 视为原分词器。
+    pub fn diff<N: Method>(&self, other: &Tokeneer<N>) -> TokenizerDiff {
+        let base_map = pieces(self.internal());
+        let other_map = pieces(other.internal());
+
+        let mut ans = TokenizerDiff::default();
+        for (&piece, &id) in &base_map {
+            match other_map.get(piece) {
+                Some(&other_id) => {
+                    if id != other_id {
+                        ans.changed_ids.push(IdChange {
+                            piece: piece.to_vec(),
+                            base: id,
+                            other: other_id,
+                        })
+                    }
+                    let base_rank = self.internal().rank(id);
+                    let other_rank = other.internal().rank(other_id);
+                    if base_rank != other_rank {
+                        ans.changed_ranks.push(RankChange {
+                            piece: piece.to_vec(),
+                            base: base_rank,
+                            other: other_rank,
+                        })
+                    }
+                }
+                None => ans.removed.push(TokenEntry {
+                    piece: piece.to_vec(),
+                    id,
+                }),
+            }
+        }
+        for (&piece, &id) in &other_map {
+            if !base_map.contains_key(piece) {
+                ans.added.push(TokenEntry {
+                    piece: piece.to_vec(),
+                    id,
+                })
+            }
+        }
+        ans.added.sort_unstable_by_key(|e| e.id);
+        ans.removed.sort_unstable_by_key(|e| e.id);
+        ans.changed_ids.sort_unstable_by_key(|c| c.base);
+        ans.changed_ranks
+            .sort_unstable_by_key(|c| base_map[&*c.piece]);
+
+        let base_special = self.special_tokens().collect::<HashMap<_, _>>();
+        let other_special = other.special_tokens().collect::<HashMap<_, _>>();
+        let names = base_special
+            .keys()
+            .chain(other_special.keys())
+            .collect::<BTreeSet<_>>();
+        for name in names {
+            let base = base_special.get(name);
+            let other = other_special.get(name);
+            if base != other {
+                ans.changed_special.push(SpecialChange {
+                    name: name.to_string(),
+                    base: base.map(|t| t.to_vec()),
+                    other: other.map(|t| t.to_vec()),
+                })
+            }
+        }
+        ans
+    }
+}
+
+/// 两个词序列中覆盖同一段文本但编码不同的部分，由 [`diff_spans`] 生成。
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpanDiff {
+    /// 这一段在文本中的字节范围
+    pub bytes: Range<usize>,
+    /// 原词序列在这一段中的词
+    pub base: Vec<utok>,
+    /// 新词序列在这一段中的词
+    pub other: Vec<utok>,
+}
+
+impl<M: Method> Tokeneer<M> {
+    /// 用 `self` 和 `other` 分别编码同一段文本，给出编码不同的部分。
+    pub fn diff_encodings<N: Method>(&self, other: &Tokeneer<N>, text: &str) -> Vec<SpanDiff> {
+        diff_spans(
+            &self.encode_with_offsets(text),
+            &other.encode_with_offsets(text),
+        )
+    }
+}
+
+/// 按字节范围对齐覆盖同一段文本的两个词序列，给出编码不同的最小片段。
+///
+/// 两侧在同一位置都有词边界时，在此处切分；切分出的片段中两侧的词不同时，报告这个片段。
+pub fn diff_spans(base: &[(utok, Range<usize>)], other: &[(utok, Range<usize>)]) -> Vec<SpanDiff> {
+    let mut ans = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < base.len() || j < other.len() {
+        let (si, sj) = (i, j);
+        let start = [base.get(i), other.get(j)]
+            .into_iter()
+            .flatten()
+            .map(|(_, r)| r.start)
+            .min()
+            .unwrap();
+        // 两侧各取一个词，再推进落后的一侧，直到边界对齐或一侧耗尽
+        let (mut be, mut oe) = (start, start);
+        if let Some((_, r)) = base.get(i) {
+            be = r.end;
+            i += 1
+        }
+        if let Some((_, r)) = other.get(j) {
+            oe = r.end;
+            j += 1
+        }
+        while be != oe {
+            if be < oe && i < base.len() {
+                be = base[i].1.end;
+                i += 1
+            } else if oe < be && j < other.len() {
+                oe = other[j].1.end;
+                j += 1
+            } else {
+                break;
+            }
+        }
+        // 共用同一范围的词属于同一个片段
+        while base.get(i).is_some_and(|(_, r)| r.end <= be) {
+            i += 1
+        }
+        while other.get(j).is_some_and(|(_, r)| r.end <= oe) {
+            j += 1
+        }
+
+        let tokens = |s: &[(utok, Range<usize>)]| s.iter().map(|&(t, _)| t).collect::<Vec<_>>();
+        let (b, o) = (tokens(&base[si..i]), tokens(&other[sj..j]));
+        if b != o {
+            ans.push(SpanDiff {
+                bytes: start..be.max(oe),
+                base: b,
+                other: o,
+            })
+        }
+    }
+    ans
+}
+
+/// 词的内容 -> 序号，内容相同时取序号最小的词。
+fn pieces(method: &impl Method) -> HashMap<&[u8], utok> {
+    let mut map = HashMap::with_capacity(method.vocab_size());
+    for t in 0..method.vocab_size() as utok {
+        map.entry(method.decode(t)).or_insert(t);
+    }
+    map
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+    use crate::{Bpe, Lpe};
+
+    #[test]
+    fn test_diff_encodings() {
+        let base = Tokeneer::new(Lpe::new(["<unk>", "a", "b", "ab"].map(str::as_bytes), 0));
+        let other = Tokeneer::new(Lpe::new(["<unk>", "a", "b", "ba"].map(str::as_bytes), 0));
+        assert_eq!(
+            base.diff_encodings(&other, "aabab"),
+            [SpanDiff {
+                bytes: 1..5,
+                base: vec![3, 3],
+                other: vec![1, 3, 2],
+            }]
+        );
+        assert!(base.diff_encodings(&base, "aabab").is_empty());
+    }
+
+    #[test]
+    fn test_diff_vocab() {
+        let base = Tokeneer::new(Lpe::new(["<unk>", "a", "b", "ab"].map(str::as_bytes), 0));
+        let mut lpe = Lpe::new(["<unk>", "b", "a", "abc", "<s>"].map(str::as_bytes), 0);
+        lpe.detect_default_specials();
+        let mut other = Tokeneer::new(lpe);
+        other.extend_special([("<pair>".to_string(), vec![1, 2])]);
+
+        let diff = base.diff(&other);
+        assert_eq!(
+            diff.added,
+            [
+                TokenEntry {
+                    piece: b"abc".to_vec(),
+                    id: 3
+                },
+                TokenEntry {
+                    piece: b"<s>".to_vec(),
+                    id: 4
+                },
+            ]
+        );
+        assert_eq!(
+            diff.removed,
+            [TokenEntry {
+                piece: b"ab".to_vec(),
+                id: 3
+            }]
+        );
+        assert_eq!(
+            diff.changed_ids
+                .iter()
+                .map(|c| (c.base, c.other))
+                .collect::<Vec<_>>(),
+            [(1, 2), (2, 1)]
+        );
+        assert!(diff.changed_ranks.is_empty());
+        assert_eq!(
+            diff.changed_special
+                .iter()
+                .map(|c| (&*c.name, c.base.is_some(), c.other.is_some()))
+                .collect::<Vec<_>>(),
+            [("<pair>", false, true), ("<s>", false, true)]
+        );
+        assert!(base.diff(&base).is_empty());
+    }
+
+    #[test]
+    fn test_write_side_by_side() {
+        let base = Tokeneer::new(Lpe::new(["<unk>", "a", "b", "ab"].map(str::as_bytes), 0));
+        let other = Tokeneer::new(Lpe::new(["<unk>", "b", "a", "bc"].map(str::as_bytes), 0));
+        let mut report = Vec::new();
+        base.diff(&other)
+            .write_side_by_side(&mut report, false)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "\
+- 3 \"ab\" |
++        | 3 \"bc\"
+~ 1 \"a\"  | 2 \"a\"
+~ 2 \"b\"  | 1 \"b\"
+1 added, 1 removed, 2 moved, 0 rank changes, 0 special changes
+"
+        );
+        let mut report = Vec::new();
+        base.diff(&other)
+            .write_side_by_side(&mut report, true)
+            .unwrap();
+        assert!(report.starts_with(b"\x1b[31m- 3"));
+    }
+
+    #[test]
+    fn test_diff_rank() {
+        let bpe =
+            |scores| Tokeneer::new(Bpe::new(["<unk>", "a", "b", "ab"], scores, [false; 4], 0));
+        let diff = bpe([0., 1., 1., 2.]).diff(&bpe([0., 1., 2., 2.]));
+        assert!(diff.changed_ids.is_empty());
+        assert_eq!(
+            diff.changed_ranks,
+            [RankChange {
+                piece: b"b".to_vec(),
+                base: Some(1),
+                other: Some(0),
+            }]
+        );
+    }
+}
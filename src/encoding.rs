@@ -0,0 +1,186 @@
+//! 这个模块提供带有逐词附加信息的编码结果。
+
+use crate::utok;
+use std::ops::Range;
+
+/// 截断配置，限制编码结果的总长度。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Truncation {
+    /// 编码结果的最大长度
+    pub max_length: usize,
+    /// 编码一对文本时的截断策略
+    pub strategy: TruncationStrategy,
+}
+
+/// 编码一对文本时，决定从哪一段截断。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum TruncationStrategy {
+    /// 每次从较长的一段末尾移除一个词，等长时从第一段移除，与 HF tokenizers 相同
+    #[default]
+    LongestFirst,
+    /// 只截断第一段
+    OnlyFirst,
+    /// 只截断第二段
+    OnlySecond,
+}
+
+impl Truncation {
+    /// 按策略计算两段截断后的长度。
+    ///
+    /// `OnlyFirst` 和 `OnlySecond` 至多将对应的一段截断为空，因此结果仍可能超过最大长度。
+    pub(crate) fn apply(&self, first: usize, second: usize) -> (usize, usize) {
+        let max = self.max_length;
+        if first + second <= max {
+            return (first, second);
+        }
+        match self.strategy {
+            TruncationStrategy::LongestFirst => match (first, second) {
+                (first, second) if first <= second && max >= first * 2 => (first, max - first),
+                (first, second) if second < first && max >= second * 2 => (max - second, second),
+                // 两段都要截断时较短的一段得到一半，余下的一个词留给较长的一段，等长时留给第二段
+                (first, second) if first <= second => (max / 2, max - max / 2),
+                _ => (max - max / 2, max / 2),
+            },
+            TruncationStrategy::OnlyFirst => (max.saturating_sub(second), second),
+            TruncationStrategy::OnlySecond => (first, max.saturating_sub(first)),
+        }
+    }
+}
+
+/// 详细编码结果，除词序列外还记录每个词的附加信息。
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Encoding {
+    ids: Vec<utok>,
+    special_tokens_mask: Vec<u8>,
+    sequence_ids: Vec<Option<usize>>,
+    word_ids: Vec<Option<usize>>,
+    offsets: Vec<Range<usize>>,
+    type_ids: Vec<u32>,
+}
+
+impl Encoding {
+    /// 追加属于第 `sequence` 个输入的一段词序列，`offsets` 是每个词的字节范围，
+    /// `special` 表示这些词是否是特殊词，`word` 是这些词所属的预分词序号。
+    pub(crate) fn push(
+        &mut self,
+        tokens: &[utok],
+        offsets: impl IntoIterator<Item = Range<usize>>,
+        special: bool,
+        sequence: usize,
+        word: Option<usize>,
+    ) {
+        use std::iter::repeat_n;
+        let n = tokens.len();
+        self.ids.extend_from_slice(tokens);
+        self.special_tokens_mask.extend(repeat_n(special as u8, n));
+        self.sequence_ids
+            .extend(repeat_n(if special { None } else { Some(sequence) }, n));
+        self.word_ids.extend(repeat_n(word, n));
+        self.offsets.extend(offsets);
+        self.type_ids.extend(repeat_n(0, n));
+        debug_assert_eq!(self.offsets.len(), self.ids.len());
+    }
+
+    /// 截断到前 `len` 个词。
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.ids.truncate(len);
+        self.special_tokens_mask.truncate(len);
+        self.sequence_ids.truncate(len);
+        self.word_ids.truncate(len);
+        self.offsets.truncate(len);
+        self.type_ids.truncate(len);
+    }
+
+    /// 将所有词的类型序号设为 `type_id`。
+    pub(crate) fn set_type_id(&mut self, type_id: u32) {
+        self.type_ids.fill(type_id);
+    }
+
+    /// 将另一个编码结果拼接到末尾。
+    pub(crate) fn append(&mut self, mut other: Self) {
+        self.ids.append(&mut other.ids);
+        self.special_tokens_mask
+            .append(&mut other.special_tokens_mask);
+        self.sequence_ids.append(&mut other.sequence_ids);
+        self.word_ids.append(&mut other.word_ids);
+        self.offsets.append(&mut other.offsets);
+        self.type_ids.append(&mut other.type_ids);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// 词序列。
+    #[inline]
+    pub fn ids(&self) -> &[utok] {
+        &self.ids
+    }
+
+    /// 特殊词掩码，特殊词的位置为 1，其他位置为 0。
+    #[inline]
+    pub fn special_tokens_mask(&self) -> &[u8] {
+        &self.special_tokens_mask
+    }
+
+    /// 每个词所属的输入序号，特殊词为 `None`。
+    #[inline]
+    pub fn sequence_ids(&self) -> &[Option<usize>] {
+        &self.sequence_ids
+    }
+
+    /// 每个词所属的预分词序号，不是由 [`encode_pretokenized`](crate::Tokeneer::encode_pretokenized) 编码的词为 `None`。
+    #[inline]
+    pub fn word_ids(&self) -> &[Option<usize>] {
+        &self.word_ids
+    }
+
+    /// 每个词的类型序号，由 [`PostProcessor`](crate::PostProcessor) 的模板决定，未配置后处理时全为 0。
+    #[inline]
+    pub fn type_ids(&self) -> &[u32] {
+        &self.type_ids
+    }
+
+    /// 每个词在其所属输入中的字节范围，映射回规范化之前的文本，规则与
+    /// [`encode_with_offsets`](crate::Tokeneer::encode_with_offsets) 相同；
+    /// 由 [`encode_pretokenized`](crate::Tokeneer::encode_pretokenized) 编码时是在所属预分词中的范围。
+    #[inline]
+    pub fn offsets(&self) -> &[Range<usize>] {
+        &self.offsets
+    }
+
+    /// 第 `token` 个词对应的字节范围，超出范围时返回 `None`。
+    ///
+    /// 位置都是字节位置而不是 HF tokenizers 的字符序号，可以直接用于切片输入的字符串。
+    #[inline]
+    pub fn token_to_bytes(&self, token: usize) -> Option<Range<usize>> {
+        self.offsets.get(token).cloned()
+    }
+
+    /// 第 `sequence` 个输入中覆盖字节位置 `pos` 的词的位置。
+    ///
+    /// 多个词共用同一范围时返回第一个；特殊词不属于任何输入，不会被找到。
+    pub fn byte_to_token(&self, pos: usize, sequence: usize) -> Option<usize> {
+        (0..self.len())
+            .find(|&i| self.sequence_ids[i] == Some(sequence) && self.offsets[i].contains(&pos))
+    }
+
+    /// 第 `sequence` 个输入中与字节范围 `range` 相交的词的位置范围，用于将预测的文本片段映射为词的片段。
+    pub fn bytes_to_tokens(&self, range: Range<usize>, sequence: usize) -> Option<Range<usize>> {
+        let mut hits = (0..self.len()).filter(|&i| {
+            let offset = &self.offsets[i];
+            self.sequence_ids[i] == Some(sequence)
+                && offset.start < range.end
+                && range.start < offset.end
+        });
+        let first = hits.next()?;
+        let last = hits.next_back().unwrap_or(first);
+        Some(first..last + 1)
+    }
+}
@@ -0,0 +1,114 @@
+//! 这个模块提供不完整编码文本的长度估计，用于按词数做准入控制。
+
+use crate::{tokeneer::or_panic, InputTooLong, Method, Tokeneer};
+
+/// 每个采样窗口的字节数
+const WINDOW: usize = 4096;
+/// 采样窗口的个数，不长于全部窗口总长的文本直接编码
+const SAMPLES: usize = 32;
+
+/// 编码结果长度的估计，见 [`Tokeneer::estimate_len`]。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LengthEstimate {
+    /// 估计的词数
+    pub tokens: usize,
+    /// 误差界，真实词数一般落在 `tokens ± margin` 内，精确计算时为 0
+    pub margin: usize,
+}
+
+impl LengthEstimate {
+    /// 估计值是否精确。
+    #[inline]
+    pub fn is_exact(&self) -> bool {
+        self.margin == 0
+    }
+
+    /// 真实词数的上界估计。
+    #[inline]
+    pub fn upper(&self) -> usize {
+        self.tokens + self.margin
+    }
+}
+
+impl<M: Method> Tokeneer<M> {
+    /// 估计文本编码后的词数，耗时与文本长度无关。
+    ///
+    /// 不超过 128 KiB 的文本直接编码，结果精确。更长的文本均匀抽取 32 个 4 KiB 的窗口编码，
+    /// 按窗口的平均词密度外推；误差界取样本均值的 3 倍标准误，再加上每个窗口边界至多 1 个词的截断误差，
+    /// 窗口之间的词密度差别越大误差界越宽。
+    ///
+    /// 文本超过 [`InputLimit::Reject`](crate::InputLimit::Reject) 的上限时 panic。
+    pub fn estimate_len(&self, text: &str) -> LengthEstimate {
+        or_panic(self.try_estimate_len(text))
+    }
+
+    /// 同 [`estimate_len`](Self::estimate_len)，文本超过上限时返回错误。
+    ///
+    /// 抽样估计时不规范化整段文本，因此按规范化之前的长度检查上限。
+    pub fn try_estimate_len(&self, text: &str) -> Result<LengthEstimate, InputTooLong> {
+        if text.len() <= WINDOW * SAMPLES {
+            return Ok(LengthEstimate {
+                tokens: self.try_encode(text)?.len(),
+                margin: 0,
+            });
+        }
+        self.check_input(text)?;
+        let stride = (text.len() - WINDOW) / (SAMPLES - 1);
+        let densities = (0..SAMPLES)
+            .map(|i| {
+                let start = floor_char_boundary(text, i * stride);
+                let end = floor_char_boundary(text, start + WINDOW);
+                let window = &text[start..end];
+                let tokens = self.try_encode(window)?.len();
+                Ok(tokens as f64 / window.len().max(1) as f64)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let n = SAMPLES as f64;
+        let mean = densities.iter().sum::<f64>() / n;
+        let variance = densities.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / (n - 1.);
+        let len = text.len() as f64;
+        let margin = 3. * (variance / n).sqrt() * len + len / WINDOW as f64;
+        Ok(LengthEstimate {
+            tokens: (mean * len).round() as usize,
+            margin: margin.ceil() as usize,
+        })
+    }
+}
+
+/// 不超过 `pos` 的最近的字符边界。
+fn floor_char_boundary(text: &str, pos: usize) -> usize {
+    let mut pos = pos.min(text.len());
+    while !text.is_char_boundary(pos) {
+        pos -= 1
+    }
+    pos
+}
+
+#[cfg(test)]
+mod estimate_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_estimate_len() {
+        let tokeneer = Tokeneer::new(Lpe::new(
+            ["<unk>", "a", "b", "ab", " ", "中"].map(str::as_bytes),
+            0,
+        ));
+        let short = "ab a";
+        assert_eq!(
+            tokeneer.estimate_len(short),
+            LengthEstimate {
+                tokens: 3,
+                margin: 0
+            }
+        );
+        let long = "ab a b 中".repeat(30_000);
+        let exact = tokeneer.encode(&long).len();
+        let estimate = tokeneer.estimate_len(&long);
+        assert!(!estimate.is_exact());
+        assert!(estimate.tokens.abs_diff(exact) <= estimate.margin);
+        // 误差界不超过真实长度的 5%
+        assert!(estimate.margin * 20 < exact);
+    }
+}
@@ -0,0 +1,253 @@
+//! 供模糊测试调用的入口，任意输入都不应 panic 或越界访问。
+//!
+//! 这些入口不属于稳定的公开接口，只用于 cargo-fuzz 或 AFL 等工具，例如：
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| tokeneer::fuzz::tokenizer_model(data));
+//! ```
+
+use crate::{utok, Bpe, EncodeMode, EncodeOptions, Lpe, Method, Tokeneer, UnkPolicy};
+
+/// 加载后用于编码的样例文本，覆盖 ascii、多字节字符和控制字符。
+const SAMPLE: &str = "Hello, world! 你好，世界 \u{0}\t\r\n<unk> <s>";
+
+/// 解析 tokenizer.model 文件，成功时编码样例文本并解码。
+pub fn tokenizer_model(data: &[u8]) {
+    if let Ok(bpe) = Bpe::try_from_tokenizer_model(data) {
+        round_trip(Tokeneer::new(bpe))
+    }
+}
+
+/// 解析 vocabs.txt 文件，成功时编码样例文本并解码。
+pub fn vocabs_txt(data: &[u8]) {
+    if let Ok(lpe) = Lpe::try_from_vocabs_txt(data) {
+        round_trip(Tokeneer::new(lpe))
+    }
+}
+
+/// 将输入按小端序解释为词序号，以所有不会 panic 的方式解码。
+///
+/// 首字节选择 <unk> 的处理方式和是否转义单字节词，其余字节每 4 个组成一个词序号。
+pub fn decode(data: &[u8]) {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+    let mut tokeneer = Tokeneer::new(Bpe::new(
+        [
+            "<unk>", "<0xE4>", "<0xBD>", "<0xA0>", "a", "b", "ab", "▁", "你",
+        ],
+        [0., 0., 0., 0., 1., 1., 2., 1., 1.],
+        [false, true, true, true, false, false, false, false, false],
+        0,
+    ));
+    tokeneer.set_unk_policy(match flags % 4 {
+        0 => UnkPolicy::Piece,
+        1 => UnkPolicy::Empty,
+        2 => UnkPolicy::Placeholder,
+        _ => UnkPolicy::Error,
+    });
+    tokeneer.set_escape_bytes(flags & 4 != 0);
+    let tokens = data
+        .chunks_exact(size_of::<utok>())
+        .map(|b| utok::from_le_bytes(b.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    // 两种解码方式对不合法的 utf-8 序列处理相同
+    let mut buf = Vec::new();
+    match (
+        tokeneer.try_decode(&tokens),
+        tokeneer.decode_to(&tokens, &mut buf),
+    ) {
+        (Ok(text), Ok(())) => assert_eq!(text.as_bytes(), buf),
+        (Err(_), Err(_)) => {}
+        _ => panic!("decode and decode_to disagree"),
+    }
+    for &t in &tokens {
+        let _ = tokeneer.decode_token(t);
+    }
+}
+
+/// 由输入构造词表并编码其中的文本，任何合法的词表和任何文本都不应使编码 panic。
+///
+/// 首字节选择分词算法、是否定义 <unk> 以及词的评分，其余字节按 utf-8 解码后按行切分，
+/// 无效的字节替换为 U+FFFD。最后一行是待编码的文本，其余各行依次是词表中的词，可以为空或重复。
+pub fn encode(data: &[u8]) {
+    let Some((&flags, data)) = data.split_first() else {
+        return;
+    };
+    let data = String::from_utf8_lossy(data);
+    let mut lines = data.split('\n').collect::<Vec<_>>();
+    let text = lines.pop().unwrap_or_default();
+
+    let bytes = (0..=u8::MAX)
+        .map(|b| format!("<0x{b:02X}>"))
+        .collect::<Vec<_>>();
+    let mut vocabs = Vec::new();
+    let unk = if flags & 1 == 0 {
+        vocabs.push("<unk>");
+        Some(0)
+    } else {
+        // 没有 <unk> 的词表必须定义所有单字节词
+        vocabs.extend(bytes.iter().map(String::as_str));
+        None
+    };
+    let n_bytes = if unk.is_some() { 0 } else { bytes.len() };
+    vocabs.extend(lines);
+    // 评分只取少数几个值，使排名相同的词大量出现
+    let step = (flags >> 2) as usize + 1;
+    let scores = (0..vocabs.len()).map(|i| -((i * step % 7) as f32));
+    let is_byte = (0..vocabs.len()).map(|i| i < n_bytes);
+    if flags & 2 == 0 {
+        encode_all(
+            Tokeneer::new(Bpe::new(vocabs.iter().copied(), scores, is_byte, unk)),
+            text,
+        )
+    } else {
+        encode_all(
+            Tokeneer::new(Lpe::new(vocabs.iter().map(|s| s.as_bytes()), unk)),
+            text,
+        )
+    }
+}
+
+fn encode_all<M: Method>(tokeneer: Tokeneer<M>, text: &str) {
+    let tokens = tokeneer.encode(text);
+    let _ = tokeneer.encode_with_offsets(text);
+    let _ = tokeneer.encode_detailed(text);
+    let _ = tokeneer.encode_with(text, EncodeOptions::default().with_mode(EncodeMode::Exact));
+    let _ = tokeneer.try_decode(&tokens);
+}
+
+fn round_trip<M: Method>(tokeneer: Tokeneer<M>) {
+    let tokens = tokeneer.encode(SAMPLE);
+    let _ = tokeneer.try_decode(&tokens);
+    let _ = tokeneer.decode_to(&tokens, std::io::sink());
+}
+
+#[cfg(test)]
+mod fuzz_tests {
+    use super::*;
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    /// 按 tokenizer.model 的格式编码一组词。
+    fn model(pieces: &[(&str, f32)]) -> Vec<u8> {
+        let mut ans = Vec::new();
+        for (piece, score) in pieces {
+            ans.extend([10, piece.len() as u8 + 7, 10, piece.len() as u8]);
+            ans.extend_from_slice(piece.as_bytes());
+            ans.push(0x15);
+            ans.extend_from_slice(&score.to_le_bytes());
+        }
+        ans
+    }
+
+    #[test]
+    fn test_fuzz_encode() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        // 小字母表使词之间大量重叠，包括多字节字符和替换字符
+        let alphabet = ["a", "b", "ab", "<", "unk>", "你", "\u{FFFD}", "\0", " "];
+        for _ in 0..2000 {
+            let mut data = vec![rng.random()];
+            for _ in 0..rng.random_range(0..12) {
+                for _ in 0..rng.random_range(0..4) {
+                    data.extend_from_slice(
+                        alphabet[rng.random_range(0..alphabet.len())].as_bytes(),
+                    );
+                }
+                data.push(b'\n');
+            }
+            for _ in 0..rng.random_range(0..24) {
+                if rng.random_range(0..8) == 0 {
+                    data.push(rng.random());
+                } else {
+                    data.extend_from_slice(
+                        alphabet[rng.random_range(0..alphabet.len())].as_bytes(),
+                    );
+                }
+            }
+            encode(&data);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_entries() {
+        let valid = model(&[
+            ("<unk>", 0.),
+            ("a", -1.),
+            ("b", -1.),
+            ("ab", -0.5),
+            ("<0x80>", 0.),
+        ]);
+        let bpe = Bpe::try_from_tokenizer_model(&valid).unwrap();
+        assert_eq!(bpe.vocab_size(), 5);
+        assert_eq!(
+            Bpe::try_from_tokenizer_model(&[]).err(),
+            Some(crate::LoadError::Empty)
+        );
+        assert_eq!(
+            Bpe::try_from_tokenizer_model(&valid[..valid.len() - 1]).err(),
+            Some(crate::LoadError::Truncated(valid.len() - 15))
+        );
+        // 词表之后只能是文件末尾或 trainer_spec 等字段，无法解析的内容不会使词表被静默截断
+        let mut with_spec = valid.clone();
+        with_spec.extend([0x12, 2, 0x18, 2]);
+        assert_eq!(
+            Bpe::try_from_tokenizer_model(&with_spec)
+                .unwrap()
+                .vocab_size(),
+            5
+        );
+        let mut garbage = valid.clone();
+        garbage.extend([0xff, 0]);
+        assert_eq!(
+            Bpe::try_from_tokenizer_model(&garbage).err(),
+            Some(crate::LoadError::MalformedRecord(valid.len()))
+        );
+        let mut no_score = model(&[("<unk>", 0.)]);
+        no_score.extend([10, 3, 10, 1, b'a']);
+        assert_eq!(
+            Bpe::try_from_tokenizer_model(&no_score).err(),
+            Some(crate::LoadError::MalformedRecord(14))
+        );
+        assert_eq!(
+            Lpe::try_from_vocabs_txt(b"\"a\"\r\nb\n").err(),
+            Some(crate::LoadError::MalformedLine(1))
+        );
+        assert_eq!(
+            Lpe::try_from_vocabs_txt(b"\"<unk>\"\n\"\"\n\"a\"\n")
+                .unwrap()
+                .vocab_size(),
+            3
+        );
+
+        // 随机变异合法的输入，以及完全随机的输入
+        let txt = b"\"<unk>\"\n\"<0xFF>\"\n\"<0xFF>\"\n\"ab\"\n\"\"\n\"\xE4\"\n";
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..2000 {
+            let mut data = if rng.random() {
+                valid.clone()
+            } else {
+                txt.to_vec()
+            };
+            for _ in 0..rng.random_range(0..4) {
+                let i = rng.random_range(0..data.len());
+                match rng.random_range(0..3) {
+                    0 => data[i] = rng.random(),
+                    1 => data.truncate(i),
+                    _ => data.insert(i, rng.random()),
+                }
+                if data.is_empty() {
+                    break;
+                }
+            }
+            tokenizer_model(&data);
+            vocabs_txt(&data);
+            decode(&data);
+
+            let len = rng.random_range(0..64);
+            let data = (0..len).map(|_| rng.random()).collect::<Vec<u8>>();
+            tokenizer_model(&data);
+            vocabs_txt(&data);
+            decode(&data);
+        }
+    }
+}
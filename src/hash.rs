@@ -0,0 +1,48 @@
+//! 这个模块提供跨平台、跨版本稳定的哈希，用于生成可持久化的缓存键。
+
+use std::hash::Hasher;
+
+/// 64 位 FNV-1a 哈希。
+///
+/// 与 [`std::collections::hash_map::DefaultHasher`] 不同，其结果不随 Rust 版本或进程变化。
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    #[inline]
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // 整数固定按小端序写入，保证结果与平台无关
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as _)
+    }
+}
@@ -1,22 +1,144 @@
 #![deny(warnings)]
+#![cfg_attr(feature = "forbid-unsafe", forbid(unsafe_code))]
 
 mod bpe;
+mod cache;
+pub mod codegen;
+pub mod coverage;
+pub mod dataset;
+mod decoder;
+mod diff;
+mod encoding;
+mod estimate;
+#[doc(hidden)]
+pub mod fuzz;
+mod hash;
 mod lpe;
+mod matcher;
+mod memory;
+mod normalizer;
+mod options;
+mod pack;
+mod postprocessor;
+#[cfg(feature = "presets")]
+pub mod presets;
+mod pretokenizer;
+mod router;
+mod shared;
+#[cfg(feature = "checksum")]
+mod source;
+pub mod testvec;
 mod tokeneer;
+mod trie;
 mod vocab;
 
-pub use bpe::Bpe;
+pub use bpe::{
+    AppliedMerge, Bpe, Derivation, FertilityChange, InaccessibleReason, InaccessibleToken,
+    MergeState, MergeTrace, PendingMerge, PruneOptions, TokenFrequency, UnreachableToken,
+    VocabRemap,
+};
+pub use cache::{CacheStats, CachedTokeneer};
+pub use decoder::DecodePreset;
+pub use diff::{
+    diff_spans, IdChange, RankChange, SpanDiff, SpecialChange, TokenEntry, TokenizerDiff,
+};
+pub use encoding::{Encoding, Truncation, TruncationStrategy};
+pub use estimate::LengthEstimate;
 pub use lpe::Lpe;
-pub use tokeneer::Tokeneer;
+pub use memory::MemoryUsage;
+pub use normalizer::Normalizer;
+pub use options::{EncodeMode, EncodeOptions};
+pub use pack::PackedSequence;
+pub use postprocessor::{PostProcessor, PostProcessorError, TemplatePiece};
+pub use pretokenizer::{CharClass, PreTokenizeStep, PreTokenizer};
+pub use router::{RouteError, Router};
+pub use shared::{ReloadableTokeneer, SharedTokeneer};
+#[cfg(feature = "checksum")]
+pub use source::SourceInfo;
+pub use tokeneer::{
+    DecodeError, InputLimit, InputTooLong, Segment, SpecialTokenError, Split, Tokeneer, UnkPolicy,
+    SPECIAL_CAPTURE,
+};
+pub use trie::{TrieNode, VocabTrie, TRIE_MAGIC};
+pub use vocab::{
+    as_bracket_byte_token, as_byte_level_token, as_byte_token, replace_metaspace, ByteTokenError,
+    CompressStats, LoadError, BYTES, BYTE_ESCAPES,
+};
 
 /// `utok` for token id.
 #[allow(non_camel_case_types)]
 pub type utok = u32;
 
 pub trait Method {
-    fn unk_token(&self) -> utok;
+    /// <unk> 的词序号，没有 <unk> 的词表（例如 tiktoken 风格的词表）返回 `None`。
+    fn unk_token(&self) -> Option<utok>;
     fn vocab_size(&self) -> usize;
+    /// 词表内部的特殊词，[`Tokeneer::new`] 将其注册为特殊词。
+    ///
+    /// 遍历顺序必须是确定的，不能依赖 `HashMap` 等容器的遍历顺序。
     fn internal_special(&self) -> impl IntoIterator<Item = (&str, utok)>;
-    fn encode(&self, text: &str) -> impl IntoIterator<Item = utok> + '_;
+    /// 编码文本，返回的迭代器可以引用文本，以便按需逐词编码。
+    ///
+    /// 对构造成功的词表和任意文本都不应 panic，由 [`fuzz::encode`] 检验。
+    fn encode<'a>(&'a self, text: &'a str) -> impl IntoIterator<Item = utok> + 'a;
     fn decode(&self, token: utok) -> &[u8];
+
+    /// 按选项编码文本，默认忽略选项，与 [`encode`](Self::encode) 相同。
+    fn encode_with<'a>(
+        &'a self,
+        text: &'a str,
+        options: EncodeOptions,
+    ) -> impl IntoIterator<Item = utok> + 'a {
+        let _ = options;
+        self.encode(text)
+    }
+
+    /// 单字节词对应的字节，一般词返回 `None`。
+    fn fallback_byte(&self, _token: utok) -> Option<u8> {
+        None
+    }
+
+    /// 词的合并排名，越小越优先。没有排名的分词算法返回 `None`。
+    fn rank(&self, _token: utok) -> Option<u32> {
+        None
+    }
+
+    /// 分词算法占用的堆内存，不支持统计的分词算法返回全 0。
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::default()
+    }
+
+    /// 找出内容包含 `pattern` 的所有词，按词序号升序排列。
+    fn search(&self, pattern: &[u8]) -> Vec<utok> {
+        let finder = memchr::memmem::Finder::new(pattern);
+        (0..self.vocab_size() as utok)
+            .filter(|&t| finder.find(self.decode(t)).is_some())
+            .collect()
+    }
+
+    /// 找出内容与正则表达式匹配的所有词，按词序号升序排列。
+    #[cfg(feature = "regex")]
+    fn search_regex(&self, regex: &regex::bytes::Regex) -> Vec<utok> {
+        (0..self.vocab_size() as utok)
+            .filter(|&t| regex.is_match(self.decode(t)))
+            .collect()
+    }
+
+    /// 找出所有接在 `partial` 之后仍能保持字节流合法的词，按词序号升序排列。
+    ///
+    /// `partial` 是字节流末尾未完成的 utf-8 字符，可以为空。
+    /// 拼接后的字节流必须是合法的 utf-8，或者只在末尾留下一个未完成的字符。
+    fn valid_continuations(&self, partial: &[u8]) -> Vec<utok> {
+        let mut buf = partial.to_vec();
+        (0..self.vocab_size() as utok)
+            .filter(|&t| {
+                buf.truncate(partial.len());
+                buf.extend_from_slice(self.decode(t));
+                match std::str::from_utf8(&buf) {
+                    Ok(_) => true,
+                    Err(e) => e.error_len().is_none(),
+                }
+            })
+            .collect()
+    }
 }
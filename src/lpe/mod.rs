@@ -1,61 +1,185 @@
-﻿//! l-p-e for Longest Prefix Encoding
+//! l-p-e for Longest Prefix Encoding
 
 use crate::{
     utok,
-    vocab::{CollectedVocab, CompressedVocab},
-    Method,
+    vocab::{
+        as_byte_token, as_str, offset, ByteTokenError, CollectedVocab, CompressStats,
+        CompressedVocab, LoadError, Offset, ReverseMap, ReverseTable,
+    },
+    EncodeMode, EncodeOptions, MemoryUsage, Method,
 };
 use patricia_tree::PatriciaMap;
-use std::{collections::HashSet, pin::Pin};
+use std::{borrow::Cow, collections::HashSet, iter::zip, ops::Range, pin::Pin};
 
 pub struct Lpe {
     /// 保存所有词的字符串内容，以 u8 为单位所以不需要对齐，占用空间少
     vocabs: Pin<Box<[u8]>>,
     /// 按 token 顺序保存元信息
-    tokens: Box<[(u32, u32)]>,
+    tokens: Box<[(Offset, Offset)]>,
     /// 词汇的前缀树
     trie: PatriciaMap<utok>,
     /// 用于索引单字节 token，因此不需要其他元信息
     bytes: Box<[utok; 256]>,
-    /// token: <unk>
-    unk: utok,
+    /// 按特殊词模式识别出的词
+    special: Box<[utok]>,
+    /// 从词的内容反查词序号，首次使用时构造
+    reverse: ReverseMap,
+    /// 单字节词的定义问题
+    byte_error: Option<ByteTokenError>,
+    /// 词表压缩的统计信息
+    stats: CompressStats,
+    /// 前缀树的键和查找的文本都折叠为小写
+    fold_case: bool,
+    /// 没有定义的词序号，升序排列
+    reserved: Box<[utok]>,
+    /// token: <unk>，没有 <unk> 的词表由单字节词保证任何文本都能编码
+    unk: Option<utok>,
 }
 
 impl Lpe {
+    /// 解析每行一个带引号的词的 vocabs.txt 文件并构造分词器。
+    ///
+    /// 文件格式错误时 panic，需要处理错误时使用 [`try_from_vocabs_txt`](Self::try_from_vocabs_txt)。
     pub fn from_vocabs_txt(txt: &[u8]) -> Self {
         Self::new(
-            unsafe { std::str::from_utf8_unchecked(txt) }
-                .lines()
-                .map(|line| {
-                    line.strip_prefix('"')
-                        .unwrap()
-                        .strip_suffix('"')
-                        .unwrap()
-                        .as_bytes()
-                }),
-            0,
+            parse_vocabs_txt(txt).unwrap_or_else(|e| panic!("{e}")),
+            Some(0),
         )
     }
 
-    pub fn new<'a>(vocabs: impl IntoIterator<Item = &'a [u8]>, unk: utok) -> Self {
+    /// 解析 vocabs.txt 文件并构造分词器，文件格式错误或不含任何词时返回错误。
+    ///
+    /// 任意输入都不会 panic，可以用于加载不可信的文件。
+    pub fn try_from_vocabs_txt(txt: &[u8]) -> Result<Self, LoadError> {
+        match parse_vocabs_txt(txt)? {
+            pieces if pieces.is_empty() => Err(LoadError::Empty),
+            pieces => Ok(Self::new(pieces, Some(0))),
+        }
+    }
+
+    /// 构造分词器，`unk` 为 `None` 时词表必须定义所有 256 个单字节词，否则 panic。
+    pub fn new<'a>(
+        vocabs: impl IntoIterator<Item = &'a [u8]>,
+        unk: impl Into<Option<utok>>,
+    ) -> Self {
+        Self::build(vocabs, unk.into(), false, as_byte_token, Cow::Borrowed)
+    }
+
+    /// 构造分词器，由 `as_byte` 识别词表中的单字节词，用于单字节词不是 `<0xAB>` 形式的词表。
+    ///
+    /// 常用的识别函数有 [`as_byte_token`](crate::as_byte_token)、
+    /// [`as_bracket_byte_token`](crate::as_bracket_byte_token) 和
+    /// [`as_byte_level_token`](crate::as_byte_level_token)。
+    pub fn new_with_byte_token<'a>(
+        vocabs: impl IntoIterator<Item = &'a [u8]>,
+        unk: impl Into<Option<utok>>,
+        as_byte: impl Fn(&[u8]) -> Option<u8>,
+    ) -> Self {
+        Self::build(vocabs, unk.into(), false, as_byte, Cow::Borrowed)
+    }
+
+    /// 构造分词器，由 `as_byte` 识别词表中的单字节词，其他词由 `transform` 转换为解码后的形式再保存，
+    /// 例如 [`replace_metaspace`](crate::replace_metaspace)。
+    ///
+    /// 转换后的词直接用于匹配文本和解码，因此解码不需要再做后处理。
+    pub fn new_with_transform<'a>(
+        vocabs: impl IntoIterator<Item = &'a [u8]>,
+        unk: impl Into<Option<utok>>,
+        as_byte: impl Fn(&[u8]) -> Option<u8>,
+        transform: impl Fn(&'a [u8]) -> Cow<'a, [u8]>,
+    ) -> Self {
+        Self::build(vocabs, unk.into(), false, as_byte, transform)
+    }
+
+    /// 构造大小写不敏感的分词器，例如 `Apple` 和 `apple` 编码为同一个词。
+    ///
+    /// 只折叠 ascii 字母的大小写，因此文本的字节位置不变；解码仍得到词表中的原始内容。
+    /// 多个词折叠后相同时，保留序号最小的词。
+    pub fn new_case_insensitive<'a>(
+        vocabs: impl IntoIterator<Item = &'a [u8]>,
+        unk: impl Into<Option<utok>>,
+    ) -> Self {
+        Self::build(vocabs, unk.into(), true, as_byte_token, Cow::Borrowed)
+    }
+
+    /// 由 `(词序号, 词)` 构造分词器，词序号可以不连续，用于预留了空缺词序号的词表。
+    ///
+    /// 词表按最大的词序号稠密存储，空缺的词序号保留给将来的词：内容为空，编码不会产生，
+    /// 可以由 [`reserved`](Self::reserved) 查询。同一个词序号定义多次时 panic。
+    pub fn from_id_pieces<'a>(
+        pairs: impl IntoIterator<Item = (utok, &'a [u8])>,
+        unk: impl Into<Option<utok>>,
+    ) -> Self {
+        let mut pieces = Vec::new();
+        let mut defined = Vec::new();
+        for (id, piece) in pairs {
+            let i = id as usize;
+            if i >= pieces.len() {
+                pieces.resize(i + 1, &[][..]);
+                defined.resize(i + 1, false);
+            }
+            assert!(
+                !std::mem::replace(&mut defined[i], true),
+                "token id {id} is defined more than once"
+            );
+            pieces[i] = piece;
+        }
+        let mut ans = Self::new(pieces, unk);
+        ans.reserved = (0..defined.len() as utok)
+            .filter(|&t| !defined[t as usize])
+            .collect();
+        ans
+    }
+
+    /// 构造时没有定义的词序号，升序排列。
+    #[inline]
+    pub fn reserved(&self) -> &[utok] {
+        &self.reserved
+    }
+
+    fn build<'a>(
+        vocabs: impl IntoIterator<Item = &'a [u8]>,
+        unk: Option<utok>,
+        fold_case: bool,
+        as_byte: impl Fn(&[u8]) -> Option<u8>,
+        transform: impl Fn(&'a [u8]) -> Cow<'a, [u8]>,
+    ) -> Self {
         let CollectedVocab {
             vocabs,
             total_len,
             bytes,
-        } = CollectedVocab::collect(vocabs, unk);
-        let CompressedVocab { vocabs, slices } = CompressedVocab::new(&vocabs, total_len);
+            byte_error,
+        } = CollectedVocab::collect_with(vocabs, unk, as_byte, transform);
+        let CompressedVocab {
+            vocabs,
+            slices,
+            stats,
+        } = CompressedVocab::new(&vocabs, total_len);
         let tokens = slices
             .into_iter()
-            .map(|(off, len)| (off as u32, len as u32))
+            .map(|(off, len)| (offset(off), offset(len)))
             .collect::<Box<_>>();
 
-        let bytes_set = bytes.iter().chain(&[unk]).cloned().collect::<HashSet<_>>();
-        let trie = tokens
+        let bytes_set = bytes.iter().chain(&unk).cloned().collect::<HashSet<_>>();
+        let pieces = tokens
             .iter()
             .enumerate()
             .filter(|&(i, _)| !bytes_set.contains(&(i as utok)))
             .map(|(i, &(off, len))| (&vocabs[off as usize..][..len as usize], i as utok))
-            .collect();
+            // 空词不消耗任何文本，不能参与匹配
+            .filter(|(piece, _)| !piece.is_empty());
+        let trie = if fold_case {
+            let mut trie = PatriciaMap::new();
+            for (piece, i) in pieces {
+                let key = piece.to_ascii_lowercase();
+                if !trie.contains_key(&key) {
+                    trie.insert(key, i);
+                }
+            }
+            trie
+        } else {
+            pieces.collect()
+        };
 
         // println!(
         //     "Building LPE vocab, detected {} tokens, compressed to {} bytes from {total_len} bytes",
@@ -63,15 +187,236 @@ impl Lpe {
         //     vocabs.len(),
         // );
 
-        Self {
+        let mut ans = Self {
             vocabs,
             tokens,
             trie,
             bytes,
+            special: Box::new([]),
+            reverse: ReverseMap::default(),
+            byte_error,
+            stats,
+            fold_case,
+            reserved: Box::new([]),
             unk,
+        };
+        ans.detect_special_by(is_control_shape);
+        ans
+    }
+
+    /// 将词表中形如 `<|im_start|>` 或 `<s>` 的词识别为特殊词，替换之前识别的结果。
+    ///
+    /// 构造时只识别 `<|im_start|>` 形式的词，`<s>` 形式可能与 `<div>` 等普通词冲突，需要时显式调用；
+    /// <unk> 和单字节词不会被识别为特殊词。
+    pub fn detect_default_specials(&mut self) {
+        self.detect_special_by(is_special_shape)
+    }
+
+    /// 清除识别的特殊词，词表中的所有词都作为普通词编码。
+    ///
+    /// 用于不希望构造时识别的 `<|im_start|>` 形式的词注册为特殊词的场景。
+    pub fn clear_specials(&mut self) {
+        self.special = Box::new([]);
+    }
+
+    /// 将词表中与模式匹配的词识别为特殊词，替换之前识别的结果。
+    ///
+    /// <unk> 和单字节词不会被识别为特殊词。
+    #[cfg(feature = "regex")]
+    pub fn detect_special(&mut self, pattern: &regex::Regex) {
+        self.detect_special_by(|piece| pattern.is_match(piece))
+    }
+
+    /// 弃用词表中的词：编码不再产生这些词，但仍可以解码，词序号保持不变。
+    ///
+    /// 用于保留模型的嵌入行而停止产生某些词，例如词表中意外包含的敏感字符串。
+    /// 弃用的单字节词回退为 <unk>；没有 <unk> 时单字节词仍作为回退保留。
+    pub fn deprecate(&mut self, tokens: impl IntoIterator<Item = utok>) {
+        let tokens = tokens.into_iter().collect::<HashSet<_>>();
+        for &t in &tokens {
+            let key = if self.fold_case {
+                Cow::Owned(self.token(t).to_ascii_lowercase())
+            } else {
+                Cow::Borrowed(self.token(t))
+            };
+            if self.trie.get(&*key) == Some(&t) {
+                let key = key.into_owned();
+                self.trie.remove(key);
+            }
+        }
+        if let Some(unk) = self.unk {
+            for b in self.bytes.iter_mut() {
+                if tokens.contains(b) {
+                    *b = unk
+                }
+            }
+        }
+        self.special = self
+            .special
+            .iter()
+            .copied()
+            .filter(|t| !tokens.contains(t))
+            .collect();
+        self.reverse = ReverseMap::default();
+    }
+
+    /// 将词表中满足条件的词识别为特殊词，替换之前识别的结果。
+    ///
+    /// <unk> 和单字节词不会被识别为特殊词。
+    pub fn detect_special_by(&mut self, mut f: impl FnMut(&str) -> bool) {
+        self.special = self
+            .trie
+            .values()
+            .copied()
+            .filter(|&t| std::str::from_utf8(self.token(t)).is_ok_and(&mut f))
+            .collect();
+        self.special.sort_unstable();
+    }
+
+    /// 查找 `text` 的最长前缀词，返回词序号和前缀的字节数。
+    ///
+    /// <unk> 和单字节词不在前缀树中，不会被查找到。
+    #[inline]
+    pub fn longest_prefix(&self, text: &[u8]) -> Option<(utok, usize)> {
+        self.find_prefix(&self.fold(text))
+    }
+
+    /// 从短到长列出所有是 `text` 前缀的词，返回词序号和前缀的字节数。
+    pub fn prefixes<'a>(&'a self, text: &'a [u8]) -> impl Iterator<Item = (utok, usize)> + 'a {
+        let text = self.fold(text);
+        self.trie
+            .common_prefixes(&text)
+            .map(|(pre, &tok)| (tok, pre.len()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// 按字典序列出所有以 `prefix` 开头的词。
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a [u8]) -> impl Iterator<Item = utok> + 'a {
+        let prefix = self.fold(prefix);
+        self.trie
+            .iter_prefix(&prefix)
+            .map(|(_, &tok)| tok)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// 在前缀树中查找已经折叠过的文本的最长前缀词。
+    #[inline]
+    fn find_prefix(&self, key: &[u8]) -> Option<(utok, usize)> {
+        self.trie
+            .get_longest_common_prefix(key)
+            .map(|(pre, &tok)| (tok, pre.len()))
+    }
+
+    /// 大小写不敏感时将文本折叠为小写，否则不做处理。
+    #[inline]
+    fn fold<'a>(&self, text: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.fold_case {
+            Cow::Owned(text.to_ascii_lowercase())
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+
+    /// 以动态规划求词数最少的切分，词数相同时优先选择较长的第一个词。
+    fn encode_exact(&self, text: &str) -> Vec<utok> {
+        let bytes = text.as_bytes();
+        let key = self.fold(bytes);
+        // best[i]：从位置 i 到结尾的最少词数及第一个词
+        let mut best = vec![(0usize, 0 as utok, 0usize); bytes.len() + 1];
+        for i in (0..bytes.len()).rev() {
+            let mut choice = (best[i + 1].0 + 1, self.bytes[bytes[i] as usize], 1);
+            for (pre, &tok) in self.trie.common_prefixes(&key[i..]) {
+                let len = pre.len();
+                if best[i + len].0 < choice.0 {
+                    choice = (best[i + len].0 + 1, tok, len)
+                }
+            }
+            best[i] = choice
+        }
+        let mut tokens = Vec::with_capacity(best[0].0);
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let (_, tok, len) = best[pos];
+            tokens.push(tok);
+            pos += len
+        }
+        tokens
+    }
+
+    /// 编码文本，同时报告回退到单字节词的字节范围，相邻的范围会被合并。
+    ///
+    /// 用于发现词表在新领域文本上的覆盖缺口。
+    pub fn encode_with_fallback(&self, text: &str) -> (Vec<utok>, Vec<Range<usize>>) {
+        let bytes = text.as_bytes();
+        let key = self.fold(bytes);
+        let mut tokens = Vec::new();
+        let mut fallback = Vec::<Range<usize>>::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            match self.find_prefix(&key[pos..]) {
+                Some((tok, len)) => {
+                    tokens.push(tok);
+                    pos += len;
+                }
+                None => {
+                    tokens.push(self.bytes[bytes[pos] as usize]);
+                    match fallback.last_mut() {
+                        Some(last) if last.end == pos => last.end += 1,
+                        _ => fallback.push(pos..pos + 1),
+                    }
+                    pos += 1;
+                }
+            }
+        }
+        (tokens, fallback)
+    }
+
+    /// 查找内容为 `piece` 的词。
+    ///
+    /// 多个词内容相同时，优先返回编码时使用的词，其次返回序号最小的词。
+    /// 首次调用时构造反查表，此后每次查找的复杂度为 O(1)。
+    #[inline]
+    pub fn token_to_id(&self, piece: &str) -> Option<utok> {
+        self.reverse_map().get(piece.as_bytes()).copied()
+    }
+
+    /// 预先构造 [`token_to_id`](Self::token_to_id) 使用的反查表，避免首次查找时的延迟。
+    #[inline]
+    pub fn warm_token_to_id(&self) {
+        self.reverse_map();
+    }
+
+    fn reverse_map(&self) -> &ReverseTable<'_> {
+        let init = || {
+            (0..self.tokens.len() as utok)
+                .rev()
+                .chain(self.trie.values().copied())
+                .map(|t| (self.token(t), t))
+        };
+        // SAFETY: 词的内容保存在固定位置的 vocabs 中，与 self 的生命周期相同。
+        #[cfg(not(feature = "forbid-unsafe"))]
+        unsafe {
+            self.reverse.get_or_init(init)
+        }
+        #[cfg(feature = "forbid-unsafe")]
+        {
+            self.reverse.get_or_init(init)
         }
     }
 
+    /// 检查构造时词表中单字节词的定义，报告重复定义的字节和部分定义时缺失的字节。
+    pub fn check_byte_tokens(&self) -> Result<(), ByteTokenError> {
+        self.byte_error.clone().map_or(Ok(()), Err)
+    }
+
+    /// 构造时词表压缩的统计信息。
+    #[inline]
+    pub fn compress_stats(&self) -> CompressStats {
+        self.stats
+    }
+
     /// token id -> token meta
     #[inline(always)]
     fn token(&self, token: utok) -> &[u8] {
@@ -80,9 +425,48 @@ impl Lpe {
     }
 }
 
+/// 解析 vocabs.txt 文件，每行是一个用双引号包围的词，行尾可以是 `\n` 或 `\r\n`。
+fn parse_vocabs_txt(txt: &[u8]) -> Result<Vec<&[u8]>, LoadError> {
+    let txt = txt.strip_suffix(b"\n").unwrap_or(txt);
+    if txt.is_empty() {
+        return Ok(Vec::new());
+    }
+    txt.split(|&b| b == b'\n')
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            line.strip_prefix(b"\"")
+                .and_then(|line| line.strip_suffix(b"\""))
+                .ok_or(LoadError::MalformedLine(i))
+        })
+        .collect()
+}
+
+/// 构造时识别的特殊词形状，匹配形如 `<|im_start|>` 的词。
+fn is_control_shape(piece: &str) -> bool {
+    piece
+        .strip_prefix("<|")
+        .and_then(|s| s.strip_suffix("|>"))
+        .is_some_and(|inner| !inner.is_empty() && !inner.contains('|'))
+}
+
+/// 默认的特殊词形状，匹配形如 `<|im_start|>` 或 `<s>` 的词。
+fn is_special_shape(piece: &str) -> bool {
+    match piece.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+        Some(inner) => match inner.strip_prefix('|').and_then(|s| s.strip_suffix('|')) {
+            Some(_) => is_control_shape(piece),
+            None => {
+                !inner.is_empty()
+                    && !inner.contains(|c: char| c == '<' || c == '>' || c.is_whitespace())
+            }
+        },
+        None => false,
+    }
+}
+
 impl Method for Lpe {
     #[inline]
-    fn unk_token(&self) -> utok {
+    fn unk_token(&self) -> Option<utok> {
         self.unk
     }
     #[inline]
@@ -91,26 +475,252 @@ impl Method for Lpe {
     }
     #[inline]
     fn internal_special(&self) -> impl IntoIterator<Item = (&str, utok)> {
-        []
+        self.special.iter().map(|&t| (as_str(self.token(t)), t))
     }
     #[inline]
-    fn encode(&self, text: &str) -> impl IntoIterator<Item = utok> + '_ {
-        let mut text = text.as_bytes();
-        let mut tokens = Vec::<utok>::new();
-
-        while !text.is_empty() {
-            let (tok, len) = match self.trie.get_longest_common_prefix(text) {
-                Some((pre, tok)) => (*tok, pre.len()),
-                None => (self.bytes[text[0] as usize], 1),
-            };
-            tokens.push(tok);
-            text = &text[len..];
-        }
-
-        tokens
+    fn encode<'a>(&'a self, text: &'a str) -> impl IntoIterator<Item = utok> + 'a {
+        // 最长前缀匹配只依赖已处理的位置，因此按需逐词编码
+        let text = text.as_bytes();
+        let key = self.fold(text);
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            let &b = text.get(pos)?;
+            let (tok, len) = self
+                .find_prefix(&key[pos..])
+                .unwrap_or((self.bytes[b as usize], 1));
+            pos += len;
+            Some(tok)
+        })
     }
     #[inline]
     fn decode(&self, token: utok) -> &[u8] {
         self.token(token)
     }
+    fn encode_with<'a>(
+        &'a self,
+        text: &'a str,
+        options: EncodeOptions,
+    ) -> impl IntoIterator<Item = utok> + 'a {
+        match options.mode() {
+            EncodeMode::Greedy => self
+                .encode(text)
+                .into_iter()
+                .take_while(|_| !options.is_cancelled())
+                .collect(),
+            EncodeMode::Exact => self.encode_exact(text),
+        }
+    }
+    #[inline]
+    fn fallback_byte(&self, token: utok) -> Option<u8> {
+        match *self.token(token) {
+            [b] if Some(token) != self.unk && self.bytes[b as usize] == token => Some(b),
+            _ => None,
+        }
+    }
+    fn memory_usage(&self) -> MemoryUsage {
+        // 前缀树按字典序遍历，每个词只有与前一个词不同的部分存为新的标签；
+        // 节点数按每个词一个值节点和至多一个分支节点估算
+        const NODE: usize = 2 + size_of::<utok>() + 2 * size_of::<usize>();
+        let mut last = Vec::new();
+        let mut labels = 0;
+        for key in self.trie.keys() {
+            let common = zip(&last, &key).take_while(|(a, b)| a == b).count();
+            labels += key.len() - common;
+            last = key;
+        }
+        MemoryUsage {
+            vocab: self.vocabs.len(),
+            metadata: size_of_val(&*self.tokens)
+                + size_of_val(&*self.bytes)
+                + size_of_val(&*self.reserved),
+            index: labels + self.trie.len() * 2 * NODE,
+            special: size_of_val(&*self.special),
+            cache: self.reverse.memory_size(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod lpe_tests {
+    use super::*;
+
+    fn test_lpe() -> Lpe {
+        Lpe::new(
+            [
+                "<unk>",
+                "<0x41>",
+                "<s>",
+                "<|im_start|>",
+                "a",
+                "ab",
+                "<",
+                "|",
+            ]
+            .map(str::as_bytes),
+            0,
+        )
+    }
+
+    #[test]
+    fn test_lpe_internal_special() {
+        let mut lpe = test_lpe();
+        // 构造时只识别 <|...|> 形式
+        let special = lpe.internal_special().into_iter().collect::<Vec<_>>();
+        assert_eq!(special, [("<|im_start|>", 3)]);
+        lpe.detect_default_specials();
+        let special = lpe.internal_special().into_iter().collect::<Vec<_>>();
+        assert_eq!(special, [("<s>", 2), ("<|im_start|>", 3)]);
+        lpe.clear_specials();
+        assert_eq!(lpe.internal_special().into_iter().count(), 0);
+
+        // 构造时识别的特殊词由 Tokeneer::new 注册
+        let tokeneer = crate::Tokeneer::new(Lpe::new(
+            ["<unk>", "<|im_start|>", "<", "|", "a"].map(str::as_bytes),
+            0,
+        ));
+        assert_eq!(tokeneer.special_id("<|im_start|>"), Some(&[1][..]));
+        assert_eq!(tokeneer.encode("<|im_start|>a"), [1, 4]);
+        let lpe = Lpe::new(["<unk>", "<div>", "<", ">", "d"].map(str::as_bytes), 0);
+        assert_eq!(lpe.internal_special().into_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_lpe_prefix() {
+        let lpe = test_lpe();
+        assert_eq!(lpe.longest_prefix(b"abc"), Some((5, 2)));
+        assert_eq!(lpe.longest_prefix(b"c"), None);
+        assert_eq!(lpe.prefixes(b"abc").collect::<Vec<_>>(), [(4, 1), (5, 2)]);
+        assert_eq!(lpe.iter_prefix(b"<").collect::<Vec<_>>(), [6, 2, 3]);
+    }
+
+    #[test]
+    fn test_lpe_encode_with_fallback() {
+        let lpe = test_lpe();
+        let (tokens, fallback) = lpe.encode_with_fallback("abcc|Aa");
+        assert_eq!(tokens, [5, 0, 0, 7, 1, 4]);
+        assert_eq!(fallback, [2..4, 5..6]);
+    }
+
+    #[test]
+    fn test_lpe_byte_token() {
+        let lpe = Lpe::new_with_byte_token(
+            ["<unk>", "[0xE4]", "[0xB8]", "[0x80]", "a"].map(str::as_bytes),
+            0,
+            crate::as_bracket_byte_token,
+        );
+        assert_eq!(
+            lpe.encode("一a").into_iter().collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(lpe.decode(1), [0xe4]);
+        assert_eq!(lpe.fallback_byte(3), Some(0x80));
+    }
+
+    #[test]
+    fn test_lpe_transform() {
+        let lpe = Lpe::new_with_transform(
+            ["<unk>", "▁a", "b", "##c"].map(str::as_bytes),
+            0,
+            crate::as_byte_token,
+            |piece| match piece.strip_prefix(b"##") {
+                Some(piece) => Cow::Borrowed(piece),
+                None => crate::replace_metaspace(piece),
+            },
+        );
+        assert_eq!(
+            lpe.encode(" abc").into_iter().collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(lpe.decode(1), b" a");
+        assert_eq!(lpe.decode(3), b"c");
+    }
+
+    #[test]
+    fn test_lpe_deprecate() {
+        let mut lpe = Lpe::new(["<unk>", "a", "b", "ab", "<0x61>"].map(str::as_bytes), 0);
+        lpe.deprecate([3, 1]);
+        assert_eq!(lpe.encode("ab").into_iter().collect::<Vec<_>>(), [4, 2]);
+        assert_eq!(lpe.decode(3), b"ab");
+        lpe.deprecate([4]);
+        assert_eq!(lpe.encode("ab").into_iter().collect::<Vec<_>>(), [0, 2]);
+    }
+
+    #[test]
+    fn test_lpe_from_id_pieces() {
+        let lpe = Lpe::from_id_pieces(
+            [(0, "<unk>"), (5, "ab"), (2, "a"), (3, "b")].map(|(t, s)| (t, s.as_bytes())),
+            0,
+        );
+        assert_eq!(lpe.vocab_size(), 6);
+        assert_eq!(lpe.reserved(), [1, 4]);
+        assert_eq!(
+            lpe.encode("abba").into_iter().collect::<Vec<_>>(),
+            [5, 3, 2]
+        );
+        assert_eq!(lpe.decode(5), b"ab");
+        assert_eq!(lpe.decode(4), b"");
+        assert!(Lpe::new(["<unk>"].map(str::as_bytes), 0)
+            .reserved()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_lpe_token_to_id() {
+        let lpe = test_lpe();
+        assert_eq!(lpe.token_to_id("ab"), Some(5));
+        assert_eq!(lpe.token_to_id("A"), Some(1));
+        assert_eq!(lpe.token_to_id("<unk>"), Some(0));
+        assert_eq!(lpe.token_to_id("b"), None);
+    }
+
+    #[test]
+    fn test_lpe_case_insensitive() {
+        let lpe = Lpe::new_case_insensitive(
+            ["<unk>", "Apple", "apple", "pie", " ", "<0x50>"].map(str::as_bytes),
+            0,
+        );
+        let tokens = lpe.encode("APPLE Pie P").into_iter().collect::<Vec<_>>();
+        assert_eq!(tokens, [1, 4, 3, 4, 5]);
+        assert_eq!(lpe.decode(1), b"Apple");
+        assert_eq!(lpe.longest_prefix(b"aPPle"), Some((1, 5)));
+        assert_eq!(lpe.encode_with_fallback("PIEP").0, [3, 5]);
+
+        let lpe = test_lpe();
+        assert_eq!(lpe.longest_prefix(b"AB"), None);
+    }
+
+    #[test]
+    fn test_lpe_encode_exact() {
+        let lpe = Lpe::new(
+            ["<unk>", "a", "b", "c", "d", "e", "abc", "bcde"].map(str::as_bytes),
+            0,
+        );
+        let encode = |mode| {
+            lpe.encode_with("abcdex", EncodeOptions::default().with_mode(mode))
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(encode(EncodeMode::Greedy), [6, 4, 5, 0]);
+        assert_eq!(encode(EncodeMode::Exact), [1, 7, 0]);
+    }
+
+    #[test]
+    fn test_lpe_search() {
+        let lpe = test_lpe();
+        assert_eq!(lpe.search(b"|"), [3, 7]);
+        #[cfg(feature = "regex")]
+        {
+            let regex = regex::bytes::Regex::new("^a").unwrap();
+            assert_eq!(lpe.search_regex(&regex), [4, 5]);
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_lpe_detect_special() {
+        let mut lpe = test_lpe();
+        lpe.detect_special(&regex::Regex::new(r"^<\|.+\|>$").unwrap());
+        let special = lpe.internal_special().into_iter().collect::<Vec<_>>();
+        assert_eq!(special, [("<|im_start|>", 3)]);
+    }
 }
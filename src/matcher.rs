@@ -0,0 +1,167 @@
+//! 这个模块提供在文本中查找特殊词的匹配器。
+//!
+//! 默认使用 `regex` 构造多选一的正则表达式；关闭 `regex` 特性时使用基于 memchr 的多模式字面量匹配。
+//! 两种实现的匹配结果相同：选择最靠左的匹配位置，同一位置有多个特殊词匹配时选择最长的一个，
+//! 与特殊词注册的顺序无关。
+
+use patricia_tree::PatriciaSet;
+use std::ops::Range;
+
+/// 默认使用正则表达式匹配的特殊词数量上限，超过时改用前缀树。
+pub(crate) const REGEX_LIMIT: usize = 64;
+
+#[derive(Clone)]
+pub(crate) enum SpecialMatcher {
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+    Literal(Literal),
+}
+
+impl SpecialMatcher {
+    /// 特殊词不超过 `regex_limit` 个时构造正则表达式，否则构造前缀树；关闭 `regex` 特性时总是使用前缀树。
+    ///
+    /// 空的特殊词不参与匹配，没有任何非空的特殊词时返回 `None`。
+    pub fn new<'a>(
+        patterns: impl IntoIterator<Item = &'a String>,
+        regex_limit: usize,
+    ) -> Option<Self> {
+        let patterns = patterns
+            .into_iter()
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<_>>();
+        if patterns.is_empty() {
+            return None;
+        }
+        #[cfg(feature = "regex")]
+        if patterns.len() <= regex_limit {
+            return Some(Self::Regex(build_pattern(patterns)));
+        }
+        #[cfg(not(feature = "regex"))]
+        let _ = regex_limit;
+        Some(Self::Literal(Literal::new(patterns)))
+    }
+
+    /// 从 `start` 开始查找第一个特殊词。
+    #[inline]
+    pub fn find_at(&self, text: &str, start: usize) -> Option<Range<usize>> {
+        match self {
+            #[cfg(feature = "regex")]
+            Self::Regex(regex) => regex.find_at(text, start).map(|m| m.range()),
+            Self::Literal(literal) => literal.find_at(text.as_bytes(), start),
+        }
+    }
+}
+
+/// 构造按字面匹配所有特殊词的正则表达式，特殊词中的元字符全部转义。
+#[cfg(feature = "regex")]
+fn build_pattern(mut text: Vec<&String>) -> regex::Regex {
+    // 正则表达式的多选一按顺序优先，将长的特殊词排在前面以实现最长匹配
+    text.sort_unstable_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    let pattern = text
+        .into_iter()
+        .map(|p| regex::escape(p))
+        .collect::<Vec<_>>()
+        .join("|");
+    regex::Regex::new(&pattern).unwrap()
+}
+
+/// 多模式字面量匹配器，以前缀树查找最长的特殊词，特殊词数量很多时仍然高效。
+#[derive(Clone)]
+pub(crate) struct Literal {
+    /// 出现在特殊词开头的字节
+    first: Vec<u8>,
+    /// 每个字节是否出现在特殊词开头
+    starts: Box<[bool; 256]>,
+    /// 所有特殊词的前缀树
+    trie: PatriciaSet,
+}
+
+impl Literal {
+    fn new<'a>(text: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut starts = Box::new([false; 256]);
+        let mut trie = PatriciaSet::new();
+        for p in text {
+            if let Some(&b) = p.as_bytes().first() {
+                starts[b as usize] = true;
+                trie.insert(p.as_bytes());
+            }
+        }
+        let first = (0..=255u8).filter(|&b| starts[b as usize]).collect();
+        Self {
+            first,
+            starts,
+            trie,
+        }
+    }
+
+    fn find_at(&self, text: &[u8], start: usize) -> Option<Range<usize>> {
+        let mut i = start;
+        while i < text.len() {
+            // 跳到下一个可能匹配的位置
+            let haystack = &text[i..];
+            i += match *self.first {
+                [] => return None,
+                [a] => memchr::memchr(a, haystack),
+                [a, b] => memchr::memchr2(a, b, haystack),
+                [a, b, c] => memchr::memchr3(a, b, c, haystack),
+                _ => haystack.iter().position(|b| self.starts[*b as usize]),
+            }?;
+            // 特殊词都是合法的 utf-8，因此匹配位置必然在字符边界上
+            if let Some(p) = self.trie.get_longest_common_prefix(&text[i..]) {
+                return Some(i..i + p.len());
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod matcher_tests {
+    use super::*;
+
+    #[test]
+    fn test_matcher_backends() {
+        let patterns = ["<s>", "<s></s>", "</s>", "<|im_start|>", "ab"]
+            .map(String::from)
+            .to_vec();
+        let text = "a<s></s>ab<|im_start|></s";
+        let find_all = |matcher: &SpecialMatcher| {
+            let mut ans = Vec::new();
+            let mut pos = 0;
+            while let Some(m) = matcher.find_at(text, pos) {
+                pos = m.end;
+                ans.push(m)
+            }
+            ans
+        };
+        let literal = SpecialMatcher::new(&patterns, 0).unwrap();
+        assert!(matches!(literal, SpecialMatcher::Literal(_)));
+        assert_eq!(find_all(&literal), [1..8, 8..10, 10..22]);
+        assert_eq!(
+            find_all(&SpecialMatcher::new(&patterns, REGEX_LIMIT).unwrap()),
+            find_all(&literal)
+        );
+        assert!(SpecialMatcher::new(&[], 0).is_none());
+        assert!(SpecialMatcher::new(&[String::new()], REGEX_LIMIT).is_none());
+    }
+
+    #[test]
+    fn test_matcher_metachar() {
+        // 正则表达式中有特殊含义的字符都按字面匹配，空的特殊词被忽略
+        let patterns = ["#", "a-b", "[x]", "{1}", "&&", "~", "\\d", ""]
+            .map(String::from)
+            .to_vec();
+        let text = "a#a-b[x]x{1}&&~\\d1";
+        for limit in [0, REGEX_LIMIT] {
+            let matcher = SpecialMatcher::new(&patterns, limit).unwrap();
+            let mut found = Vec::new();
+            let mut pos = 0;
+            while let Some(m) = matcher.find_at(text, pos) {
+                pos = m.end;
+                found.push(&text[m])
+            }
+            assert_eq!(found, ["#", "a-b", "[x]", "{1}", "&&", "~", "\\d"]);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! 这个模块统计分词器占用的内存。
+
+use std::{collections::HashMap, ops::Add};
+
+/// 分词器占用的堆内存，按用途分类，单位为字节。
+///
+/// 统计按容器的容量和元素大小估算，不包括分配器的额外开销；
+/// 正则表达式等第三方结构的内部状态无法统计，不计入。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct MemoryUsage {
+    /// 词的内容
+    pub vocab: usize,
+    /// 每个词的元信息，例如内容的位置、长度和合并排名
+    pub metadata: usize,
+    /// 从文本查找词的索引，例如排序的词序号、前缀哈希和前缀树的节点
+    pub index: usize,
+    /// 特殊词表
+    pub special: usize,
+    /// 首次使用时构造的缓存，例如从内容反查词序号的哈希表
+    pub cache: usize,
+}
+
+impl MemoryUsage {
+    /// 总字节数。
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.vocab + self.metadata + self.index + self.special + self.cache
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            vocab: self.vocab + rhs.vocab,
+            metadata: self.metadata + rhs.metadata,
+            index: self.index + rhs.index,
+            special: self.special + rhs.special,
+            cache: self.cache + rhs.cache,
+        }
+    }
+}
+
+/// 哈希表占用的字节数，每个槽位另有 1 字节控制信息。
+#[inline]
+pub(crate) fn hash_map_size<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
@@ -0,0 +1,103 @@
+//! 这个模块提供编码前的文本规范化。
+
+use std::borrow::Cow;
+
+/// 编码前的文本规范化选项，默认不做任何处理。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Normalizer {
+    /// 将 `\r\n` 转换为 `\n`
+    pub crlf_to_lf: bool,
+    /// 去除开头的 utf-8 BOM
+    pub strip_bom: bool,
+}
+
+impl Normalizer {
+    /// 规范化文本，不需要修改时不分配内存。
+    pub fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let text = if self.strip_bom {
+            text.strip_prefix('\u{feff}').unwrap_or(text)
+        } else {
+            text
+        };
+        if self.crlf_to_lf && text.contains("\r\n") {
+            Cow::Owned(text.replace("\r\n", "\n"))
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+
+    /// 规范化文本，同时返回规范化后的字节位置到原文本的映射。
+    pub(crate) fn normalize_with_map<'a>(&self, text: &'a str) -> (Cow<'a, str>, OffsetMap) {
+        let normalized = self.normalize(text);
+        let mut map = OffsetMap {
+            bom: if self.strip_bom && text.starts_with('\u{feff}') {
+                '\u{feff}'.len_utf8()
+            } else {
+                0
+            },
+            removed: Vec::new(),
+        };
+        if self.crlf_to_lf {
+            // 第 i 个 `\r\n` 中的 `\n` 在规范化后的文本中前移 i 个字节
+            map.removed = text[map.bom..]
+                .match_indices("\r\n")
+                .enumerate()
+                .map(|(i, (pos, _))| pos - i)
+                .collect();
+        }
+        (normalized, map)
+    }
+}
+
+/// 规范化后的字节位置到原文本的映射。
+pub(crate) struct OffsetMap {
+    /// 去除的 BOM 的长度
+    bom: usize,
+    /// 去除了 `\r` 的 `\n` 在规范化后的文本中的位置，升序排列
+    removed: Vec<usize>,
+}
+
+impl OffsetMap {
+    /// 规范化后的字节位置在原文本中的位置。
+    ///
+    /// 由 `\r\n` 转换而来的 `\n` 的起始位置映射到 `\r`，因此覆盖它的范围映射后覆盖整个 `\r\n`。
+    pub fn original(&self, pos: usize) -> usize {
+        pos + self.bom + self.removed.partition_point(|&r| r < pos)
+    }
+}
+
+#[cfg(test)]
+mod normalizer_tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let normalizer = Normalizer {
+            crlf_to_lf: true,
+            strip_bom: true,
+        };
+        assert_eq!(normalizer.normalize("\u{feff}a\r\nb\r"), "a\nb\r");
+        assert!(matches!(normalizer.normalize("a\nb"), Cow::Borrowed(_)));
+        assert_eq!(
+            Normalizer::default().normalize("\u{feff}\r\n"),
+            "\u{feff}\r\n"
+        );
+    }
+
+    #[test]
+    fn test_offset_map() {
+        let normalizer = Normalizer {
+            crlf_to_lf: true,
+            strip_bom: true,
+        };
+        let text = "\u{feff}a\r\nb\r\n\r\nc";
+        let (normalized, map) = normalizer.normalize_with_map(text);
+        assert_eq!(normalized, "a\nb\n\nc");
+        let original = (0..=normalized.len())
+            .map(|pos| map.original(pos))
+            .collect::<Vec<_>>();
+        assert_eq!(original, [3, 4, 6, 7, 9, 11, 12]);
+        let (_, map) = Normalizer::default().normalize_with_map(text);
+        assert_eq!(map.original(4), 4);
+    }
+}
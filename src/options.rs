@@ -0,0 +1,118 @@
+//! 这个模块提供逐次调用的编码选项。
+
+use std::{fmt, marker::PhantomData};
+
+/// 单次编码的选项，默认与 [`Method::encode`](crate::Method::encode) 的行为相同。
+///
+/// 启用 `rand` 特性时可以携带调用者提供的随机数生成器，用于 BPE-dropout 等随机编码，
+/// 调用者自行管理种子即可得到可复现的结果。
+#[derive(Default)]
+pub struct EncodeOptions<'r> {
+    mode: EncodeMode,
+    cancel: Option<&'r dyn Fn() -> bool>,
+    workspace: bool,
+    #[cfg(feature = "rand")]
+    dropout: Option<(f32, &'r mut dyn rand::RngCore)>,
+    _rng: PhantomData<&'r mut ()>,
+}
+
+/// 编码策略，在精度和延迟之间取舍。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum EncodeMode {
+    /// 默认策略，例如 Lpe 的贪心最长前缀匹配
+    #[default]
+    Greedy,
+    /// 精确策略，例如 Lpe 以动态规划求词数最少的切分；没有精确策略的分词算法与 `Greedy` 相同
+    Exact,
+}
+
+impl<'r> EncodeOptions<'r> {
+    /// 设置编码策略。
+    #[inline]
+    pub fn with_mode(mut self, mode: EncodeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// 设置取消条件，支持取消的分词算法在 `cancel` 返回 `true` 时尽快停止，结果不完整。
+    ///
+    /// 条件在编码过程中反复检查，例如每次合并之前，因此应当足够廉价。
+    #[inline]
+    pub fn with_cancel(mut self, cancel: &'r dyn Fn() -> bool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// 设置是否复用线程内的编码缓冲区，支持复用的分词算法在同一线程的多次编码之间保留缓冲区，减少内存分配。
+    #[inline]
+    pub fn with_workspace(mut self, workspace: bool) -> Self {
+        self.workspace = workspace;
+        self
+    }
+
+    /// 启用 BPE-dropout，每次合并以概率 `p` 被跳过，随机数取自 `rng`。
+    #[cfg(feature = "rand")]
+    #[inline]
+    pub fn with_dropout(mut self, p: f32, rng: &'r mut impl rand::RngCore) -> Self {
+        self.dropout = Some((p, rng));
+        self
+    }
+
+    /// 编码策略。
+    #[inline]
+    pub fn mode(&self) -> EncodeMode {
+        self.mode
+    }
+
+    /// 取消条件。
+    #[inline]
+    pub fn cancel(&self) -> Option<&'r dyn Fn() -> bool> {
+        self.cancel
+    }
+
+    /// 是否复用线程内的编码缓冲区。
+    #[inline]
+    pub fn workspace(&self) -> bool {
+        self.workspace
+    }
+
+    /// 编码是否已被取消。
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(|cancel| cancel())
+    }
+
+    /// BPE-dropout 的概率和随机数生成器。
+    #[cfg(feature = "rand")]
+    #[inline]
+    pub fn dropout(&mut self) -> Option<(f32, &mut dyn rand::RngCore)> {
+        self.dropout
+            .as_mut()
+            .map(|(p, rng)| (*p, &mut **rng as &mut dyn rand::RngCore))
+    }
+
+    /// 以更短的生命周期借用选项，以便多次传递。
+    #[inline]
+    pub fn reborrow(&mut self) -> EncodeOptions<'_> {
+        EncodeOptions {
+            mode: self.mode,
+            cancel: self.cancel,
+            workspace: self.workspace,
+            #[cfg(feature = "rand")]
+            dropout: self.dropout.as_mut().map(|(p, rng)| (*p, &mut **rng as _)),
+            _rng: PhantomData,
+        }
+    }
+}
+
+impl fmt::Debug for EncodeOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("EncodeOptions");
+        s.field("mode", &self.mode);
+        s.field("cancel", &self.cancel.is_some());
+        s.field("workspace", &self.workspace);
+        #[cfg(feature = "rand")]
+        s.field("dropout", &self.dropout.as_ref().map(|(p, _)| p));
+        s.finish()
+    }
+}
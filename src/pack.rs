@@ -0,0 +1,92 @@
+//! 这个模块将多个文档打包为固定长度的训练序列，用于预训练数据处理。
+
+use crate::{tokeneer::or_panic, utok, InputTooLong, Method, Tokeneer};
+
+/// 打包得到的一个训练序列，由 [`Tokeneer::encode_packed`] 生成。
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct PackedSequence {
+    /// 词序列
+    pub tokens: Vec<utok>,
+    /// 在此序列中开始的文档的起始位置，升序排列；从上一个序列延续而来的部分不计入
+    pub boundaries: Vec<usize>,
+}
+
+impl<M: Method> Tokeneer<M> {
+    /// 依次编码 `docs`，在每个文档之后插入 `separator`，再将拼接的词流切分为长度为 `seq_len` 的序列。
+    ///
+    /// 文档可以跨越序列边界；最后一个序列可能短于 `seq_len`，由调用者决定保留或丢弃。
+    /// 任一个文档超过 [`InputLimit::Reject`](crate::InputLimit::Reject) 的上限时 panic。
+    pub fn encode_packed<'a>(
+        &self,
+        docs: impl IntoIterator<Item = &'a str>,
+        seq_len: usize,
+        separator: utok,
+    ) -> Vec<PackedSequence> {
+        or_panic(self.try_encode_packed(docs, seq_len, separator))
+    }
+
+    /// 同 [`encode_packed`](Self::encode_packed)，任一个文档超过上限时返回错误。
+    pub fn try_encode_packed<'a>(
+        &self,
+        docs: impl IntoIterator<Item = &'a str>,
+        seq_len: usize,
+        separator: utok,
+    ) -> Result<Vec<PackedSequence>, InputTooLong> {
+        assert!(seq_len > 0, "sequence length must be positive");
+
+        let mut ans = Vec::new();
+        let mut current = PackedSequence::default();
+        for doc in docs {
+            current.boundaries.push(current.tokens.len());
+            let mut tokens = self.try_encode(doc)?;
+            tokens.push(separator);
+
+            let mut tokens = &tokens[..];
+            while !tokens.is_empty() {
+                let n = tokens.len().min(seq_len - current.tokens.len());
+                current.tokens.extend_from_slice(&tokens[..n]);
+                tokens = &tokens[n..];
+                if current.tokens.len() == seq_len {
+                    ans.push(std::mem::take(&mut current));
+                }
+            }
+        }
+        if !current.tokens.is_empty() {
+            ans.push(current)
+        }
+        Ok(ans)
+    }
+}
+
+#[cfg(test)]
+mod pack_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_encode_packed() {
+        let lpe = Lpe::new(["<unk>", "<eos>", "a", "b", "ab"].map(str::as_bytes), 0);
+        let tokeneer = Tokeneer::new(lpe);
+        let packed = tokeneer.encode_packed(["ab", "aab", "b"], 3, 1);
+        let mut limited = Tokeneer::new(Lpe::new(["<unk>", "a"].map(str::as_bytes), 0));
+        limited.set_input_limit(Some(crate::InputLimit::Reject(2)));
+        assert!(limited.try_encode_packed(["a", "aaa"], 3, 0).is_err());
+        assert_eq!(
+            packed,
+            [
+                PackedSequence {
+                    tokens: vec![4, 1, 2],
+                    boundaries: vec![0, 2],
+                },
+                PackedSequence {
+                    tokens: vec![4, 1, 3],
+                    boundaries: vec![2],
+                },
+                PackedSequence {
+                    tokens: vec![1],
+                    boundaries: vec![],
+                },
+            ]
+        );
+    }
+}
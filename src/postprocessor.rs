@@ -0,0 +1,343 @@
+//! 这个模块提供编码后按模板插入特殊词的后处理，对应 HF tokenizers 的 `post_processor`。
+
+use crate::{utok, Encoding};
+
+/// 编码后处理，按模板在编码结果的前后和两段之间插入特殊词，并为每个词设置类型序号。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PostProcessor {
+    single: Box<[TemplatePiece]>,
+    pair: Box<[TemplatePiece]>,
+}
+
+/// 模板中的一项。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TemplatePiece {
+    /// 第 `id` 段输入的编码结果，0 表示第一段，1 表示第二段
+    Sequence { id: usize, type_id: u32 },
+    /// 插入的特殊词序列
+    Special { tokens: Vec<utok>, type_id: u32 },
+}
+
+impl PostProcessor {
+    /// 由单段和两段输入的模板构造。
+    pub fn template(
+        single: impl IntoIterator<Item = TemplatePiece>,
+        pair: impl IntoIterator<Item = TemplatePiece>,
+    ) -> Self {
+        Self {
+            single: single.into_iter().collect(),
+            pair: pair.into_iter().collect(),
+        }
+    }
+
+    /// BERT 的后处理：`[CLS] A [SEP]` 和 `[CLS] A [SEP] B [SEP]`，第二段及其后的 `[SEP]` 类型为 1。
+    pub fn bert(cls: utok, sep: utok) -> Self {
+        use TemplatePiece::{Sequence, Special};
+        let special = |t, type_id| Special {
+            tokens: vec![t],
+            type_id,
+        };
+        Self::template(
+            [
+                special(cls, 0),
+                Sequence { id: 0, type_id: 0 },
+                special(sep, 0),
+            ],
+            [
+                special(cls, 0),
+                Sequence { id: 0, type_id: 0 },
+                special(sep, 0),
+                Sequence { id: 1, type_id: 1 },
+                special(sep, 1),
+            ],
+        )
+    }
+
+    /// RoBERTa 的后处理：`<s> A </s>` 和 `<s> A </s> </s> B </s>`。
+    ///
+    /// 与 HF tokenizers 的 `RobertaProcessing` 相同，第二段及其两侧的 `</s>` 类型为 1，其余为 0。
+    pub fn roberta(cls: utok, sep: utok) -> Self {
+        use TemplatePiece::{Sequence, Special};
+        let special = |t, type_id| Special {
+            tokens: vec![t],
+            type_id,
+        };
+        Self::template(
+            [
+                special(cls, 0),
+                Sequence { id: 0, type_id: 0 },
+                special(sep, 0),
+            ],
+            [
+                special(cls, 0),
+                Sequence { id: 0, type_id: 0 },
+                special(sep, 0),
+                special(sep, 1),
+                Sequence { id: 1, type_id: 1 },
+                special(sep, 1),
+            ],
+        )
+    }
+
+    /// 解析 tokenizer.json 中的 `post_processor` 一节。
+    ///
+    /// 支持 `TemplateProcessing`、`BertProcessing`、`RobertaProcessing` 和由它们组成的 `Sequence`；
+    /// `ByteLevel` 只影响偏移，不改变词序列，与 `null` 一样返回 `None`。
+    ///
+    /// 本库不从 tokenizer.json 加载分词模型，因此没有 `from_tokenizer_json` 构造函数：
+    /// 分词器由 tokenizer.model 或词表文件构造后，再用
+    /// [`Tokeneer::set_post_processor_from_tokenizer_json`](crate::Tokeneer::set_post_processor_from_tokenizer_json)
+    /// 应用同一份文档中的后处理。
+    #[cfg(feature = "tokenizer-json")]
+    pub fn from_json(json: &serde_json::Value) -> Result<Option<Self>, PostProcessorError> {
+        json::parse(json)
+    }
+
+    /// 模板插入的特殊词个数，`pair` 表示两段输入。
+    pub fn added_tokens(&self, pair: bool) -> usize {
+        self.pieces(pair)
+            .iter()
+            .map(|piece| match piece {
+                TemplatePiece::Special { tokens, .. } => tokens.len(),
+                TemplatePiece::Sequence { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// 按模板组合一段或两段输入的编码结果。
+    pub(crate) fn apply(&self, first: Encoding, second: Option<Encoding>) -> Encoding {
+        let pair = second.is_some();
+        let sequences = [Some(first), second];
+        let mut ans = Encoding::default();
+        for piece in self.pieces(pair) {
+            match piece {
+                TemplatePiece::Sequence { id, type_id } => {
+                    if let Some(Some(sequence)) = sequences.get(*id) {
+                        let mut sequence = sequence.clone();
+                        sequence.set_type_id(*type_id);
+                        ans.append(sequence)
+                    }
+                }
+                TemplatePiece::Special { tokens, type_id } => {
+                    let mut special = Encoding::default();
+                    special.push(tokens, vec![0..0; tokens.len()], true, 0, None);
+                    special.set_type_id(*type_id);
+                    ans.append(special)
+                }
+            }
+        }
+        ans
+    }
+
+    fn pieces(&self, pair: bool) -> &[TemplatePiece] {
+        if pair {
+            &self.pair
+        } else {
+            &self.single
+        }
+    }
+}
+
+/// 解析 `post_processor` 时发现的问题。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PostProcessorError {
+    /// 不支持的后处理类型
+    Unsupported(String),
+    /// 缺少字段或字段的类型不正确
+    Malformed(&'static str),
+    /// 模板引用了 `special_tokens` 中没有定义的特殊词
+    UndefinedSpecial(String),
+}
+
+impl std::fmt::Display for PostProcessorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Unsupported(ty) => write!(f, "unsupported post processor {ty:?}"),
+            Self::Malformed(field) => write!(f, "malformed post processor field {field:?}"),
+            Self::UndefinedSpecial(special) => {
+                write!(f, "template refers to undefined special {special:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PostProcessorError {}
+
+#[cfg(feature = "tokenizer-json")]
+mod json {
+    use super::{PostProcessor, PostProcessorError, TemplatePiece};
+    use crate::utok;
+    use serde_json::Value;
+
+    type Result<T> = std::result::Result<T, PostProcessorError>;
+
+    pub(super) fn parse(json: &Value) -> Result<Option<PostProcessor>> {
+        if json.is_null() {
+            return Ok(None);
+        }
+        match str_field(json, "type")? {
+            "TemplateProcessing" => {
+                let specials = json
+                    .get("special_tokens")
+                    .and_then(Value::as_object)
+                    .ok_or(PostProcessorError::Malformed("special_tokens"))?;
+                let template = |field| -> Result<Vec<TemplatePiece>> {
+                    json.get(field)
+                        .and_then(Value::as_array)
+                        .ok_or(PostProcessorError::Malformed(field))?
+                        .iter()
+                        .map(|piece| parse_piece(piece, specials))
+                        .collect()
+                };
+                Ok(Some(PostProcessor::template(
+                    template("single")?,
+                    template("pair")?,
+                )))
+            }
+            "BertProcessing" => Ok(Some(PostProcessor::bert(
+                token_field(json, "cls")?,
+                token_field(json, "sep")?,
+            ))),
+            "RobertaProcessing" => Ok(Some(PostProcessor::roberta(
+                token_field(json, "cls")?,
+                token_field(json, "sep")?,
+            ))),
+            "ByteLevel" => Ok(None),
+            // 序列中至多一个后处理改变词序列，其余只影响偏移
+            "Sequence" => {
+                let mut ans = None;
+                for processor in json
+                    .get("processors")
+                    .and_then(Value::as_array)
+                    .ok_or(PostProcessorError::Malformed("processors"))?
+                {
+                    if let Some(processor) = parse(processor)? {
+                        ans = Some(processor)
+                    }
+                }
+                Ok(ans)
+            }
+            ty => Err(PostProcessorError::Unsupported(ty.into())),
+        }
+    }
+
+    /// 解析模板中的一项，形如 `{"Sequence": {"id": "A", "type_id": 0}}`
+    /// 或 `{"SpecialToken": {"id": "[CLS]", "type_id": 0}}`。
+    fn parse_piece(
+        piece: &Value,
+        specials: &serde_json::Map<String, Value>,
+    ) -> Result<TemplatePiece> {
+        let type_id = |inner: &Value| {
+            inner
+                .get("type_id")
+                .and_then(Value::as_u64)
+                .and_then(|id| u32::try_from(id).ok())
+                .ok_or(PostProcessorError::Malformed("type_id"))
+        };
+        if let Some(inner) = piece.get("Sequence") {
+            let id = match str_field(inner, "id")? {
+                "A" => 0,
+                "B" => 1,
+                _ => return Err(PostProcessorError::Malformed("id")),
+            };
+            Ok(TemplatePiece::Sequence {
+                id,
+                type_id: type_id(inner)?,
+            })
+        } else if let Some(inner) = piece.get("SpecialToken") {
+            let name = str_field(inner, "id")?;
+            let tokens = specials
+                .get(name)
+                .ok_or_else(|| PostProcessorError::UndefinedSpecial(name.into()))?
+                .get("ids")
+                .and_then(Value::as_array)
+                .ok_or(PostProcessorError::Malformed("ids"))?
+                .iter()
+                .map(|id| as_token(id).ok_or(PostProcessorError::Malformed("ids")))
+                .collect::<Result<_>>()?;
+            Ok(TemplatePiece::Special {
+                tokens,
+                type_id: type_id(inner)?,
+            })
+        } else {
+            Err(PostProcessorError::Malformed("single"))
+        }
+    }
+
+    fn str_field<'a>(json: &'a Value, field: &'static str) -> Result<&'a str> {
+        json.get(field)
+            .and_then(Value::as_str)
+            .ok_or(PostProcessorError::Malformed(field))
+    }
+
+    /// 解析形如 `["[CLS]", 101]` 的字段中的词序号。
+    fn token_field(json: &Value, field: &'static str) -> Result<utok> {
+        json.get(field)
+            .and_then(|v| as_token(v.get(1)?))
+            .ok_or(PostProcessorError::Malformed(field))
+    }
+
+    fn as_token(value: &Value) -> Option<utok> {
+        value.as_u64().and_then(|id| utok::try_from(id).ok())
+    }
+}
+
+#[cfg(test)]
+mod postprocessor_tests {
+    use super::*;
+
+    #[test]
+    fn test_added_tokens() {
+        let bert = PostProcessor::bert(101, 102);
+        assert_eq!(bert.added_tokens(false), 2);
+        assert_eq!(bert.added_tokens(true), 3);
+        assert_eq!(PostProcessor::roberta(0, 2).added_tokens(true), 4);
+    }
+
+    #[cfg(feature = "tokenizer-json")]
+    #[test]
+    fn test_from_json() {
+        use serde_json::json;
+
+        let template = json!({
+            "type": "TemplateProcessing",
+            "single": [
+                {"SpecialToken": {"id": "[CLS]", "type_id": 0}},
+                {"Sequence": {"id": "A", "type_id": 0}},
+                {"SpecialToken": {"id": "[SEP]", "type_id": 0}}
+            ],
+            "pair": [
+                {"SpecialToken": {"id": "[CLS]", "type_id": 0}},
+                {"Sequence": {"id": "A", "type_id": 0}},
+                {"SpecialToken": {"id": "[SEP]", "type_id": 0}},
+                {"Sequence": {"id": "B", "type_id": 1}},
+                {"SpecialToken": {"id": "[SEP]", "type_id": 1}}
+            ],
+            "special_tokens": {
+                "[CLS]": {"id": "[CLS]", "ids": [101], "tokens": ["[CLS]"]},
+                "[SEP]": {"id": "[SEP]", "ids": [102], "tokens": ["[SEP]"]}
+            }
+        });
+        let bert = PostProcessor::bert(101, 102);
+        assert_eq!(PostProcessor::from_json(&template), Ok(Some(bert.clone())));
+        let sequence = json!({
+            "type": "Sequence",
+            "processors": [
+                {"type": "ByteLevel", "trim_offsets": true},
+                {"type": "BertProcessing", "cls": ["[CLS]", 101], "sep": ["[SEP]", 102]}
+            ]
+        });
+        assert_eq!(PostProcessor::from_json(&sequence), Ok(Some(bert)));
+        assert_eq!(
+            PostProcessor::from_json(
+                &json!({"type": "RobertaProcessing", "cls": ["<s>", 0], "sep": ["</s>", 2]})
+            ),
+            Ok(Some(PostProcessor::roberta(0, 2)))
+        );
+        assert_eq!(PostProcessor::from_json(&json!(null)), Ok(None));
+        assert_eq!(
+            PostProcessor::from_json(&json!({"type": "Custom"})),
+            Err(PostProcessorError::Unsupported("Custom".into()))
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! 这个模块提供常见模型家族的特殊词预设，应用可以按名字注册特殊词，不必在各处硬编码 `<|im_start|>` 等字符串。
+
+use crate::{utok, Method, Tokeneer};
+
+/// 一个模型家族的特殊词预设。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Preset {
+    /// 预设的名字
+    pub name: &'static str,
+    /// 特殊词的用途和文本，例如 `("bos", "<s>")`
+    pub specials: &'static [(&'static str, &'static str)],
+}
+
+/// Llama 2 及其 sentencepiece 词表。
+pub const LLAMA2: Preset = Preset {
+    name: "llama2",
+    specials: &[("unk", "<unk>"), ("bos", "<s>"), ("eos", "</s>")],
+};
+
+/// Llama 3 系列。
+pub const LLAMA3: Preset = Preset {
+    name: "llama3",
+    specials: &[
+        ("bos", "<|begin_of_text|>"),
+        ("eos", "<|end_of_text|>"),
+        ("header_start", "<|start_header_id|>"),
+        ("header_end", "<|end_header_id|>"),
+        ("eot", "<|eot_id|>"),
+        ("eom", "<|eom_id|>"),
+        ("python_tag", "<|python_tag|>"),
+        ("pad", "<|finetune_right_pad_id|>"),
+    ],
+};
+
+/// Qwen 2 系列，对话格式为 ChatML。
+pub const QWEN2: Preset = Preset {
+    name: "qwen2",
+    specials: &[
+        ("eos", "<|endoftext|>"),
+        ("im_start", "<|im_start|>"),
+        ("im_end", "<|im_end|>"),
+    ],
+};
+
+/// 通用的 ChatML 对话格式。
+pub const CHATML: Preset = Preset {
+    name: "chatml",
+    specials: &[("im_start", "<|im_start|>"), ("im_end", "<|im_end|>")],
+};
+
+/// 所有预设。
+pub const PRESETS: &[Preset] = &[LLAMA2, LLAMA3, QWEN2, CHATML];
+
+impl Preset {
+    /// 按名字查找预设。
+    pub fn by_name(name: &str) -> Option<&'static Self> {
+        PRESETS.iter().find(|p| p.name == name)
+    }
+
+    /// 按用途查找特殊词的文本。
+    pub fn get(&self, role: &str) -> Option<&'static str> {
+        self.specials
+            .iter()
+            .find(|(r, _)| *r == role)
+            .map(|&(_, text)| text)
+    }
+}
+
+impl<M: Method> Tokeneer<M> {
+    /// 按预设注册特殊词，每个特殊词映射到词表中内容与其相同的词，返回词表中找不到的特殊词。
+    ///
+    /// 已经注册的特殊词保持不变。注册后的特殊词以其用途命名，可以用 [`Tokeneer::special_id`] 按用途查找。
+    pub fn extend_special_preset(&mut self, preset: &Preset) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        let mut patterns = Vec::new();
+        for &(_, text) in preset.specials {
+            if self.special_tokens().any(|(k, _)| k == text) {
+                continue;
+            }
+            match self.token_of(text) {
+                Some(token) => patterns.push((text.to_string(), vec![token])),
+                None => missing.push(text),
+            }
+        }
+        self.extend_special(patterns);
+        for &(role, text) in preset.specials {
+            self.set_special_name(role, text);
+        }
+        missing
+    }
+
+    /// 内容为 `text` 的序号最小的词。
+    fn token_of(&self, text: &str) -> Option<utok> {
+        let method = self.internal();
+        (0..method.vocab_size() as utok).find(|&t| method.decode(t) == text.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod presets_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(Preset::by_name("qwen2"), Some(&QWEN2));
+        assert_eq!(Preset::by_name("gpt2"), None);
+        assert_eq!(LLAMA3.get("eot"), Some("<|eot_id|>"));
+
+        let mut tokeneer = Tokeneer::new(Lpe::new(
+            ["<unk>", "a", "<|im_start|>", "<|im_end|>"].map(str::as_bytes),
+            0,
+        ));
+        assert_eq!(tokeneer.extend_special_preset(&QWEN2), ["<|endoftext|>"]);
+        assert_eq!(tokeneer.encode("<|im_start|>a<|im_end|>"), [2, 1, 3]);
+        assert_eq!(tokeneer.special_id("im_end"), Some(&[3][..]));
+        assert_eq!(tokeneer.special_id("eos"), None);
+        // 重复注册没有副作用
+        assert_eq!(tokeneer.extend_special_preset(&CHATML), [] as [&str; 0]);
+    }
+}
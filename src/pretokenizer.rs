@@ -0,0 +1,155 @@
+//! 这个模块提供按字符类别预分词的基本步骤，可以组合出各模型特有的预分词规则。
+//!
+//! 预分词的结果是输入文本的连续切片，拼接后恰为原文本，
+//! 可以交给 [`Tokeneer::encode_pretokenized`](crate::Tokeneer::encode_pretokenized) 逐片编码。
+
+use std::ops::Range;
+
+/// 字符的粗粒度类别。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CharClass {
+    /// 字母和表意文字
+    Letter,
+    /// 数字
+    Number,
+    /// 空白
+    Whitespace,
+    /// 标点、符号和其他字符
+    Punctuation,
+}
+
+impl CharClass {
+    /// 字符所属的类别。
+    pub fn of(c: char) -> Self {
+        if c.is_whitespace() {
+            Self::Whitespace
+        } else if c.is_numeric() {
+            Self::Number
+        } else if c.is_alphabetic() {
+            Self::Letter
+        } else {
+            Self::Punctuation
+        }
+    }
+}
+
+/// 预分词的一个步骤，在片段内部加入切分点。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PreTokenizeStep {
+    /// 在相邻字符的类别不同处切分
+    ClassChange,
+    /// 在空白段的两侧切分，空白段独立成片
+    Whitespace,
+    /// 在空白段的两侧切分，但空白段的最后一个空白字符并入其后的片段，即 `" word"` 的形式
+    WhitespacePrefix,
+    /// 每个标点独立成片
+    IsolatePunctuation,
+    /// 每个数字独立成片
+    IsolateDigits,
+}
+
+impl PreTokenizeStep {
+    /// 是否在 `prev` 和 `next` 之间切分，`after` 是 `next` 之后的字符。
+    fn splits(self, prev: char, next: char, after: Option<char>) -> bool {
+        use CharClass::*;
+        let (a, b) = (CharClass::of(prev), CharClass::of(next));
+        match self {
+            Self::ClassChange => a != b,
+            Self::Whitespace => (a == Whitespace) != (b == Whitespace),
+            Self::WhitespacePrefix => match (a, b) {
+                // 空白段内部，在最后一个空白字符之前切分
+                (Whitespace, Whitespace) => after.is_some_and(|c| !c.is_whitespace()),
+                (Whitespace, _) => false,
+                (_, Whitespace) => true,
+                _ => false,
+            },
+            Self::IsolatePunctuation => a == Punctuation || b == Punctuation,
+            Self::IsolateDigits => a == Number || b == Number,
+        }
+    }
+}
+
+/// 依次应用若干步骤的预分词器，默认不切分。
+///
+/// 每一步只加入切分点，不会合并之前的片段，因此步骤的顺序不影响结果。
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct PreTokenizer {
+    steps: Vec<PreTokenizeStep>,
+}
+
+impl PreTokenizer {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 加入一个步骤。
+    #[inline]
+    pub fn with(mut self, step: PreTokenizeStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// 切分文本，返回每个片段的字节范围。
+    pub fn split_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        let mut ans = Vec::new();
+        let mut chars = text.char_indices().peekable();
+        let mut start = 0;
+        while let Some((_, prev)) = chars.next() {
+            let Some(&(pos, next)) = chars.peek() else {
+                break;
+            };
+            let after = text[pos + next.len_utf8()..].chars().next();
+            if self.steps.iter().any(|s| s.splits(prev, next, after)) {
+                ans.push(start..pos);
+                start = pos
+            }
+        }
+        if start < text.len() {
+            ans.push(start..text.len())
+        }
+        ans
+    }
+
+    /// 切分文本，返回各个片段。
+    pub fn split<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        self.split_ranges(text)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod pretokenizer_tests {
+    use super::*;
+    use PreTokenizeStep::*;
+
+    #[test]
+    fn test_pretokenizer() {
+        let text = "Hello,  world 2024!";
+        assert_eq!(PreTokenizer::new().split(text), [text]);
+        assert_eq!(
+            PreTokenizer::new().with(ClassChange).split(text),
+            ["Hello", ",", "  ", "world", " ", "2024", "!"]
+        );
+        assert_eq!(
+            PreTokenizer::new().with(WhitespacePrefix).split(text),
+            ["Hello,", " ", " world", " 2024!"]
+        );
+        // 组合多个步骤
+        assert_eq!(
+            PreTokenizer::new()
+                .with(WhitespacePrefix)
+                .with(IsolatePunctuation)
+                .with(IsolateDigits)
+                .split(text),
+            ["Hello", ",", " ", " world", " ", "2", "0", "2", "4", "!"]
+        );
+        assert_eq!(
+            PreTokenizer::new().with(Whitespace).split("中文 字符"),
+            ["中文", " ", "字符"]
+        );
+        assert!(PreTokenizer::new().with(ClassChange).split("").is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+//! 这个模块提供按语言或领域标签在多个分词器之间选择的路由。
+
+use crate::{utok, DecodeError, InputTooLong, Method, Tokeneer};
+use std::{collections::HashMap, ops::Range};
+
+/// 按请求的标签选择分词器，例如在同一个接口后同时服务代码模型和对话模型。
+///
+/// 各分词器的词序号按加入的顺序依次平移到共享的词序号空间，不同分词器的词序号不会重叠，
+/// 因此解码时可以从词序号找回产生它的分词器。每个分词器占用的范围按 [`Tokeneer::token_bound`] 确定，
+/// 包含超出词表的特殊词序号。
+pub struct Router<M> {
+    routes: Vec<Route<M>>,
+    /// 标签 -> 路由序号
+    tags: HashMap<String, usize>,
+    /// 未知标签使用的路由
+    default: Option<usize>,
+}
+
+struct Route<M> {
+    /// 加入时使用的标签
+    tag: String,
+    tokeneer: Tokeneer<M>,
+    /// 共享空间中的词序号范围
+    ids: Range<utok>,
+}
+
+/// 路由时发现的问题。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RouteError {
+    /// 标签没有对应的分词器，并且没有设置默认分词器
+    UnknownTag(String),
+    /// 标签已经对应了一个分词器
+    DuplicateTag(String),
+    /// 词序号不属于任何分词器
+    UnknownToken(utok),
+    /// 分词器按 [`UnkPolicy::Error`](crate::UnkPolicy::Error) 解码失败，词序号和位置都是共享序列中的
+    Decode(DecodeError),
+    /// 文本超过分词器的 [`InputLimit::Reject`](crate::InputLimit::Reject) 上限
+    InputTooLong(InputTooLong),
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownTag(tag) => write!(f, "no tokenizer routed for tag {tag:?}"),
+            Self::DuplicateTag(tag) => write!(f, "tag {tag:?} is already routed"),
+            Self::UnknownToken(token) => write!(f, "token {token} belongs to no tokenizer"),
+            Self::Decode(e) => e.fmt(f),
+            Self::InputTooLong(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+impl<M> Default for Router<M> {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            tags: HashMap::new(),
+            default: None,
+        }
+    }
+}
+
+impl<M: Method> Router<M> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 以 `tag` 加入一个分词器，返回它在共享空间中的词序号范围。
+    ///
+    /// 范围的大小取加入时的 [`Tokeneer::token_bound`]，加入后再注册超出范围的特殊词不会扩大范围。
+    pub fn add(
+        &mut self,
+        tag: impl Into<String>,
+        tokeneer: Tokeneer<M>,
+    ) -> Result<Range<utok>, RouteError> {
+        let tag = tag.into();
+        if self.tags.contains_key(&tag) {
+            return Err(RouteError::DuplicateTag(tag));
+        }
+        let start = self.routes.last().map_or(0, |r| r.ids.end);
+        let size = utok::try_from(tokeneer.token_bound()).unwrap();
+        let ids = start..start.checked_add(size).expect("shared id space overflow");
+        self.tags.insert(tag.clone(), self.routes.len());
+        self.routes.push(Route {
+            tag,
+            tokeneer,
+            ids: ids.clone(),
+        });
+        Ok(ids)
+    }
+
+    /// 使 `alias` 与 `tag` 选择同一个分词器，例如将多种编程语言的标签都指向代码分词器。
+    pub fn alias(&mut self, alias: impl Into<String>, tag: &str) -> Result<(), RouteError> {
+        let alias = alias.into();
+        let &i = self
+            .tags
+            .get(tag)
+            .ok_or_else(|| RouteError::UnknownTag(tag.into()))?;
+        if self.tags.contains_key(&alias) {
+            return Err(RouteError::DuplicateTag(alias));
+        }
+        self.tags.insert(alias, i);
+        Ok(())
+    }
+
+    /// 设置未知标签使用的分词器，`None` 表示未知标签报错。
+    pub fn set_default(&mut self, tag: Option<&str>) -> Result<(), RouteError> {
+        self.default = match tag {
+            Some(tag) => Some(
+                *self
+                    .tags
+                    .get(tag)
+                    .ok_or_else(|| RouteError::UnknownTag(tag.into()))?,
+            ),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// 标签选择的分词器，未知标签选择默认分词器。
+    pub fn get(&self, tag: &str) -> Option<&Tokeneer<M>> {
+        self.route(tag).ok().map(|r| &r.tokeneer)
+    }
+
+    /// 共享空间的大小，即所有分词器的词序号范围大小之和。
+    #[inline]
+    pub fn vocab_size(&self) -> usize {
+        self.routes.last().map_or(0, |r| r.ids.end as usize)
+    }
+
+    /// 用标签选择的分词器编码文本，返回共享空间中的词序号。
+    ///
+    /// 文本超过分词器的输入长度上限时返回 [`RouteError::InputTooLong`]。
+    pub fn encode(&self, tag: &str, text: &str) -> Result<Vec<utok>, RouteError> {
+        let route = self.route(tag)?;
+        let mut tokens = route
+            .tokeneer
+            .try_encode(text)
+            .map_err(RouteError::InputTooLong)?;
+        for t in &mut tokens {
+            *t += route.ids.start
+        }
+        Ok(tokens)
+    }
+
+    /// 解码共享空间中的词序列，连续属于同一个分词器的词一起解码。
+    pub fn decode(&self, tokens: &[utok]) -> Result<String, RouteError> {
+        let mut ans = String::new();
+        let mut rest = tokens;
+        let mut index = 0;
+        while let Some(&first) = rest.first() {
+            let route = self.owner(first).ok_or(RouteError::UnknownToken(first))?;
+            let len = rest
+                .iter()
+                .position(|t| !route.ids.contains(t))
+                .unwrap_or(rest.len());
+            let (run, tail) = rest.split_at(len);
+            let local = run.iter().map(|t| t - route.ids.start).collect::<Vec<_>>();
+            let text = route.tokeneer.try_decode(&local).map_err(|e| {
+                RouteError::Decode(DecodeError {
+                    token: e.token + route.ids.start,
+                    index: e.index + index,
+                })
+            })?;
+            ans.push_str(&text);
+            index += len;
+            rest = tail
+        }
+        Ok(ans)
+    }
+
+    /// 将标签选择的分词器的词序号转换为共享空间中的词序号，超出词表时返回 `None`。
+    pub fn to_shared(&self, tag: &str, token: utok) -> Option<utok> {
+        let route = self.route(tag).ok()?;
+        let shared = route.ids.start.checked_add(token)?;
+        route.ids.contains(&shared).then_some(shared)
+    }
+
+    /// 共享空间中的词序号所属分词器加入时的标签，以及在这个分词器中的词序号。
+    pub fn to_local(&self, token: utok) -> Option<(&str, utok)> {
+        self.owner(token).map(|r| (&*r.tag, token - r.ids.start))
+    }
+
+    fn route(&self, tag: &str) -> Result<&Route<M>, RouteError> {
+        self.tags
+            .get(tag)
+            .copied()
+            .or(self.default)
+            .map(|i| &self.routes[i])
+            .ok_or_else(|| RouteError::UnknownTag(tag.into()))
+    }
+
+    fn owner(&self, token: utok) -> Option<&Route<M>> {
+        // 各分词器的范围按加入顺序递增且首尾相接
+        let i = self.routes.partition_point(|r| r.ids.end <= token);
+        self.routes.get(i).filter(|r| r.ids.contains(&token))
+    }
+}
+
+#[cfg(test)]
+mod router_tests {
+    use super::*;
+    use crate::Lpe;
+
+    fn two_routes() -> Router<Lpe> {
+        let chat = Tokeneer::new(Lpe::new(["<unk>", "a", "b", "ab"].map(str::as_bytes), 0));
+        let code = Tokeneer::new(Lpe::new(
+            ["<unk>", "fn", "(", ")", " ", "f", "n"].map(str::as_bytes),
+            0,
+        ));
+        let mut router = Router::new();
+        assert_eq!(router.add("chat", chat), Ok(0..4));
+        assert_eq!(router.add("code", code), Ok(4..11));
+        router
+    }
+
+    #[test]
+    fn test_router() {
+        let mut router = two_routes();
+        assert_eq!(router.vocab_size(), 11);
+        assert_eq!(router.encode("chat", "aba"), Ok(vec![3, 1]));
+        assert_eq!(router.encode("code", "fn f()"), Ok(vec![5, 8, 9, 6, 7]));
+        assert_eq!(
+            router.encode("rust", "fn"),
+            Err(RouteError::UnknownTag("rust".into()))
+        );
+        router.alias("rust", "code").unwrap();
+        assert_eq!(router.encode("rust", "fn"), Ok(vec![5]));
+        assert_eq!(
+            router.alias("chat", "code"),
+            Err(RouteError::DuplicateTag("chat".into()))
+        );
+        router.set_default(Some("chat")).unwrap();
+        assert_eq!(router.encode("en", "b"), Ok(vec![2]));
+
+        // 混合两个分词器的词序列
+        assert_eq!(router.decode(&[3, 5, 8, 1]), Ok("abfn a".into()));
+        assert_eq!(router.decode(&[11]), Err(RouteError::UnknownToken(11)));
+        assert_eq!(router.to_shared("code", 1), Some(5));
+        assert_eq!(router.to_shared("code", 7), None);
+        assert_eq!(router.to_local(5), Some(("code", 1)));
+        assert_eq!(router.to_local(3), Some(("chat", 3)));
+    }
+
+    #[test]
+    fn test_router_input_limit() {
+        let mut router = two_routes();
+        let mut limited = Tokeneer::new(Lpe::new(["<unk>", "a"].map(str::as_bytes), 0));
+        limited.set_input_limit(Some(crate::InputLimit::Reject(1)));
+        router.add("short", limited).unwrap();
+        assert_eq!(router.encode("short", "a"), Ok(vec![12]));
+        assert_eq!(
+            router.encode("short", "aa"),
+            Err(RouteError::InputTooLong(crate::InputTooLong {
+                len: 2,
+                limit: 1
+            }))
+        );
+    }
+
+    #[test]
+    fn test_router_out_of_vocab_special() {
+        let mut router = Router::new();
+        let mut chat = Tokeneer::new(Lpe::new(["<unk>", "a", "b"].map(str::as_bytes), 0));
+        chat.extend_special([("<|eot|>".to_string(), vec![5])]);
+        assert_eq!(chat.token_bound(), 6);
+        let code = Tokeneer::new(Lpe::new(["<unk>", "f"].map(str::as_bytes), 0));
+        // 超出词表的特殊词序号属于注册它的分词器
+        assert_eq!(router.add("chat", chat), Ok(0..6));
+        assert_eq!(router.add("code", code), Ok(6..8));
+        assert_eq!(router.encode("chat", "a<|eot|>"), Ok(vec![1, 5]));
+        assert_eq!(router.to_local(5), Some(("chat", 5)));
+        assert_eq!(router.to_local(7), Some(("code", 1)));
+        let eot = router.get("chat").unwrap().decode(&[5]);
+        assert_eq!(router.decode(&[1, 5, 7]), Ok(format!("a{eot}f")));
+    }
+
+    #[test]
+    fn test_router_decode_error() {
+        let mut router = Router::new();
+        let chat = Tokeneer::new(Lpe::new(["<unk>", "a", "b"].map(str::as_bytes), 0));
+        let mut code = Tokeneer::new(Lpe::new(["<unk>", "f"].map(str::as_bytes), 0));
+        code.set_unk_policy(crate::UnkPolicy::Error);
+        router.add("chat", chat).unwrap();
+        router.add("code", code).unwrap();
+        assert_eq!(router.decode(&[1, 0, 4]), Ok("a<unk>f".into()));
+        // 错误中的词序号和位置都对应共享的词序列
+        assert_eq!(
+            router.decode(&[1, 2, 4, 3]),
+            Err(RouteError::Decode(DecodeError { token: 3, index: 3 }))
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! 这个模块提供可以在多个线程间廉价共享的只读分词器。
+
+use crate::{utok, EncodeOptions, MemoryUsage, Method, Tokeneer};
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+/// 冻结的分词器句柄，克隆只增加引用计数，适合在请求处理器之间传递。
+///
+/// 句柄只提供只读接口。修改配置时通过 [`edit`](Self::edit) 取得可修改的副本，
+/// 修改完成后转换为新的句柄；新旧句柄共享词表，已经分发出去的旧句柄不受影响，
+/// 因此服务可以在不加锁的情况下热更新分词器配置。
+pub struct SharedTokeneer<M>(Arc<Tokeneer<Arc<M>>>);
+
+impl<M> Clone for SharedTokeneer<M> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<M> SharedTokeneer<M> {
+    /// 冻结一个分词器。
+    pub fn new(tokeneer: Tokeneer<M>) -> Self {
+        Self(Arc::new(tokeneer.map_method(Arc::new)))
+    }
+
+    /// 复制当前的配置用于修改，词表不会被复制。
+    pub fn edit(&self) -> Tokeneer<Arc<M>> {
+        (*self.0).clone()
+    }
+}
+
+impl<M> From<Tokeneer<Arc<M>>> for SharedTokeneer<M> {
+    #[inline]
+    fn from(tokeneer: Tokeneer<Arc<M>>) -> Self {
+        Self(Arc::new(tokeneer))
+    }
+}
+
+impl<M> Deref for SharedTokeneer<M> {
+    type Target = Tokeneer<Arc<M>>;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// 可以热更新的分词器，例如由配置文件监视器的回调更新特殊词和预处理配置。
+///
+/// 更新只替换配置，不重新构造词表；正在使用旧句柄的请求不受影响。
+pub struct ReloadableTokeneer<M>(RwLock<SharedTokeneer<M>>);
+
+impl<M> ReloadableTokeneer<M> {
+    pub fn new(tokeneer: SharedTokeneer<M>) -> Self {
+        Self(RwLock::new(tokeneer))
+    }
+
+    /// 取得当前的分词器句柄。
+    #[inline]
+    pub fn load(&self) -> SharedTokeneer<M> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// 替换当前的分词器句柄。
+    #[inline]
+    pub fn store(&self, tokeneer: SharedTokeneer<M>) {
+        *self.0.write().unwrap() = tokeneer
+    }
+
+    /// 在当前配置的副本上执行 `f`，成功时原子地替换为修改后的配置，失败时保留原配置。
+    ///
+    /// 多个更新依次执行，不会互相覆盖。
+    pub fn update<E>(
+        &self,
+        f: impl FnOnce(&mut Tokeneer<Arc<M>>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut current = self.0.write().unwrap();
+        let mut edit = current.edit();
+        f(&mut edit)?;
+        *current = edit.into();
+        Ok(())
+    }
+}
+
+impl<M: Method> Method for Arc<M> {
+    #[inline]
+    fn unk_token(&self) -> Option<utok> {
+        (**self).unk_token()
+    }
+    #[inline]
+    fn vocab_size(&self) -> usize {
+        (**self).vocab_size()
+    }
+    #[inline]
+    fn internal_special(&self) -> impl IntoIterator<Item = (&str, utok)> {
+        (**self).internal_special()
+    }
+    #[inline]
+    fn encode<'a>(&'a self, text: &'a str) -> impl IntoIterator<Item = utok> + 'a {
+        (**self).encode(text)
+    }
+    #[inline]
+    fn decode(&self, token: utok) -> &[u8] {
+        (**self).decode(token)
+    }
+    #[inline]
+    fn encode_with<'a>(
+        &'a self,
+        text: &'a str,
+        options: EncodeOptions,
+    ) -> impl IntoIterator<Item = utok> + 'a {
+        (**self).encode_with(text, options)
+    }
+    #[inline]
+    fn fallback_byte(&self, token: utok) -> Option<u8> {
+        (**self).fallback_byte(token)
+    }
+    #[inline]
+    fn rank(&self, token: utok) -> Option<u32> {
+        (**self).rank(token)
+    }
+    #[inline]
+    fn memory_usage(&self) -> MemoryUsage {
+        (**self).memory_usage()
+    }
+}
+
+#[cfg(test)]
+mod shared_tokeneer_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_shared_tokeneer() {
+        let lpe = Lpe::new(["<unk>", "<s>", "a", "b", "ab"].map(str::as_bytes), 0);
+        let shared = SharedTokeneer::new(Tokeneer::new(lpe));
+        let handle = shared.clone();
+
+        let mut edit = shared.edit();
+        edit.extend_special([("<pair>".to_string(), vec![2, 3])]);
+        let updated = SharedTokeneer::from(edit);
+
+        // 旧句柄不受影响，新旧句柄共享词表
+        assert_eq!(handle.encode("<pair>ab"), [0, 0, 2, 0, 0, 0, 4]);
+        assert_eq!(updated.encode("<pair>ab"), [2, 3, 4]);
+        assert_eq!(handle.fingerprint(), shared.fingerprint());
+        assert_ne!(updated.fingerprint(), shared.fingerprint());
+        assert!(Arc::ptr_eq(shared.internal(), updated.internal()));
+
+        let reloadable = ReloadableTokeneer::new(updated.clone());
+        let before = reloadable.load();
+        reloadable
+            .update(|t| {
+                t.replace_special([("<b>".to_string(), vec![3])]);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        assert_eq!(
+            reloadable.load().encode("<pair><b><s>"),
+            [0, 0, 2, 0, 0, 0, 3, 1]
+        );
+        assert_eq!(before.encode("<pair><b><s>"), [2, 3, 0, 3, 0, 1]);
+        // 回调失败时保留原配置
+        assert_eq!(
+            reloadable.update(|t| {
+                t.replace_special([]);
+                Err("bad")
+            }),
+            Err("bad")
+        );
+        assert_eq!(reloadable.load().encode("<b>"), [3]);
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                let handle = updated.clone();
+                s.spawn(move || assert_eq!(handle.decode(&[2, 3, 1]), "ab<s>"));
+            }
+        });
+    }
+}
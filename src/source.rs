@@ -0,0 +1,85 @@
+//! 这个模块记录和校验构造分词器所用的模型文件，以证明缓存的编码结果来自哪个词表。
+
+use sha2::{Digest, Sha256};
+use std::{fmt, fs, io, path::Path, path::PathBuf};
+
+/// 模型文件的来源信息。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceInfo {
+    /// 文件路径，从内存构造时为 `None`
+    pub path: Option<PathBuf>,
+    /// 文件字节数
+    pub size: u64,
+    /// 文件内容的 SHA-256
+    pub sha256: [u8; 32],
+}
+
+impl SourceInfo {
+    /// 计算内存中模型文件的来源信息。
+    pub fn new(content: &[u8], path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            size: content.len() as _,
+            sha256: Sha256::digest(content).into(),
+        }
+    }
+
+    /// 读取模型文件并计算来源信息。
+    ///
+    /// 如果提供了 `expected_sha256`（十六进制，不区分大小写），校验失败时返回 [`io::ErrorKind::InvalidData`]。
+    pub fn load(
+        path: impl AsRef<Path>,
+        expected_sha256: Option<&str>,
+    ) -> io::Result<(Vec<u8>, Self)> {
+        let path = path.as_ref();
+        let content = fs::read(path)?;
+        let info = Self::new(&content, Some(path.to_path_buf()));
+        if let Some(expected) = expected_sha256 {
+            info.verify(expected)?;
+        }
+        Ok((content, info))
+    }
+
+    /// 校验 SHA-256 是否与十六进制字符串一致。
+    pub fn verify(&self, expected_sha256: &str) -> io::Result<()> {
+        let actual = self.sha256_hex();
+        if actual.eq_ignore_ascii_case(expected_sha256.trim()) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("sha256 mismatch: expected {expected_sha256}, got {actual}"),
+            ))
+        }
+    }
+
+    /// 十六进制表示的 SHA-256。
+    pub fn sha256_hex(&self) -> String {
+        self.sha256.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+impl fmt::Display for SourceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}", path.display())?,
+            None => write!(f, "<memory>")?,
+        }
+        write!(f, " ({} bytes, sha256 {})", self.size, self.sha256_hex())
+    }
+}
+
+#[cfg(test)]
+mod source_tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let info = SourceInfo::new(b"abc", None);
+        assert_eq!(info.size, 3);
+        let hex = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(info.sha256_hex(), hex);
+        assert!(info.verify(&hex.to_uppercase()).is_ok());
+        assert!(info.verify(&hex[1..]).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+//! 这个模块生成用于跨实现一致性检查的测试向量。
+//!
+//! 采样的输入覆盖混合文字、表情符号、utf-8 编码边界附近的字符和形似特殊词的文本，
+//! 输出为 JSON 文件，记录每个输入由本库编码得到的词序列，其他语言的实现可据此逐字节核对：
+//!
+//! ```json
+//! {
+//!   "fingerprint": "0123456789abcdef",
+//!   "vectors": [
+//!     { "input": "Hello世界", "ids": [1, 2, 3] }
+//!   ]
+//! }
+//! ```
+//!
+//! [`sample_vocab_text`] 和 [`sample_adversarial_text`] 则按词表采样保证可以往返编解码的文本，
+//! 供下游的性质测试使用。
+
+use crate::{utok, Method, Tokeneer};
+use std::io::{self, Write};
+
+/// 多种文字
+const SCRIPTS: &[&str] = &[
+    "Hello",
+    "world",
+    "世界",
+    "分词",
+    "Привет",
+    "مرحبا",
+    "नमस्ते",
+    "こんにちは",
+    "한국어",
+    "Ελλάδα",
+];
+
+/// 表情符号，包括肤色修饰、零宽连接序列、旗帜和变体选择符
+const EMOJI: &[&str] = &["😀", "👍🏽", "👨\u{200D}👩\u{200D}👧", "🇨🇳", "❤\u{FE0F}"];
+
+/// utf-8 各编码长度的边界字符，以及代理区两侧和最大码点
+const BOUNDARY: &[&str] = &[
+    "\0",
+    "\u{7F}",
+    "\u{80}",
+    "\u{7FF}",
+    "\u{800}",
+    "\u{D7FF}",
+    "\u{E000}",
+    "\u{FFFD}",
+    "\u{FFFF}",
+    "\u{10000}",
+    "\u{10FFFF}",
+];
+
+/// 空白字符
+const SPACES: &[&str] = &[
+    " ", "  ", "\t", "\n", "\r\n", "\u{A0}", "\u{3000}", "\u{200B}",
+];
+
+/// 常见的形似特殊词的文本
+const LOOKALIKES: &[&str] = &["<", ">", "<|", "|>", "<s", "</s>", "<|im_start|", "<0x41>"];
+
+/// 以 `seed` 为种子采样 `count` 个输入，相同的参数总是得到相同的结果。
+///
+/// 每个片段先单独作为一个输入，其余输入由 1 到 8 个随机片段拼接而成。
+/// `specials` 中的特殊词及其变形也会作为片段参与采样。
+pub fn sample_inputs<'a>(
+    specials: impl IntoIterator<Item = &'a str>,
+    count: usize,
+    seed: u64,
+) -> Vec<String> {
+    let mut fragments = [SCRIPTS, EMOJI, BOUNDARY, SPACES, LOOKALIKES]
+        .concat()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    for special in specials {
+        let mut chars = special.chars();
+        chars.next_back();
+        fragments.extend([
+            special.to_string(),
+            // 截断、插入空白、大小写变化、重复开头
+            chars.as_str().to_string(),
+            special.replacen(|c: char| c.is_alphanumeric(), " ", 1),
+            special.to_uppercase(),
+            format!(
+                "{}{special}",
+                &special[..special.chars().next().map_or(0, char::len_utf8)]
+            ),
+        ]);
+    }
+    fragments.retain(|s| !s.is_empty());
+    fragments.sort_unstable();
+    fragments.dedup();
+
+    let mut rng = SplitMix64(seed);
+    let mut ans = fragments.clone();
+    ans.truncate(count);
+    while ans.len() < count {
+        let n = rng.below(8) + 1;
+        ans.push(
+            (0..n)
+                .map(|_| &*fragments[rng.below(fragments.len())])
+                .collect(),
+        );
+    }
+    ans
+}
+
+/// 编码每个输入，并将输入和词序列写为 JSON。
+pub fn write_test_vectors<'a, M: Method>(
+    mut w: impl Write,
+    tokeneer: &Tokeneer<M>,
+    inputs: impl IntoIterator<Item = &'a str>,
+) -> io::Result<()> {
+    writeln!(w, "{{")?;
+    writeln!(w, "  \"fingerprint\": \"{:016x}\",", tokeneer.fingerprint())?;
+    write!(w, "  \"vectors\": [")?;
+    for (i, input) in inputs.into_iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "\n    {{ \"input\": \"{}\", \"ids\": [", JsonStr(input))?;
+        for (j, t) in tokeneer.encode(input).into_iter().enumerate() {
+            if j > 0 {
+                write!(w, ", ")?;
+            }
+            write!(w, "{t}")?;
+        }
+        write!(w, "] }}")?;
+    }
+    writeln!(w, "\n  ]")?;
+    writeln!(w, "}}")
+}
+
+/// 以分词器注册的特殊词采样 `count` 个输入并写出测试向量。
+pub fn generate<M: Method>(
+    w: impl Write,
+    tokeneer: &Tokeneer<M>,
+    count: usize,
+    seed: u64,
+) -> io::Result<()> {
+    // 特殊词的遍历顺序不确定，排序以保证结果可复现
+    let mut specials = tokeneer
+        .special_tokens()
+        .map(|(k, _)| k)
+        .collect::<Vec<_>>();
+    specials.sort_unstable();
+    let inputs = sample_inputs(specials, count, seed);
+    write_test_vectors(w, tokeneer, inputs.iter().map(String::as_str))
+}
+
+/// 按词表采样 `count` 段类似语料的文本，相同的参数总是得到相同的结果。
+///
+/// 每段文本由 1 到 16 个随机的一般词解码拼接而成，跳过 <unk>、单字节词和不是合法 utf-8 的词。
+/// 只保留编码再解码后与自身相同的文本；词表中可用的词太少时，结果可能少于 `count` 段。
+pub fn sample_vocab_text(method: &impl Method, count: usize, seed: u64) -> Vec<String> {
+    let pieces = text_pieces(method);
+    let mut rng = SplitMix64(seed);
+    sample_round_trip(method, count, |text| {
+        for _ in 0..rng.below(16) + 1 {
+            text.push_str(pieces.get(rng.below(pieces.len().max(1)))?)
+        }
+        Some(())
+    })
+}
+
+/// 按词表采样 `count` 段对抗性的文本，相同的参数总是得到相同的结果。
+///
+/// 每段文本由 1 到 8 个片段拼接而成，片段可能是：
+///
+/// - 一个一般词的内容；
+/// - 一个一般词在字符边界处切开后的前半或后半，使相邻的词跨越原本的合并边界；
+/// - 由单字节词拼成的字符，这个字符不在词表中，编码时必须按字节回退；
+/// - utf-8 各编码长度的边界字符。
+///
+/// 只保留编码再解码后与自身相同的文本；词表中可用的词太少时，结果可能少于 `count` 段。
+pub fn sample_adversarial_text(method: &impl Method, count: usize, seed: u64) -> Vec<String> {
+    let pieces = text_pieces(method);
+    let bytes = (0..method.vocab_size() as utok)
+        .filter_map(|t| method.fallback_byte(t))
+        .collect::<Vec<_>>();
+    let mut rng = SplitMix64(seed);
+    sample_round_trip(method, count, |text| {
+        for _ in 0..rng.below(8) + 1 {
+            match rng.below(4) {
+                0 => text.push_str(pieces.get(rng.below(pieces.len().max(1)))?),
+                1 => {
+                    let piece = *pieces.get(rng.below(pieces.len().max(1)))?;
+                    let mut cut = rng.below(piece.len() + 1);
+                    while !piece.is_char_boundary(cut) {
+                        cut -= 1
+                    }
+                    let (head, tail) = piece.split_at(cut);
+                    text.push_str(if rng.below(2) == 0 { head } else { tail })
+                }
+                2 => {
+                    // 从单字节词中随机挑选首字节和后续字节，直到拼成一个合法的字符
+                    let c = (0..16).find_map(|_| {
+                        let lead = *bytes.get(rng.below(bytes.len().max(1)))?;
+                        let len = match lead {
+                            0x00..=0x7F => 1,
+                            0xC2..=0xDF => 2,
+                            0xE0..=0xEF => 3,
+                            0xF0..=0xF4 => 4,
+                            _ => return None,
+                        };
+                        let mut buf = vec![lead];
+                        buf.extend((1..len).map(|_| bytes[rng.below(bytes.len())]));
+                        String::from_utf8(buf).ok()
+                    })?;
+                    let fallback = method
+                        .encode(&c)
+                        .into_iter()
+                        .all(|t| method.fallback_byte(t).is_some());
+                    if fallback {
+                        text.push_str(&c)
+                    }
+                }
+                _ => text.push_str(BOUNDARY[rng.below(BOUNDARY.len())]),
+            }
+        }
+        Some(())
+    })
+}
+
+/// 词表中可以作为文本的一般词的内容。
+fn text_pieces(method: &impl Method) -> Vec<&str> {
+    (0..method.vocab_size() as utok)
+        .filter(|&t| Some(t) != method.unk_token() && method.fallback_byte(t).is_none())
+        .filter_map(|t| std::str::from_utf8(method.decode(t)).ok())
+        .filter(|piece| !piece.is_empty())
+        .collect()
+}
+
+/// 反复调用 `fill` 生成文本，只保留非空且可以往返编解码的文本，至多尝试 `count` 的 16 倍次。
+///
+/// `fill` 返回 `None` 表示无法生成，直接放弃这一次尝试。
+fn sample_round_trip(
+    method: &impl Method,
+    count: usize,
+    mut fill: impl FnMut(&mut String) -> Option<()>,
+) -> Vec<String> {
+    let mut ans = Vec::with_capacity(count);
+    for _ in 0..count * 16 {
+        if ans.len() == count {
+            break;
+        }
+        let mut text = String::new();
+        if fill(&mut text).is_none() || text.is_empty() {
+            continue;
+        }
+        let decoded = method
+            .encode(&text)
+            .into_iter()
+            .flat_map(|t| method.decode(t).iter().copied())
+            .collect::<Vec<_>>();
+        if decoded == text.as_bytes() {
+            ans.push(text)
+        }
+    }
+    ans
+}
+
+/// 转义为 JSON 字符串的内容，非 ascii 字符保持原样。
+struct JsonStr<'a>(&'a str);
+
+impl std::fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 不依赖外部库的伪随机数生成器，保证不同平台上的采样结果一致。
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod testvec_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_sample_vocab_text() {
+        let bpe = crate::Bpe::new(
+            [
+                "<unk>", "<0xE4>", "<0xBD>", "<0xA0>", "<0x41>", "a", "b", "ab", "世界", "你好",
+            ],
+            [0., 0., 0., 0., 0., 1., 1., 2., 1., 1.],
+            [
+                false, true, true, true, true, false, false, false, false, false,
+            ],
+            0,
+        );
+        let round_trip = |text: &str| {
+            let tokens = bpe.encode(text).into_iter().collect::<Vec<_>>();
+            assert!(!tokens.contains(&0));
+            let decoded = tokens.iter().flat_map(|&t| bpe.decode(t)).copied();
+            assert_eq!(decoded.collect::<Vec<_>>(), text.as_bytes());
+        };
+        let corpus = sample_vocab_text(&bpe, 100, 3);
+        assert_eq!(corpus, sample_vocab_text(&bpe, 100, 3));
+        assert_eq!(corpus.len(), 100);
+        corpus.iter().for_each(|text| round_trip(text));
+
+        let adversarial = sample_adversarial_text(&bpe, 100, 3);
+        assert_eq!(adversarial.len(), 100);
+        adversarial.iter().for_each(|text| round_trip(text));
+        // 切开的词和按字节回退的字符都会出现
+        assert!(adversarial
+            .iter()
+            .any(|text| text.contains('你') && !text.contains("你好")));
+        assert!(adversarial.iter().any(|text| text.contains('A')));
+    }
+
+    #[test]
+    fn test_generate() {
+        let lpe = Lpe::new(
+            ["<unk>", "<s>", "a", "b", "ab", "\"", "世界"].map(str::as_bytes),
+            0,
+        );
+        let tokeneer = Tokeneer::new(lpe);
+        assert_eq!(
+            sample_inputs(["<s>"], 200, 7),
+            sample_inputs(["<s>"], 200, 7)
+        );
+        assert!(sample_inputs(["<s>"], 200, 7).contains(&"<S>".to_string()));
+
+        let mut buf = Vec::new();
+        generate(&mut buf, &tokeneer, 200, 7).unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&buf).unwrap();
+        let vectors = json["vectors"].as_array().unwrap();
+        assert_eq!(vectors.len(), 200);
+        for v in vectors {
+            let input = v["input"].as_str().unwrap();
+            let ids = v["ids"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|t| t.as_u64().unwrap() as u32)
+                .collect::<Vec<_>>();
+            assert_eq!(ids, tokeneer.encode(input));
+        }
+    }
+}
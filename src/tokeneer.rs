@@ -1,18 +1,258 @@
-﻿use crate::{utok, Method};
-use regex::Regex;
+use crate::{
+    hash::StableHasher,
+    matcher::{SpecialMatcher, REGEX_LIMIT},
+    memory::hash_map_size,
+    normalizer::OffsetMap,
+    utok,
+    vocab::BYTE_ESCAPES,
+    DecodePreset, EncodeOptions, Encoding, MemoryUsage, Method, Normalizer, PostProcessor,
+    Truncation,
+};
 use std::{
-    collections::{HashMap, HashSet},
-    ops::Deref,
+    borrow::Cow,
+    collections::HashMap,
+    hash::Hasher,
+    io,
+    iter::zip,
+    ops::{Deref, Range},
     slice::from_ref,
-    sync::LazyLock,
+    sync::OnceLock,
+    time::Instant,
 };
 
+#[derive(Clone)]
 pub struct Tokeneer<M> {
     method: M,
-    special: HashMap<String, TokenSeq>,
-    special_regex: Regex,
+    special: HashMap<String, SpecialToken>,
+    /// 含有占位符的特殊词，以占位符之前的固定文本为键
+    templates: HashMap<String, SpecialTemplate>,
+    /// 特殊词的名字 -> 特殊词的文本
+    special_names: HashMap<String, String>,
+    /// 特殊词的匹配器，特殊词变化后首次查找时重新构造
+    special_matcher: OnceLock<Option<SpecialMatcher>>,
+    /// 使用正则表达式匹配的特殊词数量上限
+    special_regex_limit: usize,
+    /// 占位符匹配的文本长度上限
+    capture_limit: usize,
+    /// 编码时复用线程内的缓冲区
+    thread_workspace: bool,
+    /// 编码时的输入长度上限
+    input_limit: Option<InputLimit>,
+    normalizer: Normalizer,
+    truncation: Option<Truncation>,
+    /// 详细编码的后处理
+    post_processor: Option<PostProcessor>,
+    unk_policy: UnkPolicy,
+    /// 超出词表的词序号的占位符
+    unk_placeholder: String,
+    /// 解码时将单字节词转义为 `<0xAB>`
+    escape_bytes: bool,
+    /// 解码后处理
+    decode_preset: DecodePreset,
+    /// 词表的指纹
+    vocab_fingerprint: u64,
+    /// 词表和特殊词的指纹，特殊词变化后首次使用时重新计算
+    fingerprint: OnceLock<u64>,
+    /// 模型文件的来源信息
+    #[cfg(feature = "checksum")]
+    source: Option<crate::SourceInfo>,
+}
+
+/// 解码时 <unk> 和超出词表的词序号的处理方式。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum UnkPolicy {
+    /// 解码为词表中 <unk> 的内容，词表没有 <unk> 时解码为空字符串
+    #[default]
+    Piece,
+    /// 解码为空字符串
+    Empty,
+    /// 解码失败，见 [`Tokeneer::try_decode`]
+    Error,
+    /// <unk> 解码为其内容，超出词表的词序号解码为占位符，用于从损坏的记录中尽量解码，
+    /// 见 [`Tokeneer::set_unk_placeholder`]
+    Placeholder,
+}
+
+/// 按 [`UnkPolicy::Error`] 解码时遇到的未知词。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DecodeError {
+    /// 未知词的序号
+    pub token: utok,
+    /// 未知词在输入中的位置
+    pub index: usize,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown token {} at index {}", self.token, self.index)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// 输入超过长度上限时的处理方式，见 [`Tokeneer::set_input_limit`]。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputLimit {
+    /// 将超过上限的一般文本段切成不超过上限的块分别编码，块之间不会合并
+    Chunk(usize),
+    /// 拒绝编码规范化后超过上限的文本，见 [`Tokeneer::try_encode`]
+    Reject(usize),
+}
+
+/// 输入超过 [`InputLimit::Reject`] 的上限。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InputTooLong {
+    /// 规范化后的文本字节数
+    pub len: usize,
+    /// 上限
+    pub limit: usize,
+}
+
+impl std::fmt::Display for InputTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "input of {} bytes exceeds the limit of {} bytes",
+            self.len, self.limit
+        )
+    }
+}
+
+impl std::error::Error for InputTooLong {}
+
+/// 注册特殊词时发现的冲突，见 [`Tokeneer::try_extend_special`]。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SpecialTokenError {
+    /// 特殊词引用了超出词表的词序号
+    OutOfRange { special: String, token: utok },
+    /// 特殊词已经注册或在同一批中出现为不同的词序列
+    Redefined { special: String },
+    /// 只含一个词的特殊词同时可以由一般文本编码得到
+    Reachable { special: String, token: utok },
+}
+
+impl std::fmt::Display for SpecialTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::OutOfRange { special, token } => {
+                write!(
+                    f,
+                    "special {special:?} refers to out-of-range token {token}"
+                )
+            }
+            Self::Redefined { special } => {
+                write!(f, "special {special:?} is already registered differently")
+            }
+            Self::Reachable { special, token } => {
+                write!(
+                    f,
+                    "special {special:?} maps to token {token}, which is also a normal piece"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpecialTokenError {}
+
+/// 注册的特殊词。
+#[derive(Clone)]
+struct SpecialToken {
+    tokens: TokenSeq,
+    /// 匹配时吞掉左侧的空白
+    lstrip: bool,
+    /// 匹配时吞掉右侧的空白
+    rstrip: bool,
+    /// 只匹配独立的词，两侧不能紧邻字母、数字或下划线
+    single_word: bool,
+}
+
+impl SpecialToken {
+    #[inline]
+    fn new(tokens: TokenSeq) -> Self {
+        Self {
+            tokens,
+            lstrip: false,
+            rstrip: false,
+            single_word: false,
+        }
+    }
+}
+
+impl Deref for SpecialToken {
+    type Target = [utok];
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.tokens
+    }
 }
 
+/// 特殊词的词序列中标记占位符位置的词序号，见 [`Tokeneer::extend_special`]。
+pub const SPECIAL_CAPTURE: utok = utok::MAX;
+
+/// 含有占位符的特殊词，例如 `<tool:{name}>`。
+#[derive(Clone)]
+struct SpecialTemplate {
+    /// 注册时的完整模式
+    pattern: String,
+    /// 占位符之后的固定文本
+    suffix: String,
+    /// 注册时的词序列，包含一个 [`SPECIAL_CAPTURE`]
+    tokens: Box<[utok]>,
+    /// 标记的位置，之前的词插入到占位符之前，之后的词插入到占位符之后
+    capture: usize,
+}
+
+impl SpecialTemplate {
+    /// 模式含有占位符并且词序列恰好含有一个标记时构造。
+    fn new(pattern: &str, tokens: &[utok]) -> Option<(String, Self)> {
+        let (prefix, suffix) = split_capture(pattern)?;
+        let mut marks = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, &t)| t == SPECIAL_CAPTURE);
+        let (capture, _) = marks.next()?;
+        if marks.next().is_some() {
+            return None;
+        }
+        Some((
+            prefix.into(),
+            Self {
+                pattern: pattern.into(),
+                suffix: suffix.into(),
+                tokens: tokens.into(),
+                capture,
+            },
+        ))
+    }
+
+    #[inline]
+    fn before(&self) -> &[utok] {
+        &self.tokens[..self.capture]
+    }
+
+    #[inline]
+    fn after(&self) -> &[utok] {
+        &self.tokens[self.capture + 1..]
+    }
+}
+
+/// 将模式拆分为占位符前后的固定文本，两段都不能为空。
+///
+/// 占位符形如 `{name}`，`name` 由 ascii 字母、数字和下划线组成。
+fn split_capture(pattern: &str) -> Option<(&str, &str)> {
+    let open = pattern.find('{')?;
+    let close = open + pattern[open..].find('}')?;
+    let name = &pattern[open + 1..close];
+    let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+    (!prefix.is_empty()
+        && !suffix.is_empty()
+        && !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'))
+    .then_some((prefix, suffix))
+}
+
+#[derive(Clone)]
 enum TokenSeq {
     Single(utok),
     Multi(Box<[utok]>),
@@ -29,89 +269,1989 @@ impl Deref for TokenSeq {
     }
 }
 
+/// 文本中的一段，要么是匹配到的特殊词，要么是一般文本及其编码。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Segment<'t, 's> {
+    /// 特殊词，直接映射到注册的词序列
+    Special { text: &'t str, tokens: &'s [utok] },
+    /// 一般文本，由分词算法编码
+    Normal { text: &'t str, tokens: Vec<utok> },
+}
+
+impl<'t> Segment<'t, '_> {
+    /// 这一段的原始文本。
+    #[inline]
+    pub fn text(&self) -> &'t str {
+        match self {
+            Self::Special { text, .. } | Self::Normal { text, .. } => text,
+        }
+    }
+
+    /// 这一段编码得到的词序列。
+    #[inline]
+    pub fn tokens(&self) -> &[utok] {
+        match self {
+            Self::Special { tokens, .. } => tokens,
+            Self::Normal { tokens, .. } => tokens,
+        }
+    }
+
+    #[inline]
+    pub fn is_special(&self) -> bool {
+        matches!(self, Self::Special { .. })
+    }
+}
+
+/// [`Tokeneer::split`] 返回的迭代器。
+pub struct Split<'s, 't, M> {
+    tokeneer: &'s Tokeneer<M>,
+    text: &'t str,
+    /// 已经找到但尚未返回的段：(所占范围, 特殊词的词序列)，按顺序返回
+    pending: [Option<Span<'s>>; 3],
+    start: usize,
+}
+
 impl<M: Method> Tokeneer<M> {
     pub fn new(method: M) -> Self {
         let special = method
             .internal_special()
             .into_iter()
             .filter(|(k, _)| k.is_ascii())
-            .map(|(k, v)| (k.to_string(), TokenSeq::Single(v)))
+            .map(|(k, v)| (k.to_string(), SpecialToken::new(TokenSeq::Single(v))))
             .collect::<HashMap<_, _>>();
-        let special_regex = build_pattern(special.keys());
+        let vocab_fingerprint = vocab_fingerprint(&method);
         Self {
             method,
             special,
-            special_regex,
+            templates: HashMap::new(),
+            special_names: HashMap::new(),
+            special_matcher: OnceLock::new(),
+            special_regex_limit: REGEX_LIMIT,
+            capture_limit: CAPTURE_LIMIT,
+            thread_workspace: true,
+            input_limit: None,
+            normalizer: Normalizer::default(),
+            truncation: None,
+            post_processor: None,
+            unk_policy: UnkPolicy::default(),
+            unk_placeholder: UNK_PLACEHOLDER.into(),
+            escape_bytes: false,
+            decode_preset: DecodePreset::default(),
+            vocab_fingerprint,
+            fingerprint: OnceLock::new(),
+            #[cfg(feature = "checksum")]
+            source: None,
         }
     }
 
+    /// 编码文本。
+    ///
+    /// 设置了 [`InputLimit::Reject`] 且文本超过上限时 panic，需要处理错误时使用 [`try_encode`](Self::try_encode)。
     pub fn encode(&self, text: &str) -> Vec<utok> {
+        self.encode_with(text, EncodeOptions::default())
+    }
+
+    /// 编码文本，设置了 [`InputLimit::Reject`] 且规范化后的文本超过上限时返回错误。
+    pub fn try_encode(&self, text: &str) -> Result<Vec<utok>, InputTooLong> {
+        self.try_encode_with(text, EncodeOptions::default())
+    }
+
+    /// 编码文本，并给出每个词在输入文本中的字节范围。
+    ///
+    /// 编码作用于规范化后的文本，范围映射回规范化之前的输入：由 `\r\n` 转换而来的 `\n` 覆盖整个 `\r\n`，
+    /// 去除的 BOM 不属于任何词。
+    /// 特殊词的所有词共用特殊词所占的范围；一般文本段中的词按解码长度依次对应，
+    /// 解码总长度与文本段不符（例如包含 <unk>）时，段内所有词共用整段的范围。
+    /// 文本超过 [`InputLimit::Reject`] 的上限时 panic。
+    pub fn encode_with_offsets(&self, text: &str) -> Vec<(utok, Range<usize>)> {
+        or_panic(self.try_encode_with_offsets(text))
+    }
+
+    /// 同 [`encode_with_offsets`](Self::encode_with_offsets)，文本超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_encode_with_offsets(
+        &self,
+        text: &str,
+    ) -> Result<Vec<(utok, Range<usize>)>, InputTooLong> {
+        let (text, map) = self.normalizer.normalize_with_map(text);
+        self.check_input(&text)?;
         let mut ans = Vec::new();
         let mut start = 0;
-        if !self.special_regex.as_str().is_empty() {
-            for m in self.special_regex.find_iter(text) {
-                ans.extend(self.method.encode(&text[start..m.start()]));
-                ans.extend_from_slice(&self.special[m.as_str()]);
-                start = m.end();
+        for segment in self.split(&text) {
+            ans.extend(zip(
+                segment.tokens().iter().copied(),
+                self.segment_offsets(&segment, start, &map),
+            ));
+            start += segment.text().len()
+        }
+        Ok(ans)
+    }
+
+    /// 一段中每个词在原文本中的字节范围，`start` 是这一段在规范化后的文本中的起始位置。
+    fn segment_offsets(
+        &self,
+        segment: &Segment,
+        start: usize,
+        map: &OffsetMap,
+    ) -> Vec<Range<usize>> {
+        let end = start + segment.text().len();
+        let tokens = segment.tokens();
+        let lens = tokens
+            .iter()
+            .map(|&t| self.internal().decode(t).len())
+            .collect::<Vec<_>>();
+        let original = |range: Range<usize>| map.original(range.start)..map.original(range.end);
+        match segment {
+            Segment::Normal { .. } if lens.iter().sum::<usize>() == end - start => lens
+                .into_iter()
+                .scan(start, |pos, len| {
+                    *pos += len;
+                    Some(original(*pos - len..*pos))
+                })
+                .collect(),
+            _ => vec![original(start..end); tokens.len()],
+        }
+    }
+
+    /// 按选项编码文本，选项只作用于一般文本段。
+    ///
+    /// 是否复用线程内的缓冲区由 [`set_thread_workspace`](Self::set_thread_workspace) 决定，
+    /// 超长输入的处理方式由 [`set_input_limit`](Self::set_input_limit) 决定。
+    pub fn encode_with(&self, text: &str, options: EncodeOptions) -> Vec<utok> {
+        or_panic(self.try_encode_with(text, options))
+    }
+
+    /// 同 [`encode_with`](Self::encode_with)，文本超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_encode_with(
+        &self,
+        text: &str,
+        options: EncodeOptions,
+    ) -> Result<Vec<utok>, InputTooLong> {
+        let mut ans = Vec::new();
+        self.try_encode_into(text, options, &mut ans)?;
+        Ok(ans)
+    }
+
+    /// 编码文本并追加到 `ans`，出错时 `ans` 不变。
+    pub(crate) fn try_encode_into(
+        &self,
+        text: &str,
+        options: EncodeOptions,
+        ans: &mut Vec<utok>,
+    ) -> Result<(), InputTooLong> {
+        let mut options = options.with_workspace(self.thread_workspace);
+        let text = &*self.normalizer.normalize(text);
+        self.check_input(text)?;
+        let mut split = self.split(text);
+        while let Some((range, special)) = split.next_span() {
+            match special {
+                Some(tokens) => ans.extend_from_slice(tokens),
+                None => self.encode_segment_with(&text[range], &mut options, ans),
             }
         }
-        ans.extend(self.method.encode(&text[start..]));
-        ans
+        Ok(())
+    }
+
+    /// 按输入长度上限检查规范化后的文本。
+    pub(crate) fn check_input(&self, text: &str) -> Result<(), InputTooLong> {
+        match self.input_limit {
+            Some(InputLimit::Reject(limit)) if text.len() > limit => Err(InputTooLong {
+                len: text.len(),
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// 编码一般文本段时分块的字节数，没有设置 [`InputLimit::Chunk`] 时不分块。
+    fn chunk_size(&self) -> usize {
+        match self.input_limit {
+            Some(InputLimit::Chunk(limit)) => limit.max(1),
+            _ => usize::MAX,
+        }
+    }
+
+    /// 编码一般文本段，按 [`InputLimit::Chunk`] 分块编码。
+    fn encode_segment<'a>(&'a self, text: &'a str) -> impl Iterator<Item = utok> + 'a {
+        chunks(text, self.chunk_size()).flat_map(|piece| self.method.encode(piece))
+    }
+
+    /// 按选项编码一般文本段并追加到 `ans`，分块方式与 [`encode_segment`](Self::encode_segment) 相同。
+    fn encode_segment_with(&self, text: &str, options: &mut EncodeOptions, ans: &mut Vec<utok>) {
+        for piece in chunks(text, self.chunk_size()) {
+            ans.extend(self.method.encode_with(piece, options.reborrow()))
+        }
     }
 
+    /// 编码文本，并记录每个词是否来自特殊词。
+    ///
+    /// 如果配置了截断，结果会截断到最大长度；如果配置了后处理，截断后按模板插入特殊词。
+    /// 文本超过 [`InputLimit::Reject`] 的上限时 panic。
+    pub fn encode_detailed(&self, text: &str) -> Encoding {
+        or_panic(self.try_encode_detailed(text))
+    }
+
+    /// 同 [`encode_detailed`](Self::encode_detailed)，文本超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_encode_detailed(&self, text: &str) -> Result<Encoding, InputTooLong> {
+        let ans = self.encode_sequence(text, 0)?;
+        Ok(self.finish(ans))
+    }
+
+    /// 编码一对文本，依次拼接两段的编码结果，并以序号 0 和 1 区分两段。
+    ///
+    /// 如果配置了截断，按截断策略分别截断两段；如果配置了后处理，截断后按模板插入特殊词。
+    /// 任一段超过 [`InputLimit::Reject`] 的上限时 panic。
+    pub fn encode_pair(&self, first: &str, second: &str) -> Encoding {
+        or_panic(self.try_encode_pair(first, second))
+    }
+
+    /// 同 [`encode_pair`](Self::encode_pair)，任一段超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_encode_pair(&self, first: &str, second: &str) -> Result<Encoding, InputTooLong> {
+        let mut first = self.encode_sequence(first, 0)?;
+        let mut second = self.encode_sequence(second, 1)?;
+        if let Some(truncation) = self.truncation(true) {
+            let (len0, len1) = truncation.apply(first.len(), second.len());
+            first.truncate(len0);
+            second.truncate(len1);
+        }
+        Ok(match &self.post_processor {
+            Some(post_processor) => post_processor.apply(first, Some(second)),
+            None => {
+                first.append(second);
+                first
+            }
+        })
+    }
+
+    /// 编码已经预先分好的词，每个词独立编码，词之间不会合并，并记录每个词所属的预分词序号。
+    ///
+    /// 如果配置了截断，结果会截断到最大长度；如果配置了后处理，截断后按模板插入特殊词。
+    /// 任一个词超过 [`InputLimit::Reject`] 的上限时 panic。
+    pub fn encode_pretokenized(&self, words: &[&str]) -> Encoding {
+        or_panic(self.try_encode_pretokenized(words))
+    }
+
+    /// 同 [`encode_pretokenized`](Self::encode_pretokenized)，任一个词超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_encode_pretokenized(&self, words: &[&str]) -> Result<Encoding, InputTooLong> {
+        let mut ans = Encoding::default();
+        for (i, word) in words.iter().enumerate() {
+            self.encode_into(&mut ans, word, 0, Some(i))?;
+        }
+        Ok(self.finish(ans))
+    }
+
+    /// 截断并后处理一段输入的编码结果。
+    fn finish(&self, mut ans: Encoding) -> Encoding {
+        if let Some(truncation) = self.truncation(false) {
+            let (len, _) = truncation.apply(ans.len(), 0);
+            ans.truncate(len);
+        }
+        match &self.post_processor {
+            Some(post_processor) => post_processor.apply(ans, None),
+            None => ans,
+        }
+    }
+
+    /// 扣除后处理插入的特殊词后，输入的编码结果可用的截断配置。
+    fn truncation(&self, pair: bool) -> Option<Truncation> {
+        let mut truncation = self.truncation?;
+        if let Some(post_processor) = &self.post_processor {
+            truncation.max_length = truncation
+                .max_length
+                .saturating_sub(post_processor.added_tokens(pair));
+        }
+        Some(truncation)
+    }
+
+    fn encode_sequence(&self, text: &str, sequence: usize) -> Result<Encoding, InputTooLong> {
+        let mut ans = Encoding::default();
+        self.encode_into(&mut ans, text, sequence, None)?;
+        Ok(ans)
+    }
+
+    fn encode_into(
+        &self,
+        ans: &mut Encoding,
+        text: &str,
+        sequence: usize,
+        word: Option<usize>,
+    ) -> Result<(), InputTooLong> {
+        let (text, map) = self.normalizer.normalize_with_map(text);
+        self.check_input(&text)?;
+        let mut start = 0;
+        for segment in self.split(&text) {
+            let offsets = self.segment_offsets(&segment, start, &map);
+            start += segment.text().len();
+            ans.push(
+                segment.tokens(),
+                offsets,
+                segment.is_special(),
+                sequence,
+                word,
+            );
+        }
+        Ok(())
+    }
+
+    /// 编码文本，词数达到 `max_tokens` 时停止。
+    ///
+    /// 返回词序列、已编码的字节数和是否还有未编码的内容，字节数按规范化后的文本计算。
+    /// 特殊词对应的词序列不会被截断，放不下时整体留给后续处理。
+    /// 编码在达到上限后停止处理后续文本；但 Bpe 的合并依赖整段文本，最后一段一般文本仍然完整合并。
+    /// 文本超过 [`InputLimit::Reject`] 的上限时 panic。
+    pub fn encode_bounded(&self, text: &str, max_tokens: usize) -> (Vec<utok>, usize, bool) {
+        or_panic(self.try_encode_bounded(text, max_tokens))
+    }
+
+    /// 同 [`encode_bounded`](Self::encode_bounded)，文本超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_encode_bounded(
+        &self,
+        text: &str,
+        max_tokens: usize,
+    ) -> Result<(Vec<utok>, usize, bool), InputTooLong> {
+        let text = &*self.normalizer.normalize(text);
+        self.check_input(text)?;
+        let unk = self.method.unk_token();
+        let mut tokens = Vec::new();
+        let mut split = self.split(text);
+        while let Some((range, special)) = split.next_span() {
+            match special {
+                Some(special) => {
+                    if tokens.len() + special.len() > max_tokens {
+                        return Ok((tokens, range.start, true));
+                    }
+                    tokens.extend_from_slice(special)
+                }
+                None => {
+                    let mut pos = range.start;
+                    for t in self.encode_segment(&text[range]) {
+                        if tokens.len() == max_tokens {
+                            return Ok((tokens, pos, true));
+                        }
+                        tokens.push(t);
+                        // <unk> 总是代替 1 个无法编码的字节
+                        pos += if Some(t) == unk {
+                            1
+                        } else {
+                            self.method.decode(t).len()
+                        }
+                    }
+                }
+            }
+        }
+        Ok((tokens, text.len(), false))
+    }
+
+    /// 编码文本，`cancelled` 返回 `true` 时尽快停止，用于限制恶意长文本的最坏编码时间。
+    ///
+    /// 返回词序列、已编码的字节数和是否被取消，字节数按规范化后的文本计算。
+    /// 被中断的一般文本段的结果不完整，整段丢弃，因此返回的词序列总是完整编码结果的前缀，
+    /// 从返回的位置继续编码即可得到剩余部分。文本超过 [`InputLimit::Reject`] 的上限时 panic。
+    pub fn encode_cancellable(
+        &self,
+        text: &str,
+        cancelled: impl Fn() -> bool,
+    ) -> (Vec<utok>, usize, bool) {
+        or_panic(self.try_encode_cancellable(text, cancelled))
+    }
+
+    /// 同 [`encode_cancellable`](Self::encode_cancellable)，文本超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_encode_cancellable(
+        &self,
+        text: &str,
+        cancelled: impl Fn() -> bool,
+    ) -> Result<(Vec<utok>, usize, bool), InputTooLong> {
+        let text = &*self.normalizer.normalize(text);
+        self.check_input(text)?;
+        let mut options = EncodeOptions::default()
+            .with_cancel(&cancelled)
+            .with_workspace(self.thread_workspace);
+        let mut tokens = Vec::new();
+        let mut split = self.split(text);
+        while let Some((range, special)) = split.next_span() {
+            if cancelled() {
+                return Ok((tokens, range.start, true));
+            }
+            match special {
+                Some(special) => tokens.extend_from_slice(special),
+                None => {
+                    let len = tokens.len();
+                    self.encode_segment_with(&text[range.clone()], &mut options, &mut tokens);
+                    if cancelled() {
+                        tokens.truncate(len);
+                        return Ok((tokens, range.start, true));
+                    }
+                }
+            }
+        }
+        Ok((tokens, text.len(), false))
+    }
+
+    /// 编码文本，超过 `deadline` 时尽快停止，参见 [`encode_cancellable`](Self::encode_cancellable)。
+    #[inline]
+    pub fn encode_until(&self, text: &str, deadline: Instant) -> (Vec<utok>, usize, bool) {
+        self.encode_cancellable(text, || Instant::now() >= deadline)
+    }
+
+    /// 同 [`encode_until`](Self::encode_until)，文本超过 [`InputLimit::Reject`] 的上限时返回错误。
+    #[inline]
+    pub fn try_encode_until(
+        &self,
+        text: &str,
+        deadline: Instant,
+    ) -> Result<(Vec<utok>, usize, bool), InputTooLong> {
+        self.try_encode_cancellable(text, || Instant::now() >= deadline)
+    }
+
+    /// 计算文本编码结果的稳定哈希，可用作提示词缓存的键。
+    ///
+    /// 哈希包含分词器指纹，因此不同分词器的结果不会碰撞；计算过程不构造完整的词序列。
+    /// 编码方式与 [`encode`](Self::encode) 相同，文本超过 [`InputLimit::Reject`] 的上限时 panic。
+    pub fn encode_hash(&self, text: &str) -> u64 {
+        or_panic(self.try_encode_hash(text))
+    }
+
+    /// 同 [`encode_hash`](Self::encode_hash)，文本超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_encode_hash(&self, text: &str) -> Result<u64, InputTooLong> {
+        let text = &*self.normalizer.normalize(text);
+        self.check_input(text)?;
+        let mut hasher = StableHasher::default();
+        hasher.write_u64(self.fingerprint());
+        let mut split = self.split(text);
+        while let Some((range, special)) = split.next_span() {
+            match special {
+                Some(tokens) => tokens.iter().for_each(|&t| hasher.write_u32(t)),
+                None => {
+                    for t in self.encode_segment(&text[range]) {
+                        hasher.write_u32(t)
+                    }
+                }
+            }
+        }
+        Ok(hasher.finish())
+    }
+
+    /// 将文本切分为特殊词和一般文本段，并分别编码。
+    ///
+    /// 各段引用输入的文本，因此输入不经过规范化。一般文本段按 [`InputLimit::Chunk`] 分块编码，
+    /// 文本超过 [`InputLimit::Reject`] 的上限时 panic。
+    pub fn split<'s, 't>(&'s self, text: &'t str) -> Split<'s, 't, M> {
+        or_panic(self.try_split(text))
+    }
+
+    /// 同 [`split`](Self::split)，文本超过 [`InputLimit::Reject`] 的上限时返回错误。
+    pub fn try_split<'s, 't>(&'s self, text: &'t str) -> Result<Split<'s, 't, M>, InputTooLong> {
+        self.check_input(text)?;
+        Ok(Split {
+            tokeneer: self,
+            text,
+            pending: [None, None, None],
+            start: 0,
+        })
+    }
+
+    /// 解码词序列，<unk> 和超出词表的词序号按 [`UnkPolicy`] 处理。
+    ///
+    /// 策略为 [`UnkPolicy::Error`] 且遇到未知词时 panic，需要处理错误时使用 [`try_decode`](Self::try_decode)。
     pub fn decode(&self, tokens: &[utok]) -> String {
+        match self.try_decode(tokens) {
+            Ok(text) => text,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// 解码词序列，策略为 [`UnkPolicy::Error`] 时遇到 <unk> 或超出词表的词序号返回错误。
+    pub fn try_decode(&self, tokens: &[utok]) -> Result<String, DecodeError> {
         let mut ans = Vec::new();
-        for &t in tokens {
-            ans.extend_from_slice(self.method.decode(t));
+        for (index, &token) in tokens.iter().enumerate() {
+            let piece = self.render(token).ok_or(DecodeError { token, index })?;
+            self.decode_preset.apply(index, &piece, &mut ans);
+        }
+        // 任意词序列拼接的字节流不一定是合法的 utf-8，不合法的部分替换为 U+FFFD
+        Ok(String::from_utf8(ans)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+    }
+
+    /// 解码词序列并直接写入 `w`，不构造完整的字符串。
+    ///
+    /// 跨词的 utf-8 字符在完整后才写入。不合法的 utf-8 序列与 [`try_decode`](Self::try_decode) 一样替换为 U+FFFD，
+    /// 因此写入的内容与其结果相同。策略为 [`UnkPolicy::Error`] 时遇到未知词返回 [`io::ErrorKind::InvalidData`] 错误，
+    /// 此前的内容已经写入。
+    pub fn decode_to(&self, tokens: &[utok], mut w: impl io::Write) -> io::Result<()> {
+        // 上一个词留下的尚未完整的 utf-8 字符和当前词的内容
+        let mut buf = Vec::new();
+        for (index, &token) in tokens.iter().enumerate() {
+            let piece = self.render(token).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, DecodeError { token, index })
+            })?;
+            self.decode_preset.apply(index, &piece, &mut buf);
+            let written = write_lossy(&buf, &mut w)?;
+            buf.drain(..written);
+        }
+        if !buf.is_empty() {
+            w.write_all(REPLACEMENT.as_bytes())?
+        }
+        Ok(())
+    }
+
+    /// 检查并注册特殊词，任何一项检查失败时不注册任何特殊词。
+    ///
+    /// 检查词序号是否都在词表范围内，以及是否与已注册的特殊词冲突；
+    /// `reject_reachable` 为 `true` 时，还拒绝只含一个词且这个词能由其自身内容编码得到的特殊词。
+    pub fn try_extend_special(
+        &mut self,
+        patterns: impl IntoIterator<Item = (String, Vec<utok>)>,
+        reject_reachable: bool,
+    ) -> Result<(), SpecialTokenError> {
+        let patterns = patterns.into_iter().collect::<Vec<_>>();
+        let mut batch = HashMap::<&str, &[utok]>::new();
+        let mut batch_templates = HashMap::<String, &str>::new();
+        for (special, tokens) in &patterns {
+            let template = SpecialTemplate::new(special, tokens);
+            if let Some(&token) = tokens.iter().enumerate().find_map(|(i, t)| {
+                let capture = template.as_ref().is_some_and(|(_, t)| t.capture == i);
+                (!capture && *t as usize >= self.method.vocab_size()).then_some(t)
+            }) {
+                let special = special.clone();
+                return Err(SpecialTokenError::OutOfRange { special, token });
+            }
+            // 占位符前的固定文本相同的模式只能注册一个
+            let template_redefined = template.is_some_and(|(prefix, _)| {
+                self.templates
+                    .get(&prefix)
+                    .is_some_and(|t| t.pattern != *special || *t.tokens != **tokens)
+                    || batch_templates
+                        .insert(prefix, special)
+                        .is_some_and(|p| p != special)
+            });
+            let registered = self.special.get(special).map(|s| &**s);
+            let batched = batch.insert(special, tokens);
+            if template_redefined || registered.or(batched).is_some_and(|s| s != &**tokens) {
+                let special = special.clone();
+                return Err(SpecialTokenError::Redefined { special });
+            }
+            if let &[token] = &**tokens {
+                if reject_reachable && self.is_reachable(token) {
+                    let special = special.clone();
+                    return Err(SpecialTokenError::Reachable { special, token });
+                }
+            }
+        }
+        self.extend_special(patterns);
+        Ok(())
+    }
+
+    /// 判断词能否由其自身内容编码得到。
+    fn is_reachable(&self, token: utok) -> bool {
+        std::str::from_utf8(self.method.decode(token)).is_ok_and(|piece| {
+            let mut tokens = self.method.encode(piece).into_iter();
+            tokens.next() == Some(token) && tokens.next().is_none()
+        })
+    }
+
+    /// 编码可能产生的最大词序号加一，即词表大小与注册的特殊词中最大词序号加一的较大者。
+    ///
+    /// 通过 [`extend_special`](Self::extend_special) 注册的特殊词可以使用超出词表的词序号，
+    /// 此时编码结果的词序号不全在 `0..vocab_size` 中。
+    pub fn token_bound(&self) -> usize {
+        let special = self.special.values().flat_map(|s| s.iter());
+        let templates = self.templates.values().flat_map(|t| t.tokens.iter());
+        special
+            .chain(templates)
+            .filter(|&&t| t != SPECIAL_CAPTURE)
+            .map(|&t| t as usize + 1)
+            .fold(self.method.vocab_size(), usize::max)
+    }
+
+    /// 解码单个词，不分配内存。
+    ///
+    /// 词序号超出词表或词的内容不是完整的 utf-8 序列时返回 `None`，
+    /// 此时可以通过 [`Method::decode`] 获取字节序列。<unk> 按 [`UnkPolicy`] 处理。
+    ///
+    /// 返回词表中的原始内容，不应用 [`DecodePreset`]：预设可能改变内容或依赖词在序列中的位置，
+    /// 需要预设处理后的文本时使用 [`decode`](Self::decode)。
+    #[inline]
+    pub fn decode_token(&self, token: utok) -> Option<&str> {
+        if (token as usize) < self.method.vocab_size() {
+            match self.render(token)? {
+                Cow::Borrowed(piece) => std::str::from_utf8(piece).ok(),
+                // 词表内的词序号不使用占位符
+                Cow::Owned(_) => unreachable!(),
+            }
+        } else {
+            None
+        }
+    }
+
+    /// 分词器占用的堆内存，包括分词算法和注册的特殊词，用于统计每个模型的内存占用。
+    ///
+    /// 特殊词的匹配器内部状态无法统计，不计入。
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let special = hash_map_size(&self.special)
+            + self
+                .special
+                .iter()
+                .map(|(k, v)| {
+                    k.capacity()
+                        + match &v.tokens {
+                            TokenSeq::Single(_) => 0,
+                            TokenSeq::Multi(tokens) => size_of_val(&**tokens),
+                        }
+                })
+                .sum::<usize>()
+            + hash_map_size(&self.templates)
+            + self
+                .templates
+                .iter()
+                .map(|(k, v)| {
+                    k.capacity()
+                        + v.pattern.capacity()
+                        + v.suffix.capacity()
+                        + size_of_val(&*v.tokens)
+                })
+                .sum::<usize>()
+            + hash_map_size(&self.special_names)
+            + self
+                .special_names
+                .iter()
+                .map(|(k, v)| k.capacity() + v.capacity())
+                .sum::<usize>();
+        self.method.memory_usage()
+            + MemoryUsage {
+                special,
+                ..Default::default()
+            }
+    }
+
+    /// 按 [`UnkPolicy`] 获取词的内容，策略为 [`UnkPolicy::Error`] 时未知词返回 `None`。
+    ///
+    /// 只有超出词表的词序号的占位符需要分配内存。
+    fn render(&self, token: utok) -> Option<Cow<'_, [u8]>> {
+        let unk = self.method.unk_token();
+        let in_range = (token as usize) < self.method.vocab_size();
+        if Some(token) != unk && in_range {
+            if self.escape_bytes {
+                if let Some(b) = self.method.fallback_byte(token) {
+                    return Some(Cow::Borrowed(&BYTE_ESCAPES[b as usize]));
+                }
+            }
+            return Some(Cow::Borrowed(self.method.decode(token)));
+        }
+        let piece = || unk.map_or(&[][..], |unk| self.method.decode(unk));
+        match self.unk_policy {
+            UnkPolicy::Piece => Some(Cow::Borrowed(piece())),
+            UnkPolicy::Placeholder if in_range => Some(Cow::Borrowed(piece())),
+            UnkPolicy::Placeholder => Some(Cow::Owned(
+                self.unk_placeholder
+                    .replace("{id}", &token.to_string())
+                    .into_bytes(),
+            )),
+            UnkPolicy::Empty => Some(Cow::Borrowed(b"")),
+            UnkPolicy::Error => None,
         }
-        String::from_utf8(ans).unwrap()
     }
 }
 
 impl<M> Tokeneer<M> {
+    /// 注册特殊词及其对应的词序列。
+    ///
+    /// 编码时先在文本中查找特殊词，因此特殊词总是优先于词表中的词，即使它是某个更长的词的一部分。
+    /// 查找选择最靠左的位置，同一位置选择最长的特殊词，与注册顺序无关。
+    ///
+    /// 特殊词可以含有一个形如 `{name}` 的占位符，此时词序列中须恰好有一个 [`SPECIAL_CAPTURE`] 标记插入的位置，
+    /// 例如 `("<tool:{name}>", vec![start, SPECIAL_CAPTURE, end])`。编码时占位符匹配到后一段固定文本（此例为 `>`）首次出现为止，
+    /// 匹配的文本由分词算法编码后插入标记处。占位符匹配的文本不包含其他特殊词，长度不超过
+    /// [`set_special_capture_limit`](Self::set_special_capture_limit) 设置的上限，找不到后一段固定文本时不匹配。
+    /// 没有标记的词序列仍按字面匹配整个模式；没有占位符或含有多个标记时，词序列含有 [`SPECIAL_CAPTURE`] 将 panic。
     pub fn extend_special(&mut self, patterns: impl IntoIterator<Item = (String, Vec<utok>)>) {
         use std::collections::hash_map::Entry::{Occupied, Vacant};
         let mut any = false;
         for (k, v) in patterns {
+            if let Some((prefix, template)) = SpecialTemplate::new(&k, &v) {
+                match self.templates.entry(prefix) {
+                    Occupied(entry) => {
+                        assert_eq!(entry.get().pattern, template.pattern);
+                        assert_eq!(entry.get().tokens, template.tokens);
+                    }
+                    Vacant(entry) => {
+                        entry.insert(template);
+                        any = true;
+                    }
+                }
+                continue;
+            }
+            assert!(
+                !v.contains(&SPECIAL_CAPTURE),
+                "special token {k:?} contains SPECIAL_CAPTURE without a placeholder"
+            );
             match self.special.entry(k) {
                 Occupied(entry) => {
                     assert_eq!(&**entry.get(), &v);
                 }
                 Vacant(entry) => {
-                    entry.insert(TokenSeq::Multi(v.into_boxed_slice()));
+                    entry.insert(SpecialToken::new(TokenSeq::Multi(v.into_boxed_slice())));
                     any = true;
                 }
             }
         }
         if any {
-            self.special_regex = build_pattern(self.special.keys());
+            self.special_changed()
+        }
+    }
+
+    /// 替换通过 [`extend_special`](Self::extend_special) 注册的所有特殊词，分词算法内置的特殊词保留不变。
+    ///
+    /// 新注册的特殊词不保留旧的空白和单词边界设置。
+    pub fn replace_special(&mut self, patterns: impl IntoIterator<Item = (String, Vec<utok>)>) {
+        self.special
+            .retain(|_, token| matches!(token.tokens, TokenSeq::Single(_)));
+        self.templates.clear();
+        self.extend_special(patterns);
+        self.special_changed()
+    }
+
+    /// 注册或更新多模态占位词，编码时展开为 `len` 个 `token`，
+    /// 例如每张图片固定占用 576 个图块的 `<image>`。
+    ///
+    /// 已注册的占位词只替换其展开结果，保留空白和单词边界设置。
+    pub fn set_placeholder(&mut self, placeholder: &str, token: utok, len: usize) {
+        let tokens = TokenSeq::Multi(vec![token; len].into_boxed_slice());
+        match self.special.get_mut(placeholder) {
+            Some(special) => special.tokens = tokens,
+            None => {
+                self.special
+                    .insert(placeholder.into(), SpecialToken::new(tokens));
+            }
+        }
+        self.special_changed()
+    }
+
+    /// 设置特殊词是否吞掉匹配位置两侧的空白，返回特殊词是否已注册。
+    ///
+    /// 例如设置 `<|eot|>` 吞掉左侧空白后，`" <|eot|>"` 和 `"<|eot|>"` 的编码相同。
+    pub fn set_special_strip(&mut self, special: &str, lstrip: bool, rstrip: bool) -> bool {
+        match self.special.get_mut(special) {
+            Some(token) => {
+                token.lstrip = lstrip;
+                token.rstrip = rstrip;
+                self.fingerprint = OnceLock::new();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 设置特殊词是否只匹配独立的词，返回特殊词是否已注册。
+    ///
+    /// 例如设置 `end` 只匹配独立的词后，`"weekend"` 中的 `end` 不会被识别为特殊词。
+    pub fn set_special_single_word(&mut self, special: &str, single_word: bool) -> bool {
+        match self.special.get_mut(special) {
+            Some(token) => {
+                token.single_word = single_word;
+                self.fingerprint = OnceLock::new();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 为已注册的特殊词设置名字，例如将 `"<|im_end|>"` 命名为 `"eos"`，返回特殊词是否已注册。
+    pub fn set_special_name(&mut self, name: impl Into<String>, special: &str) -> bool {
+        let registered = self.special.contains_key(special);
+        if registered {
+            self.special_names.insert(name.into(), special.into());
+        }
+        registered
+    }
+
+    /// 按文本或 [`set_special_name`](Self::set_special_name) 设置的名字查找特殊词的词序列，文本优先。
+    ///
+    /// 模板代码可以直接拼接返回的词序列，不必重新编码特殊词的文本，特殊词对应的词可能无法由编码得到。
+    /// 名字对应的特殊词已被移除或含有占位符时返回 `None`。
+    pub fn special_id(&self, name_or_surface: &str) -> Option<&[utok]> {
+        let special = match self.special.get(name_or_surface) {
+            Some(special) => special,
+            None => self.special.get(self.special_names.get(name_or_surface)?)?,
+        };
+        Some(&**special)
+    }
+
+    /// 词表和特殊词的稳定指纹，词表或特殊词变化时指纹随之变化。
+    #[inline]
+    pub fn fingerprint(&self) -> u64 {
+        *self
+            .fingerprint
+            .get_or_init(|| fingerprint(self.vocab_fingerprint, &self.special, &self.templates))
+    }
+
+    /// 设置使用正则表达式匹配特殊词的数量上限，特殊词更多时改用前缀树匹配，默认为 64。
+    ///
+    /// 正则表达式在特殊词较少时更快，但构造代价随特殊词数量增长；关闭 `regex` 特性时总是使用前缀树。
+    pub fn set_special_regex_limit(&mut self, limit: usize) {
+        self.special_regex_limit = limit;
+        self.special_matcher = OnceLock::new();
+    }
+
+    /// 设置特殊词占位符匹配的文本长度上限（字节），默认为 256。
+    ///
+    /// 占位符之后的固定文本须在上限之内、且在下一个特殊词之前出现，否则前一段固定文本按一般文本编码。
+    pub fn set_special_capture_limit(&mut self, limit: usize) {
+        self.capture_limit = limit;
+    }
+
+    /// 设置编码时是否复用线程内的缓冲区，默认复用。
+    ///
+    /// 复用使同一线程的多次编码免于重复分配合并用的缓冲区，代价是每个编码过的线程保留一份有上限的缓冲区；
+    /// 对内存敏感、线程很多的嵌入场景可以关闭。
+    #[inline]
+    pub fn set_thread_workspace(&mut self, enabled: bool) {
+        self.thread_workspace = enabled;
+    }
+
+    /// 设置编码时的输入长度上限，以规范化后的字节数计，默认不限制。
+    ///
+    /// 合并算法的缓冲区与一般文本段的长度成正比，上限防止单个超长输入占用过多内存。
+    /// 上限作用于所有编码方法，每个超过上限时 panic 的方法都有返回错误的 `try_` 版本。
+    /// 分块编码时块的边界优先选在空白字符之前，边界两侧不会合并，结果可能与不分块时不同。
+    #[inline]
+    pub fn set_input_limit(&mut self, limit: Option<InputLimit>) {
+        self.input_limit = limit;
+    }
+
+    /// 特殊词的匹配器，首次使用时构造；没有任何特殊词时返回 `None`，编码时跳过查找。
+    fn special_matcher(&self) -> Option<&SpecialMatcher> {
+        self.special_matcher
+            .get_or_init(|| {
+                SpecialMatcher::new(
+                    self.special.keys().chain(self.templates.keys()),
+                    self.special_regex_limit,
+                )
+            })
+            .as_ref()
+    }
+
+    /// 特殊词变化后丢弃匹配器和指纹，推迟到下次使用时重新构造，使批量注册的代价与特殊词总数成线性。
+    fn special_changed(&mut self) {
+        self.special_matcher = OnceLock::new();
+        self.fingerprint = OnceLock::new();
+    }
+
+    /// 设置编码前的文本规范化选项。
+    #[inline]
+    pub fn set_normalizer(&mut self, normalizer: Normalizer) {
+        self.normalizer = normalizer;
+    }
+
+    /// 设置详细编码的截断配置，`None` 表示不截断。
+    #[inline]
+    pub fn set_truncation(&mut self, truncation: Option<Truncation>) {
+        self.truncation = truncation;
+    }
+
+    /// 设置详细编码的后处理，`None` 表示不插入特殊词。
+    ///
+    /// 截断的最大长度包括后处理插入的特殊词，与 HF tokenizers 一致。
+    #[inline]
+    pub fn set_post_processor(&mut self, post_processor: Option<PostProcessor>) {
+        self.post_processor = post_processor;
+    }
+
+    /// 从完整的 tokenizer.json 文档设置详细编码的后处理，解析其中的 `post_processor` 一节，
+    /// 参见 [`PostProcessor::from_json`]。文档没有 `post_processor` 字段时与其为 `null` 相同，清除后处理。
+    #[cfg(feature = "tokenizer-json")]
+    pub fn set_post_processor_from_tokenizer_json(
+        &mut self,
+        json: &serde_json::Value,
+    ) -> Result<(), crate::PostProcessorError> {
+        if !json.is_object() {
+            return Err(crate::PostProcessorError::Malformed("post_processor"));
         }
+        let post_processor = json
+            .get("post_processor")
+            .unwrap_or(&serde_json::Value::Null);
+        self.post_processor = PostProcessor::from_json(post_processor)?;
+        Ok(())
+    }
+
+    /// 设置解码时 <unk> 和超出词表的词序号的处理方式。
+    #[inline]
+    pub fn set_unk_policy(&mut self, policy: UnkPolicy) {
+        self.unk_policy = policy;
+    }
+
+    /// 设置 [`UnkPolicy::Placeholder`] 使用的占位符，其中的 `{id}` 替换为词序号，默认为 `�[{id}]`。
+    #[inline]
+    pub fn set_unk_placeholder(&mut self, placeholder: impl Into<String>) {
+        self.unk_placeholder = placeholder.into();
+    }
+
+    /// 设置解码预设，按模型族对解码结果做后处理，例如 `DecodePreset::from_name("gpt2-bytelevel")`。
+    #[inline]
+    pub fn set_decode_preset(&mut self, preset: DecodePreset) {
+        self.decode_preset = preset;
+    }
+
+    /// 设置解码时是否将单字节词转义为 `<0xAB>` 形式的文本，与 SentencePiece 对单字节词的输出一致。
+    ///
+    /// 转义后的解码结果总是合法的 utf-8，适合写入日志。
+    #[inline]
+    pub fn set_escape_bytes(&mut self, escape: bool) {
+        self.escape_bytes = escape;
+    }
+
+    /// 记录构造分词器所用模型文件的来源信息。
+    #[cfg(feature = "checksum")]
+    #[inline]
+    pub fn set_source(&mut self, source: crate::SourceInfo) {
+        self.source = Some(source);
+    }
+
+    /// 构造分词器所用模型文件的来源信息。
+    #[cfg(feature = "checksum")]
+    #[inline]
+    pub fn source(&self) -> Option<&crate::SourceInfo> {
+        self.source.as_ref()
     }
 
     #[inline]
     pub fn internal(&self) -> &M {
         &self.method
     }
+
+    /// 替换分词算法并保留其余配置，新的分词算法必须使用相同的词表。
+    pub(crate) fn map_method<N>(self, f: impl FnOnce(M) -> N) -> Tokeneer<N> {
+        Tokeneer {
+            method: f(self.method),
+            special: self.special,
+            templates: self.templates,
+            special_names: self.special_names,
+            special_matcher: self.special_matcher,
+            special_regex_limit: self.special_regex_limit,
+            capture_limit: self.capture_limit,
+            thread_workspace: self.thread_workspace,
+            input_limit: self.input_limit,
+            normalizer: self.normalizer,
+            truncation: self.truncation,
+            post_processor: self.post_processor,
+            unk_policy: self.unk_policy,
+            unk_placeholder: self.unk_placeholder,
+            escape_bytes: self.escape_bytes,
+            decode_preset: self.decode_preset,
+            vocab_fingerprint: self.vocab_fingerprint,
+            fingerprint: self.fingerprint,
+            #[cfg(feature = "checksum")]
+            source: self.source,
+        }
+    }
+
+    /// 遍历注册的特殊词及其词序列，按特殊词的文本排序，遍历顺序是确定的。
+    ///
+    /// 带占位符的特殊词不在其中。
+    pub fn special_tokens(&self) -> impl Iterator<Item = (&str, &[utok])> {
+        let mut ans = self
+            .special
+            .iter()
+            .map(|(k, v)| (&**k, &**v))
+            .collect::<Vec<_>>();
+        ans.sort_unstable_by_key(|&(k, _)| k);
+        ans.into_iter()
+    }
 }
 
-fn build_pattern<'a>(text: impl IntoIterator<Item = &'a String>) -> Regex {
-    static SPECIAL: LazyLock<HashSet<char>> = LazyLock::new(|| {
-        HashSet::from([
-            '*', '.', '?', '+', '^', '$', '|', '/', '\\', '(', ')', '[', ']', '{', '}',
-        ])
-    });
+/// 替换不合法 utf-8 序列的字符
+const REPLACEMENT: &str = "\u{FFFD}";
 
-    let mut pattern = String::new();
-    for p in text {
-        for c in p.chars() {
-            if SPECIAL.contains(&c) {
-                pattern.push('\\');
+/// 将字节序列写入 `w`，不合法的序列替换为 [`REPLACEMENT`]，与 [`String::from_utf8_lossy`] 相同。
+///
+/// 末尾尚未完整的 utf-8 字符不写入，返回已经处理的字节数。
+fn write_lossy(bytes: &[u8], w: &mut impl io::Write) -> io::Result<usize> {
+    let mut pos = 0;
+    loop {
+        match std::str::from_utf8(&bytes[pos..]) {
+            Ok(_) => {
+                w.write_all(&bytes[pos..])?;
+                return Ok(bytes.len());
+            }
+            Err(e) => {
+                let valid = pos + e.valid_up_to();
+                w.write_all(&bytes[pos..valid])?;
+                match e.error_len() {
+                    Some(len) => {
+                        w.write_all(REPLACEMENT.as_bytes())?;
+                        pos = valid + len
+                    }
+                    None => return Ok(valid),
+                }
             }
-            pattern.push(c);
         }
-        pattern.push('|');
     }
-    pattern.pop();
+}
+
+/// 展开输入长度检查的结果，超过上限时 panic。
+pub(crate) fn or_panic<T>(result: Result<T, InputTooLong>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => panic!("{e}"),
+    }
+}
+
+/// 将文本切成不超过 `limit` 字节的块，块的边界优先选在空白字符之前。
+///
+/// 单个字符超过上限时独占一块。
+fn chunks(text: &str, limit: usize) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let mut end = rest.len().min(limit);
+        if end < rest.len() {
+            while !rest.is_char_boundary(end) {
+                end -= 1
+            }
+            end = match rest[..end].rfind(char::is_whitespace) {
+                Some(space) if space > 0 => space,
+                _ if end > 0 => end,
+                _ => rest.chars().next().unwrap().len_utf8(),
+            };
+        }
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+/// 占位符匹配的文本长度的默认上限
+const CAPTURE_LIMIT: usize = 256;
+
+/// 超出词表的词序号的默认占位符
+const UNK_PLACEHOLDER: &str = "\u{FFFD}[{id}]";
+
+/// 切分出的一段：(所占范围, 特殊词的词序列)，一般文本段没有词序列。
+type Span<'s> = (Range<usize>, Option<&'s [utok]>);
 
-    Regex::new(&pattern).unwrap()
+/// 在文本中找到的特殊词。
+enum Found<'s> {
+    /// 按字面匹配的特殊词
+    Special(Range<usize>, &'s SpecialToken),
+    /// 含有占位符的特殊词，记录占位符前后两段固定文本的范围
+    Template(Range<usize>, Range<usize>, &'s SpecialTemplate),
+}
+
+impl<'s, M> Split<'s, '_, M> {
+    /// 从当前位置开始查找下一个满足边界条件的特殊词。
+    fn find_special(&self) -> Option<Found<'s>> {
+        let tokeneer = self.tokeneer;
+        let text = self.text;
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let mut pos = self.start;
+        loop {
+            let longest = tokeneer.special_matcher()?.find_at(text, pos)?;
+            // 最长的特殊词被拒绝时，依次尝试同一位置开始的较短的特殊词
+            let ends = (longest.start + 1..=longest.end)
+                .rev()
+                .filter(|&end| text.is_char_boundary(end));
+            for end in ends {
+                let m = longest.start..end;
+                let matched = &text[m.clone()];
+                if let Some(template) = tokeneer.templates.get(matched) {
+                    if let Some(close) = self.find_suffix(m.end, template) {
+                        return Some(Found::Template(m, close, template));
+                    }
+                }
+                if let Some(special) = tokeneer.special.get(matched) {
+                    if !special.single_word
+                        || !(text[..m.start].ends_with(is_word)
+                            || text[m.end..].starts_with(is_word))
+                    {
+                        return Some(Found::Special(m, special));
+                    }
+                }
+            }
+            // 从下一个字符开始继续查找
+            pos = longest.start + text[longest.start..].chars().next().unwrap().len_utf8();
+        }
+    }
+
+    /// 从 `start` 开始查找占位符之后的固定文本。
+    ///
+    /// 占位符匹配的文本不超过长度上限，也不包含其他特殊词：固定文本须在下一个特殊词之前出现，
+    /// 除非下一个特殊词恰以固定文本开头。查找范围不超过下一个特殊词，因此总代价与文本长度成线性。
+    fn find_suffix(&self, start: usize, template: &SpecialTemplate) -> Option<Range<usize>> {
+        let text = self.text;
+        let suffix = &*template.suffix;
+        let mut end = text
+            .len()
+            .min(start + self.tokeneer.capture_limit + suffix.len());
+        if let Some(next) = self.tokeneer.special_matcher()?.find_at(text, start) {
+            let next = match text[next.start..].starts_with(suffix) {
+                true => next.start + suffix.len(),
+                false => next.start,
+            };
+            end = end.min(next)
+        }
+        let pos = memchr::memmem::find(&text.as_bytes()[start..end], suffix.as_bytes())?;
+        Some(start + pos..start + pos + suffix.len())
+    }
+
+    /// 返回下一段的范围，特殊词同时返回其词序列。
+    ///
+    /// 特殊词的范围包含其吞掉的空白，一般文本段不会为空。
+    /// 占位符模式切分为前后两段固定文本对应的特殊词段，以及其间由分词算法编码的一般文本段。
+    fn next_span(&mut self) -> Option<Span<'s>> {
+        if let Some(span) = self.pending.iter_mut().find_map(Option::take) {
+            self.start = span.0.end;
+            return Some(span);
+        }
+        let text = self.text;
+        let start = self.start;
+        let spans = match self.find_special() {
+            Some(Found::Special(m, special)) => {
+                let mut range = m.clone();
+                if special.lstrip {
+                    range.start = start + text[start..m.start].trim_end().len();
+                }
+                if special.rstrip {
+                    let tail = &text[m.end..];
+                    range.end += tail.len() - tail.trim_start().len();
+                }
+                [Some((range, Some(&**special))), None, None]
+            }
+            Some(Found::Template(open, close, template)) => [
+                Some((open.clone(), Some(template.before()))),
+                (open.end < close.start).then_some((open.end..close.start, None)),
+                Some((close, Some(template.after()))),
+            ],
+            None if start < text.len() => {
+                self.start = text.len();
+                return Some((start..text.len(), None));
+            }
+            None => return None,
+        };
+        let first = spans[0].as_ref().map_or(start, |(range, _)| range.start);
+        self.pending = spans;
+        if first > start {
+            self.start = first;
+            Some((start..first, None))
+        } else {
+            self.next_span()
+        }
+    }
+}
+
+impl<'s, 't, M: Method> Iterator for Split<'s, 't, M> {
+    type Item = Segment<'t, 's>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (range, special) = self.next_span()?;
+        let text = &self.text[range];
+        Some(match special {
+            Some(tokens) => Segment::Special { text, tokens },
+            None => Segment::Normal {
+                text,
+                tokens: self.tokeneer.encode_segment(text).collect(),
+            },
+        })
+    }
+}
+
+fn vocab_fingerprint(method: &impl Method) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write_usize(method.vocab_size());
+    // 没有 <unk> 时写入不可能出现的词序号，有 <unk> 的词表的指纹保持不变
+    hasher.write_u32(method.unk_token().unwrap_or(utok::MAX));
+    for t in 0..method.vocab_size() as utok {
+        let piece = method.decode(t);
+        hasher.write_usize(piece.len());
+        hasher.write(piece);
+    }
+    hasher.finish()
+}
+
+fn fingerprint(
+    vocab: u64,
+    special: &HashMap<String, SpecialToken>,
+    templates: &HashMap<String, SpecialTemplate>,
+) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write_u64(vocab);
+    // HashMap 的遍历顺序不确定，排序后再哈希
+    let mut special = special.iter().collect::<Vec<_>>();
+    special.sort_unstable_by_key(|(k, _)| *k);
+    for (k, v) in special {
+        hasher.write_usize(k.len());
+        hasher.write(k.as_bytes());
+        hasher.write_usize(v.len());
+        for &t in &**v {
+            hasher.write_u32(t)
+        }
+        hasher.write(&[v.lstrip as u8, v.rstrip as u8, v.single_word as u8]);
+    }
+    // 没有占位符模式时不写入任何内容，保持原有的指纹不变
+    let mut templates = templates.values().collect::<Vec<_>>();
+    templates.sort_unstable_by_key(|t| &t.pattern);
+    for t in templates {
+        hasher.write_usize(t.pattern.len());
+        hasher.write(t.pattern.as_bytes());
+        hasher.write_usize(t.tokens.len());
+        for &t in &*t.tokens {
+            hasher.write_u32(t)
+        }
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tokeneer_tests {
+    use super::*;
+    use crate::Lpe;
+
+    fn test_tokeneer() -> Tokeneer<Lpe> {
+        let mut lpe = Lpe::new(["<unk>", "<s>", "a", "b", "ab", " "].map(str::as_bytes), 0);
+        lpe.detect_default_specials();
+        let mut tokeneer = Tokeneer::new(lpe);
+        tokeneer.extend_special([("<pair>".to_string(), vec![2, 3])]);
+        tokeneer
+    }
+
+    #[test]
+    fn test_split() {
+        let tokeneer = test_tokeneer();
+        let segments = tokeneer.split("<s>ab a<pair>").collect::<Vec<_>>();
+        assert_eq!(
+            segments,
+            [
+                Segment::Special {
+                    text: "<s>",
+                    tokens: &[1]
+                },
+                Segment::Normal {
+                    text: "ab a",
+                    tokens: vec![4, 5, 2]
+                },
+                Segment::Special {
+                    text: "<pair>",
+                    tokens: &[2, 3]
+                },
+            ]
+        );
+        assert_eq!(tokeneer.encode("<s>ab a<pair>"), [1, 4, 5, 2, 2, 3]);
+    }
+
+    #[test]
+    fn test_special_tokens_order() {
+        let mut tokeneer = test_tokeneer();
+        tokeneer.extend_special([("<z>".to_string(), vec![3]), ("<a>".to_string(), vec![2])]);
+        assert_eq!(
+            tokeneer.special_tokens().collect::<Vec<_>>(),
+            [
+                ("<a>", &[2][..]),
+                ("<pair>", &[2, 3]),
+                ("<s>", &[1]),
+                ("<z>", &[3])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_input_limit() {
+        let mut tokeneer = test_tokeneer();
+        tokeneer.set_input_limit(Some(InputLimit::Chunk(3)));
+        assert_eq!(tokeneer.encode("ab ab<s>ab"), [4, 5, 4, 1, 4]);
+        // 块之间不会合并
+        tokeneer.set_input_limit(Some(InputLimit::Chunk(1)));
+        assert_eq!(tokeneer.encode("ab"), [2, 3]);
+        tokeneer.set_input_limit(Some(InputLimit::Reject(4)));
+        assert_eq!(tokeneer.try_encode("ab a"), Ok(vec![4, 5, 2]));
+        assert_eq!(
+            tokeneer.try_encode("ab ab"),
+            Err(InputTooLong { len: 5, limit: 4 })
+        );
+        assert_eq!(
+            chunks("中文 ab", 4).collect::<Vec<_>>(),
+            ["中", "文", " ab"]
+        );
+        // 所有编码方法都检查上限
+        let long = "ab ab";
+        assert!(tokeneer.try_encode_with_offsets(long).is_err());
+        assert!(tokeneer.try_encode_detailed(long).is_err());
+        assert!(tokeneer.try_encode_pair("a", long).is_err());
+        assert!(tokeneer.try_encode_pretokenized(&["a", long]).is_err());
+        assert!(tokeneer.try_encode_bounded(long, 10).is_err());
+        assert!(tokeneer.try_encode_cancellable(long, || false).is_err());
+        assert!(tokeneer.try_encode_hash(long).is_err());
+        assert!(tokeneer.try_split(long).is_err());
+        // 所有编码方法都按相同的方式分块
+        tokeneer.set_input_limit(Some(InputLimit::Chunk(1)));
+        let text = "ab<s>ab a";
+        let tokens = tokeneer.encode(text);
+        assert_eq!(tokens, [2, 3, 1, 2, 3, 5, 2]);
+        let ids = |pairs: Vec<(utok, Range<usize>)>| {
+            pairs.into_iter().map(|(t, _)| t).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(tokeneer.encode_with_offsets(text)), tokens);
+        assert_eq!(tokeneer.encode_detailed(text).ids(), tokens);
+        assert_eq!(tokeneer.encode_bounded(text, 100).0, tokens);
+        assert_eq!(tokeneer.encode_cancellable(text, || false).0, tokens);
+        let mut hasher = StableHasher::default();
+        hasher.write_u64(tokeneer.fingerprint());
+        tokens.iter().for_each(|&t| hasher.write_u32(t));
+        assert_eq!(tokeneer.encode_hash(text), hasher.finish());
+    }
+
+    #[test]
+    fn test_special_id() {
+        let mut tokeneer = test_tokeneer();
+        assert_eq!(tokeneer.special_id("<pair>"), Some(&[2, 3][..]));
+        assert_eq!(tokeneer.special_id("pair"), None);
+        assert!(tokeneer.set_special_name("pair", "<pair>"));
+        assert!(!tokeneer.set_special_name("bos", "<bos>"));
+        assert_eq!(tokeneer.special_id("pair"), Some(&[2, 3][..]));
+        // 特殊词移除后名字失效
+        tokeneer.replace_special([]);
+        assert_eq!(tokeneer.special_id("pair"), None);
+        assert_eq!(tokeneer.special_id("<s>"), Some(&[1][..]));
+    }
+
+    #[test]
+    fn test_special_strip() {
+        let mut tokeneer = test_tokeneer();
+        assert!(tokeneer.set_special_strip("<s>", true, true));
+        assert!(!tokeneer.set_special_strip("<t>", true, true));
+        let segments = tokeneer.split("a  <s> b").collect::<Vec<_>>();
+        assert_eq!(
+            segments.iter().map(Segment::text).collect::<Vec<_>>(),
+            ["a", "  <s> ", "b"]
+        );
+        assert_eq!(tokeneer.encode("a  <s> b"), tokeneer.encode("a<s>b"));
+    }
+
+    #[test]
+    fn test_special_precedence() {
+        let mut tokeneer = test_tokeneer();
+        tokeneer.extend_special([
+            ("ab".to_string(), vec![2]),
+            ("<pair><s>".to_string(), vec![1, 1]),
+        ]);
+        // 特殊词优先于词表中的词，同一位置选择最长的特殊词
+        assert_eq!(tokeneer.encode("<pair><s>ab"), [1, 1, 2]);
+
+        assert!(tokeneer.set_special_single_word("ab", true));
+        assert_eq!(tokeneer.encode("bab ab"), [3, 4, 5, 2]);
+
+        // 较长的特殊词因单词边界被拒绝时，尝试同一位置开始的较短的特殊词
+        tokeneer.extend_special([("end".to_string(), vec![1]), ("endx".to_string(), vec![0])]);
+        assert!(tokeneer.set_special_single_word("endx", true));
+        assert_eq!(tokeneer.encode("endx a"), [0, 5, 2]);
+        assert_eq!(tokeneer.encode("endxa"), [1, 0, 2]);
+    }
+
+    #[test]
+    fn test_encode_detailed() {
+        let tokeneer = test_tokeneer();
+        let encoding = tokeneer.encode_detailed("<s>ab a<pair>");
+        assert_eq!(encoding.ids(), [1, 4, 5, 2, 2, 3]);
+        assert_eq!(encoding.special_tokens_mask(), [1, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_encode_pair() {
+        let tokeneer = test_tokeneer();
+        let encoding = tokeneer.encode_pair("<s>ab", "a b");
+        assert_eq!(encoding.ids(), [1, 4, 2, 5, 3]);
+        assert_eq!(
+            encoding.sequence_ids(),
+            [None, Some(0), Some(1), Some(1), Some(1)]
+        );
+    }
+
+    #[test]
+    fn test_encoding_alignment() {
+        let tokeneer = test_tokeneer();
+        let encoding = tokeneer.encode_pair("<s>ab", "a b");
+        assert_eq!(encoding.offsets(), [0..3, 3..5, 0..1, 1..2, 2..3]);
+        assert_eq!(encoding.token_to_bytes(1), Some(3..5));
+        assert_eq!(encoding.token_to_bytes(5), None);
+        assert_eq!(encoding.byte_to_token(4, 0), Some(1));
+        assert_eq!(encoding.byte_to_token(2, 1), Some(4));
+        // 特殊词不属于任何输入
+        assert_eq!(encoding.byte_to_token(0, 0), None);
+        assert_eq!(encoding.byte_to_token(3, 1), None);
+        assert_eq!(encoding.bytes_to_tokens(0..3, 1), Some(2..5));
+        assert_eq!(encoding.bytes_to_tokens(1..2, 1), Some(3..4));
+        assert_eq!(encoding.bytes_to_tokens(5..6, 0), None);
+
+        // 位置按字节计算
+        let tokeneer = Tokeneer::new(Lpe::new(["<unk>", "中", "文", "a"].map(str::as_bytes), 0));
+        let encoding = tokeneer.encode_detailed("中文a");
+        assert_eq!(encoding.byte_to_token(3, 0), Some(1));
+        assert_eq!(encoding.byte_to_token(6, 0), Some(2));
+
+        // 范围对应规范化之前的输入
+        let mut tokeneer = Tokeneer::new(Lpe::new(["<unk>", "a", "b", "\n"].map(str::as_bytes), 0));
+        tokeneer.set_normalizer(Normalizer {
+            crlf_to_lf: true,
+            strip_bom: true,
+        });
+        let text = "\u{feff}a\r\nb";
+        let encoding = tokeneer.encode_detailed(text);
+        assert_eq!(encoding.offsets(), [3..4, 4..6, 6..7]);
+        assert_eq!(&text[encoding.offsets()[1].clone()], "\r\n");
+        assert_eq!(encoding.byte_to_token(6, 0), Some(2));
+        assert_eq!(
+            tokeneer
+                .encode_with_offsets(text)
+                .into_iter()
+                .map(|(_, range)| range)
+                .collect::<Vec<_>>(),
+            encoding.offsets()
+        );
+    }
+
+    #[test]
+    fn test_pair_truncation() {
+        use crate::TruncationStrategy::*;
+
+        let mut tokeneer = test_tokeneer();
+        let mut lens = |strategy| {
+            tokeneer.set_truncation(Some(Truncation {
+                max_length: 3,
+                strategy,
+            }));
+            let encoding = tokeneer.encode_pair("a a", "b");
+            let first = encoding.sequence_ids().iter().filter(|&&s| s == Some(0));
+            (first.count(), encoding.len())
+        };
+        assert_eq!(lens(LongestFirst), (2, 3));
+        assert_eq!(lens(OnlyFirst), (2, 3));
+        assert_eq!(lens(OnlySecond), (3, 3));
+
+        // 预算为奇数时多出的一个词留给较长的一段，等长时留给第二段
+        let longest_first = |first, second, max_length| {
+            Truncation {
+                max_length,
+                strategy: LongestFirst,
+            }
+            .apply(first, second)
+        };
+        assert_eq!(longest_first(5, 5, 7), (3, 4));
+        assert_eq!(longest_first(4, 10, 7), (3, 4));
+        assert_eq!(longest_first(10, 4, 7), (4, 3));
+        assert_eq!(longest_first(5, 5, 6), (3, 3));
+        assert_eq!(longest_first(2, 10, 7), (2, 5));
+    }
+
+    #[test]
+    fn test_post_processor() {
+        let mut tokeneer = test_tokeneer();
+        tokeneer.set_post_processor(Some(PostProcessor::bert(1, 0)));
+        let encoding = tokeneer.encode_pair("ab", "a b");
+        assert_eq!(encoding.ids(), [1, 4, 0, 2, 5, 3, 0]);
+        assert_eq!(encoding.type_ids(), [0, 0, 0, 1, 1, 1, 1]);
+        assert_eq!(encoding.special_tokens_mask(), [1, 0, 1, 0, 0, 0, 1]);
+        assert_eq!(encoding.offsets()[..3], [0..0, 0..2, 0..0]);
+        // 截断的最大长度包括插入的特殊词
+        tokeneer.set_truncation(Some(Truncation {
+            max_length: 4,
+            strategy: Default::default(),
+        }));
+        assert_eq!(tokeneer.encode_detailed("a b").ids(), [1, 2, 5, 0]);
+        // 只剩 1 个词的预算时留给较长的第二段
+        assert_eq!(tokeneer.encode_pair("ab", "a b").ids(), [1, 0, 2, 0]);
+    }
+
+    #[cfg(feature = "tokenizer-json")]
+    #[test]
+    fn test_post_processor_from_tokenizer_json() {
+        use serde_json::json;
+
+        // 按 bert-base-uncased 的 tokenizer.json 裁剪的词表
+        let document = json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [
+                {"id": 0, "content": "[PAD]", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true},
+                {"id": 1, "content": "[UNK]", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true},
+                {"id": 2, "content": "[CLS]", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true},
+                {"id": 3, "content": "[SEP]", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true}
+            ],
+            "normalizer": {"type": "BertNormalizer", "clean_text": true, "handle_chinese_chars": true, "strip_accents": null, "lowercase": true},
+            "pre_tokenizer": {"type": "BertPreTokenizer"},
+            "post_processor": {
+                "type": "TemplateProcessing",
+                "single": [
+                    {"SpecialToken": {"id": "[CLS]", "type_id": 0}},
+                    {"Sequence": {"id": "A", "type_id": 0}},
+                    {"SpecialToken": {"id": "[SEP]", "type_id": 0}}
+                ],
+                "pair": [
+                    {"SpecialToken": {"id": "[CLS]", "type_id": 0}},
+                    {"Sequence": {"id": "A", "type_id": 0}},
+                    {"SpecialToken": {"id": "[SEP]", "type_id": 0}},
+                    {"Sequence": {"id": "B", "type_id": 1}},
+                    {"SpecialToken": {"id": "[SEP]", "type_id": 1}}
+                ],
+                "special_tokens": {
+                    "[CLS]": {"id": "[CLS]", "ids": [2], "tokens": ["[CLS]"]},
+                    "[SEP]": {"id": "[SEP]", "ids": [3], "tokens": ["[SEP]"]}
+                }
+            },
+            "decoder": {"type": "WordPiece", "prefix": "##", "cleanup": true},
+            "model": {
+                "type": "WordPiece",
+                "unk_token": "[UNK]",
+                "continuing_subword_prefix": "##",
+                "max_input_chars_per_word": 100,
+                "vocab": {"[PAD]": 0, "[UNK]": 1, "[CLS]": 2, "[SEP]": 3, "a": 4, "b": 5, " ": 6}
+            }
+        });
+        let mut vocab = document["model"]["vocab"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(piece, id)| (id.as_u64().unwrap(), piece.as_bytes()))
+            .collect::<Vec<_>>();
+        vocab.sort_unstable();
+        let mut tokeneer = Tokeneer::new(Lpe::new(vocab.into_iter().map(|(_, p)| p), 1));
+        tokeneer
+            .set_post_processor_from_tokenizer_json(&document)
+            .unwrap();
+
+        let single = tokeneer.encode_detailed("ab");
+        assert_eq!(single.ids(), [2, 4, 5, 3]);
+        assert_eq!(single.type_ids(), [0, 0, 0, 0]);
+        assert_eq!(single.special_tokens_mask(), [1, 0, 0, 1]);
+        let pair = tokeneer.encode_pair("a", "b a");
+        assert_eq!(pair.ids(), [2, 4, 3, 5, 6, 4, 3]);
+        assert_eq!(pair.type_ids(), [0, 0, 0, 1, 1, 1, 1]);
+        assert_eq!(pair.special_tokens_mask(), [1, 0, 1, 0, 0, 0, 1]);
+
+        // 按 roberta-base 的 tokenizer.json 裁剪的文档，第二段及其两侧的 </s> 类型为 1
+        let document = json!({
+            "version": "1.0",
+            "added_tokens": [
+                {"id": 0, "content": "<s>", "single_word": false, "lstrip": false, "rstrip": false, "normalized": true, "special": true},
+                {"id": 2, "content": "</s>", "single_word": false, "lstrip": false, "rstrip": false, "normalized": true, "special": true}
+            ],
+            "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": true},
+            "post_processor": {
+                "type": "RobertaProcessing",
+                "sep": ["</s>", 2],
+                "cls": ["<s>", 0],
+                "trim_offsets": true,
+                "add_prefix_space": false
+            },
+            "model": {
+                "type": "BPE",
+                "vocab": {"<s>": 0, "<pad>": 1, "</s>": 2, "<unk>": 3, "a": 4, "b": 5},
+                "merges": []
+            }
+        });
+        let mut roberta = Tokeneer::new(Lpe::new(
+            ["<s>", "<pad>", "</s>", "<unk>", "a", "b"].map(str::as_bytes),
+            3,
+        ));
+        roberta
+            .set_post_processor_from_tokenizer_json(&document)
+            .unwrap();
+        let single = roberta.encode_detailed("ab");
+        assert_eq!(single.ids(), [0, 4, 5, 2]);
+        assert_eq!(single.type_ids(), [0, 0, 0, 0]);
+        let pair = roberta.encode_pair("a", "b");
+        assert_eq!(pair.ids(), [0, 4, 2, 2, 5, 2]);
+        assert_eq!(pair.type_ids(), [0, 0, 0, 1, 1, 1]);
+        assert_eq!(pair.special_tokens_mask(), [1, 0, 1, 1, 0, 1]);
+
+        // 没有 post_processor 字段时清除后处理
+        tokeneer
+            .set_post_processor_from_tokenizer_json(&json!({"version": "1.0"}))
+            .unwrap();
+        assert_eq!(tokeneer.encode_detailed("ab").ids(), [4, 5]);
+        assert_eq!(
+            tokeneer.set_post_processor_from_tokenizer_json(&json!([])),
+            Err(crate::PostProcessorError::Malformed("post_processor"))
+        );
+    }
+
+    #[test]
+    fn test_many_special() {
+        let mut tokeneer = test_tokeneer();
+        for i in 0..1000 {
+            tokeneer.extend_special([(format!("<u{i}>"), vec![i % 6])]);
+        }
+        let text = "a<u7><u999>b<u1000><pair>";
+        let tokens = tokeneer.encode(text);
+        assert_eq!(tokens[..4], [2, 1, 3, 3]);
+        assert_eq!(tokens[tokens.len() - 2..], [2, 3]);
+        // 改用正则表达式匹配，结果不变
+        tokeneer.set_special_regex_limit(usize::MAX);
+        assert_eq!(tokeneer.encode(text), tokens);
+    }
+
+    #[test]
+    fn test_thread_workspace() {
+        let mut tokeneer = test_tokeneer();
+        let long = "dcba".repeat(100);
+        let texts = ["abcd<pair>ba", "", &long, "a"];
+        let expected = texts.map(|text| tokeneer.encode(text));
+        // 复用的缓冲区在不同长度的文本之间不残留状态
+        for (text, expected) in zip(texts, &expected) {
+            assert_eq!(&tokeneer.encode(text), expected);
+        }
+        tokeneer.set_thread_workspace(false);
+        for (text, expected) in zip(texts, &expected) {
+            assert_eq!(&tokeneer.encode(text), expected);
+        }
+    }
+
+    #[test]
+    fn test_empty_special() {
+        // 没有特殊词时不构造匹配器，空的特殊词不参与匹配
+        let mut tokeneer = Tokeneer::new(Lpe::new(["<unk>", "a"].map(str::as_bytes), 0));
+        assert_eq!(tokeneer.encode("aa"), [1, 1]);
+        tokeneer.extend_special([(String::new(), vec![0])]);
+        assert_eq!(tokeneer.encode("aa"), [1, 1]);
+    }
+
+    #[test]
+    fn test_special_capture() {
+        let mut tokeneer = test_tokeneer();
+        let fingerprint = tokeneer.fingerprint();
+        tokeneer.extend_special([("<t:{name}>".to_string(), vec![1, SPECIAL_CAPTURE, 5])]);
+        assert_ne!(tokeneer.fingerprint(), fingerprint);
+        // 占位符匹配的文本由分词算法编码，插入两段固定的词序列之间
+        assert_eq!(tokeneer.encode("a<t:ab>b"), [2, 1, 4, 5, 3]);
+        assert_eq!(tokeneer.encode("<t:><t:b a>"), [1, 5, 1, 3, 5, 2, 5]);
+        let segments = tokeneer.split("<t:ba>").collect::<Vec<_>>();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[1].text(), "ba");
+        // 找不到后一段固定文本时按一般文本编码
+        assert_eq!(
+            tokeneer.encode("<t:a"),
+            tokeneer
+                .internal()
+                .encode("<t:a")
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+        let base = test_tokeneer();
+        let plain = |text: &str| base.internal().encode(text).into_iter().collect::<Vec<_>>();
+        // 占位符不跨越其他特殊词
+        let mut expected = plain("<t:a ");
+        expected.extend([1]);
+        expected.extend(plain(" b>"));
+        assert_eq!(tokeneer.encode("<t:a <s> b>"), expected);
+        tokeneer.extend_special([("<|end|>".to_string(), vec![5])]);
+        let mut expected = plain("<t:a");
+        expected.extend([5]);
+        expected.extend(plain("b>"));
+        assert_eq!(tokeneer.encode("<t:a<|end|>b>"), expected);
+        // 占位符匹配的文本不超过长度上限
+        tokeneer.set_special_capture_limit(1);
+        assert_eq!(tokeneer.encode("<t:b>"), [1, 3, 5]);
+        assert_eq!(tokeneer.encode("<t:ab>"), plain("<t:ab>"));
+        tokeneer.set_special_capture_limit(256);
+        // 大量找不到后一段固定文本的前缀不会使查找退化为平方复杂度
+        let text = "<t:".repeat(40_000);
+        assert_eq!(tokeneer.encode(&text), plain(&text));
+        // 没有标记的词序列按字面匹配
+        tokeneer.extend_special([("<u:{x}>".to_string(), vec![2])]);
+        assert_eq!(tokeneer.encode("<u:{x}><u:a>")[0], 2);
+        assert_eq!(
+            tokeneer.try_extend_special([("<t:{id}>".to_string(), vec![SPECIAL_CAPTURE])], false),
+            Err(SpecialTokenError::Redefined {
+                special: "<t:{id}>".into()
+            })
+        );
+        assert_eq!(
+            tokeneer
+                .try_extend_special([("<v:{id}>".to_string(), vec![SPECIAL_CAPTURE, 9])], false),
+            Err(SpecialTokenError::OutOfRange {
+                special: "<v:{id}>".into(),
+                token: 9
+            })
+        );
+        // 替换特殊词时一并移除占位符模式
+        tokeneer.replace_special([]);
+        assert_eq!(tokeneer.encode("<t:ab>")[..1], [0]);
+    }
+
+    #[test]
+    #[should_panic = "SPECIAL_CAPTURE without a placeholder"]
+    fn test_special_capture_literal() {
+        let mut tokeneer = test_tokeneer();
+        tokeneer.extend_special([("<x>".to_string(), vec![SPECIAL_CAPTURE])]);
+    }
+
+    #[test]
+    fn test_placeholder() {
+        let mut tokeneer = test_tokeneer();
+        let fingerprint = tokeneer.fingerprint();
+        tokeneer.set_placeholder("<image>", 1, 3);
+        assert_ne!(tokeneer.fingerprint(), fingerprint);
+        assert_eq!(
+            tokeneer.encode("a<image>b<image>"),
+            [2, 1, 1, 1, 3, 1, 1, 1]
+        );
+        let encoding = tokeneer.encode_detailed("a<image>");
+        assert_eq!(encoding.special_tokens_mask(), [0, 1, 1, 1]);
+        assert_eq!(encoding.offsets()[1..], [1..8, 1..8, 1..8]);
+        // 更新展开长度，保留空白设置
+        assert!(tokeneer.set_special_strip("<image>", true, false));
+        tokeneer.set_placeholder("<image>", 1, 2);
+        assert_eq!(tokeneer.encode("a <image>"), [2, 1, 1]);
+    }
+
+    #[test]
+    fn test_decode_token() {
+        let tokeneer = test_tokeneer();
+        assert_eq!(tokeneer.decode_token(4), Some("ab"));
+        assert_eq!(tokeneer.decode_token(6), None);
+    }
+
+    #[test]
+    fn test_unk_policy() {
+        let mut tokeneer = test_tokeneer();
+        assert_eq!(tokeneer.decode(&[2, 0, 99]), "a<unk><unk>");
+
+        tokeneer.set_unk_policy(UnkPolicy::Empty);
+        assert_eq!(tokeneer.decode(&[2, 0, 99]), "a");
+        assert_eq!(tokeneer.decode_token(0), Some(""));
+
+        tokeneer.set_unk_policy(UnkPolicy::Error);
+        assert_eq!(
+            tokeneer.try_decode(&[2, 99, 0]),
+            Err(DecodeError {
+                token: 99,
+                index: 1
+            })
+        );
+        assert_eq!(tokeneer.decode_token(0), None);
+
+        // 只有超出词表的词序号使用占位符
+        tokeneer.set_unk_policy(UnkPolicy::Placeholder);
+        assert_eq!(tokeneer.decode(&[2, 0, 99]), "a<unk>\u{FFFD}[99]");
+        tokeneer.set_unk_placeholder("<bad:{id}>");
+        assert_eq!(tokeneer.decode(&[99, 2, 100]), "<bad:99>a<bad:100>");
+        assert_eq!(tokeneer.decode_token(99), None);
+    }
+
+    #[test]
+    fn test_escape_bytes() {
+        let mut tokeneer = Tokeneer::new(Lpe::new(
+            ["<unk>", "<0xE4>", "a", "<0x61>"].map(str::as_bytes),
+            0,
+        ));
+        assert_eq!(tokeneer.internal().fallback_byte(1), Some(0xe4));
+        assert_eq!(tokeneer.internal().fallback_byte(2), None);
+        assert_eq!(tokeneer.internal().fallback_byte(0), None);
+
+        tokeneer.set_escape_bytes(true);
+        assert_eq!(tokeneer.decode(&[2, 1, 3]), "a<0xE4><0x61>");
+        assert_eq!(tokeneer.decode_token(1), Some("<0xE4>"));
+    }
+
+    #[test]
+    fn test_decode_to() {
+        let mut tokeneer = test_tokeneer();
+        let mut buf = Vec::new();
+        tokeneer.decode_to(&[1, 4, 5, 0], &mut buf).unwrap();
+        assert_eq!(buf, tokeneer.decode(&[1, 4, 5, 0]).as_bytes());
+
+        tokeneer.set_unk_policy(UnkPolicy::Error);
+        let e = tokeneer.decode_to(&[2, 0], io::sink()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // 跨词的 utf-8 字符
+        let tokeneer = Tokeneer::new(Lpe::new(
+            ["<unk>", "<0xE4>", "<0xBD>", "<0xA0>", "a"].map(str::as_bytes),
+            0,
+        ));
+        let mut buf = Vec::new();
+        tokeneer.decode_to(&[4, 1, 2, 3, 4], &mut buf).unwrap();
+        assert_eq!(buf, "a你a".as_bytes());
+        // 不合法的 utf-8 序列与 decode 一样替换为 U+FFFD
+        for tokens in [
+            &[1, 2][..],
+            &[1, 4],
+            &[3],
+            &[3, 1, 2, 3, 1],
+            &[1, 1, 2, 4, 2],
+        ] {
+            let mut buf = Vec::new();
+            tokeneer.decode_to(tokens, &mut buf).unwrap();
+            assert_eq!(String::from_utf8(buf).unwrap(), tokeneer.decode(tokens));
+        }
+        assert_eq!(tokeneer.decode(&[1, 4]), "\u{FFFD}a");
+    }
+
+    #[test]
+    fn test_decode_preset() {
+        let mut tokeneer =
+            Tokeneer::new(Lpe::new(["<unk>", "▁a", "▁b", "b"].map(str::as_bytes), 0));
+        tokeneer.set_decode_preset(DecodePreset::LlamaSentencepiece);
+        assert_eq!(tokeneer.decode(&[1, 2, 3]), "a bb");
+        let mut buf = Vec::new();
+        tokeneer.decode_to(&[1, 2, 3], &mut buf).unwrap();
+        assert_eq!(buf, b"a bb");
+    }
+
+    #[test]
+    fn test_encode_with_offsets() {
+        let tokeneer = test_tokeneer();
+        assert_eq!(
+            tokeneer.encode_with_offsets("ab<pair>x"),
+            [(4, 0..2), (2, 2..8), (3, 2..8), (0, 8..9)]
+        );
+    }
+
+    #[test]
+    fn test_encode_bounded() {
+        let tokeneer = test_tokeneer();
+        let text = "ab<pair>a c";
+        assert_eq!(tokeneer.encode(text), [4, 2, 3, 2, 5, 0]);
+        assert_eq!(tokeneer.encode_bounded(text, 1), (vec![4], 2, true));
+        // 特殊词不截断
+        assert_eq!(tokeneer.encode_bounded(text, 2), (vec![4], 2, true));
+        assert_eq!(
+            tokeneer.encode_bounded(text, 5),
+            (vec![4, 2, 3, 2, 5], 10, true)
+        );
+        assert_eq!(
+            tokeneer.encode_bounded(text, 6),
+            (tokeneer.encode(text), text.len(), false)
+        );
+    }
+
+    #[test]
+    fn test_encode_pretokenized() {
+        let tokeneer = test_tokeneer();
+        // "a" 和 "b" 分属不同的词，不会合并为 "ab"
+        let encoding = tokeneer.encode_pretokenized(&["a", "b", "ab<s>"]);
+        assert_eq!(encoding.ids(), [2, 3, 4, 1]);
+        assert_eq!(encoding.word_ids(), [Some(0), Some(1), Some(2), Some(2)]);
+        assert_eq!(encoding.special_tokens_mask(), [0, 0, 0, 1]);
+        assert_eq!(tokeneer.encode_detailed("ab").word_ids(), [None]);
+    }
+
+    #[test]
+    fn test_encode_with() {
+        let tokeneer = test_tokeneer();
+        let text = "<s>ab a<pair>";
+        assert_eq!(
+            tokeneer.encode_with(text, EncodeOptions::default()),
+            tokeneer.encode(text)
+        );
+        let options = EncodeOptions::default().with_mode(crate::EncodeMode::Exact);
+        assert_eq!(tokeneer.encode_with(text, options), tokeneer.encode(text));
+    }
+
+    #[test]
+    fn test_encode_cancellable() {
+        use std::{cell::Cell, time::Duration};
+
+        let tokeneer = test_tokeneer();
+        let text = "<s>ab a<pair>";
+        let (tokens, pos, cancelled) = tokeneer.encode_cancellable(text, || false);
+        assert_eq!(
+            (tokens, pos, cancelled),
+            (tokeneer.encode(text), text.len(), false)
+        );
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            tokeneer.encode_until(text, deadline).0,
+            tokeneer.encode(text)
+        );
+
+        // 在第二段中取消，中断的段整体丢弃
+        let checks = Cell::new(0);
+        let (tokens, pos, cancelled) = tokeneer.encode_cancellable(text, || {
+            checks.set(checks.get() + 1);
+            checks.get() > 2
+        });
+        assert!(cancelled);
+        assert_eq!(pos, 3);
+        assert_eq!(tokens, tokeneer.encode("<s>"));
+
+        let (tokens, pos, cancelled) = tokeneer.encode_until(text, Instant::now());
+        assert_eq!((tokens, pos, cancelled), (vec![], 0, true));
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut tokeneer = test_tokeneer();
+        let usage = tokeneer.memory_usage();
+        assert!(usage.vocab > 0 && usage.metadata > 0 && usage.index > 0);
+        assert_eq!(usage.cache, 0);
+        assert_eq!(
+            usage.total(),
+            usage.vocab + usage.metadata + usage.index + usage.special + usage.cache
+        );
+
+        tokeneer.extend_special([("<extra>".to_string(), vec![1, 2])]);
+        assert!(tokeneer.memory_usage().special > usage.special);
+        // 反查表在首次使用时构造，计入缓存
+        tokeneer.internal().warm_token_to_id();
+        assert!(tokeneer.memory_usage().cache > 0);
+    }
+
+    #[test]
+    fn test_try_extend_special() {
+        let mut tokeneer = test_tokeneer();
+        let fingerprint = tokeneer.fingerprint();
+        assert_eq!(
+            tokeneer.try_extend_special(
+                [
+                    ("<x>".to_string(), vec![2]),
+                    ("<y>".to_string(), vec![2, 6])
+                ],
+                false
+            ),
+            Err(SpecialTokenError::OutOfRange {
+                special: "<y>".into(),
+                token: 6
+            })
+        );
+        assert_eq!(
+            tokeneer.try_extend_special([("<pair>".to_string(), vec![2])], false),
+            Err(SpecialTokenError::Redefined {
+                special: "<pair>".into()
+            })
+        );
+        assert_eq!(
+            tokeneer.try_extend_special([("<a>".to_string(), vec![2])], true),
+            Err(SpecialTokenError::Reachable {
+                special: "<a>".into(),
+                token: 2
+            })
+        );
+        assert_eq!(
+            tokeneer.try_extend_special(
+                [("<z>".to_string(), vec![2]), ("<z>".to_string(), vec![3])],
+                false
+            ),
+            Err(SpecialTokenError::Redefined {
+                special: "<z>".into()
+            })
+        );
+        // 检查失败时不注册任何特殊词
+        assert_eq!(tokeneer.fingerprint(), fingerprint);
+
+        assert_eq!(
+            tokeneer.try_extend_special([("<a>".to_string(), vec![2])], false),
+            Ok(())
+        );
+        assert_eq!(tokeneer.encode("<a>"), [2]);
+    }
+
+    #[test]
+    fn test_encode_hash() {
+        let mut tokeneer = test_tokeneer();
+        let hash = tokeneer.encode_hash("<s>ab a<pair>");
+        assert_eq!(hash, tokeneer.encode_hash("<s>ab a<pair>"));
+        assert_ne!(hash, tokeneer.encode_hash("<s>ab a"));
+
+        tokeneer.extend_special([("<b>".to_string(), vec![3])]);
+        assert_ne!(hash, tokeneer.encode_hash("<s>ab a<pair>"));
+    }
 }
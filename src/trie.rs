@@ -0,0 +1,117 @@
+//! 这个模块将词表导出为字节级前缀树，供 Rust 之外的约束解码库使用。
+
+use crate::{utok, Method};
+use std::io::{self, Write};
+
+/// 二进制格式的魔数。
+pub const TRIE_MAGIC: &[u8; 8] = b"TKNTRIE\0";
+
+/// 词表的字节级前缀树。
+///
+/// 根节点序号为 0，每个节点包含按字节升序排列的子节点表，以及以该节点结尾的所有词。
+/// 不同的词可能具有相同的字节序列（例如单字节词和同内容的一般词），因此一个节点可以对应多个词。
+///
+/// 启用 `serde` 特性时可以序列化为 JSON 等格式，结构与 [`VocabTrie::write_binary`] 的二进制格式相同。
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VocabTrie {
+    pub nodes: Vec<TrieNode>,
+}
+
+/// 前缀树节点。
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrieNode {
+    /// (字节, 子节点序号)，按字节升序排列
+    pub children: Vec<(u8, u32)>,
+    /// 以此节点结尾的词
+    pub tokens: Vec<utok>,
+}
+
+impl VocabTrie {
+    /// 从词表构造前缀树，空的词不会被收录。
+    pub fn new(method: &impl Method) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+        for t in 0..method.vocab_size() as utok {
+            let piece = method.decode(t);
+            if piece.is_empty() {
+                continue;
+            }
+            let mut node = 0;
+            for &b in piece {
+                node = match nodes[node].children.binary_search_by_key(&b, |&(b, _)| b) {
+                    Ok(i) => nodes[node].children[i].1 as usize,
+                    Err(i) => {
+                        let next = nodes.len();
+                        nodes[node].children.insert(i, (b, next as _));
+                        nodes.push(TrieNode::default());
+                        next
+                    }
+                };
+            }
+            nodes[node].tokens.push(t);
+        }
+        Self { nodes }
+    }
+
+    /// 查找字节序列对应的节点。
+    pub fn find(&self, bytes: &[u8]) -> Option<&TrieNode> {
+        let mut node = &self.nodes[0];
+        for &b in bytes {
+            let i = node.children.binary_search_by_key(&b, |&(b, _)| b).ok()?;
+            node = &self.nodes[node.children[i].1 as usize];
+        }
+        Some(node)
+    }
+
+    /// 以二进制格式写出前缀树，所有整数均为小端序：
+    ///
+    /// ```text
+    /// magic      : b"TKNTRIE\0"
+    /// node_count : u32
+    /// node * node_count:
+    ///     child_count : u32
+    ///     child * child_count:
+    ///         byte  : u8
+    ///         node  : u32
+    ///     token_count : u32
+    ///     token * token_count : u32
+    /// ```
+    pub fn write_binary(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(TRIE_MAGIC)?;
+        w.write_all(&(self.nodes.len() as u32).to_le_bytes())?;
+        for node in &self.nodes {
+            w.write_all(&(node.children.len() as u32).to_le_bytes())?;
+            for &(b, child) in &node.children {
+                w.write_all(&[b])?;
+                w.write_all(&child.to_le_bytes())?;
+            }
+            w.write_all(&(node.tokens.len() as u32).to_le_bytes())?;
+            for &t in &node.tokens {
+                w.write_all(&t.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod trie_tests {
+    use super::*;
+    use crate::Lpe;
+
+    #[test]
+    fn test_vocab_trie() {
+        let lpe = Lpe::new(["<unk>", "a", "ab", "b"].map(str::as_bytes), 0);
+        let trie = VocabTrie::new(&lpe);
+        assert_eq!(trie.find(b"a").unwrap().tokens, [1]);
+        assert_eq!(trie.find(b"ab").unwrap().tokens, [2]);
+        assert!(trie.find(b"<u").unwrap().tokens.is_empty());
+        assert!(trie.find(b"ba").is_none());
+
+        let mut buf = Vec::new();
+        trie.write_binary(&mut buf).unwrap();
+        assert_eq!(&buf[..8], TRIE_MAGIC);
+        assert_eq!(buf[8..12], (trie.nodes.len() as u32).to_le_bytes());
+    }
+}
@@ -1,7 +1,31 @@
-﻿//! 这个模块提供对词表的预处理功能，这些功能适用于多种不同算法的分词器。
+//! 这个模块提供对词表的预处理功能，这些功能适用于多种不同算法的分词器。
 
 use crate::utok;
-use std::{iter::zip, pin::Pin, slice::from_ref};
+use std::{
+    borrow::Cow, collections::HashMap, iter::zip, pin::Pin, slice::from_ref, sync::OnceLock,
+    time::Duration,
+};
+
+/// 词表中字节偏移和长度的存储类型，启用 `wide-offsets` 特性时为 `u64`。
+#[cfg(not(feature = "wide-offsets"))]
+pub(crate) type Offset = u32;
+#[cfg(feature = "wide-offsets")]
+pub(crate) type Offset = u64;
+
+/// 将字节偏移或长度转换为存储类型，超出范围时 panic。
+#[inline]
+pub(crate) fn offset(n: usize) -> Offset {
+    n.try_into().unwrap_or_else(|_| {
+        panic!("offset {n} overflows the vocab layout, enable the `wide-offsets` feature")
+    })
+}
+
+/// 将词表中的序号转换为词序号，超出范围时 panic。
+#[inline]
+fn token_id(i: usize) -> utok {
+    i.try_into()
+        .unwrap_or_else(|_| panic!("token index {i} overflows utok"))
+}
 
 /// 收集和预处理词表。
 ///
@@ -14,106 +38,378 @@ use std::{iter::zip, pin::Pin, slice::from_ref};
 /// 因此，单个 ASCII 码作为含语义的一般词或者作为单字节词可能有不同的 2 个词序号，必须分离到不同的空间中索引。
 pub(crate) struct CollectedVocab<'s> {
     /// 词序列表，按词序分割存储每个词的字节序列，并对字节词转义
-    pub vocabs: Vec<&'s [u8]>,
+    pub vocabs: Vec<Cow<'s, [u8]>>,
     /// 词序表中片段的总字节数
     pub total_len: usize,
     /// 字节词到词序号的映射
     pub bytes: Box<[utok; 256]>,
+    /// 单字节词的定义问题，没有问题时为 `None`
+    pub byte_error: Option<ByteTokenError>,
 }
 
 impl<'s> CollectedVocab<'s> {
     /// 收集词表，并对字节词进行转义。
-    pub fn collect(vocabs: impl IntoIterator<Item = &'s [u8]>, unk: utok) -> Self {
-        let mut bytes = Box::new([unk; 256]);
+    pub fn collect(vocabs: impl IntoIterator<Item = &'s [u8]>, unk: Option<utok>) -> Self {
+        Self::collect_with(vocabs, unk, as_byte_token, Cow::Borrowed)
+    }
+
+    /// 收集词表，由 `as_byte` 识别单字节词并进行转义，其他词由 `transform` 转换为解码后的形式。
+    pub fn collect_with(
+        vocabs: impl IntoIterator<Item = &'s [u8]>,
+        unk: Option<utok>,
+        as_byte: impl Fn(&[u8]) -> Option<u8>,
+        transform: impl Fn(&'s [u8]) -> Cow<'s, [u8]>,
+    ) -> Self {
+        Self::build(
+            vocabs.into_iter().map(|piece| match as_byte(piece) {
+                Some(b) => Piece::Byte(b),
+                None => Piece::Normal(transform(piece)),
+            }),
+            unk,
+        )
+    }
+
+    /// 收集词表，根据提示决定一个词是否是单字节词，由 `as_byte` 解析单字节词的字节，
+    /// 其他词由 `transform` 转换为解码后的形式。
+    pub fn collect_with_hint(
+        vocabs: impl IntoIterator<Item = &'s [u8]>,
+        is_byte: impl IntoIterator<Item = bool>,
+        unk: Option<utok>,
+        as_byte: impl Fn(&[u8]) -> Option<u8>,
+        transform: impl Fn(&'s [u8]) -> Cow<'s, [u8]>,
+    ) -> Self {
+        Self::build(
+            zip(vocabs, is_byte).map(|(piece, is_byte)| {
+                if is_byte {
+                    Piece::Byte(
+                        as_byte(piece)
+                            .unwrap_or_else(|| panic!("{piece:?} is not a valid byte token")),
+                    )
+                } else {
+                    Piece::Normal(transform(piece))
+                }
+            }),
+            unk,
+        )
+    }
+
+    /// 收集已分类的词。
+    ///
+    /// 多个词表示同一个字节时，序号较大的词生效。
+    /// 没有 <unk> 时，所有字节都必须有单字节词作为回退，否则 panic。
+    fn build(pieces: impl IntoIterator<Item = Piece<'s>>, unk: Option<utok>) -> Self {
+        // 没有 <unk> 时所有位置都会被单字节词覆盖，填充值不会被使用
+        let mut bytes = Box::new([unk.unwrap_or_default(); 256]);
+        let mut defined = [false; 256];
+        let mut duplicates = Vec::new();
         let mut total_len = 0;
-        let vocabs = vocabs
+        let vocabs = pieces
             .into_iter()
             .enumerate()
             .map(|(i, piece)| {
-                let piece = match as_byte_token(piece) {
-                    Some(b) => {
+                let piece = match piece {
+                    Piece::Normal(piece) => piece,
+                    Piece::Byte(b) => {
                         let b = b as usize;
-                        bytes[b] = i as _;
-                        from_ref(&BYTES[b])
+                        if std::mem::replace(&mut defined[b], true) {
+                            duplicates.push((b as u8, bytes[b]))
+                        }
+                        bytes[b] = token_id(i);
+                        Cow::Borrowed(from_ref(&BYTES[b]))
                     }
-                    None => piece,
                 };
                 total_len += piece.len();
                 piece
             })
             .collect();
+        if unk.is_none() {
+            if let Some(b) = defined.iter().position(|&defined| !defined) {
+                panic!("vocab without <unk> must define byte token for {b:#04X}")
+            }
+        }
+        // 完全没有单字节词的词表是合法的，只有部分定义时才报告缺失
+        let missing = if defined.contains(&true) {
+            (0..=255u8).filter(|&b| !defined[b as usize]).collect()
+        } else {
+            Vec::new()
+        };
+        let byte_error =
+            (!duplicates.is_empty() || !missing.is_empty()).then_some(ByteTokenError {
+                duplicates,
+                missing,
+            });
         Self {
             vocabs,
             total_len,
             bytes,
+            byte_error,
         }
     }
+}
 
-    /// 收集词表，根据提示决定一个词是否是单字节词。
-    pub fn collect_with_hint(
-        vocabs: impl IntoIterator<Item = &'s [u8]>,
-        is_byte: impl IntoIterator<Item = bool>,
-        unk: utok,
-    ) -> Self {
-        let mut bytes = Box::new([unk; 256]);
-        let mut total_len = 0;
-        let vocabs = zip(vocabs, is_byte)
-            .enumerate()
-            .map(|(i, (piece, is_byte))| {
-                let piece = if is_byte {
-                    let b = as_byte_token(piece)
-                        .unwrap_or_else(|| panic!("{piece:?} is not a valid byte token"))
-                        as usize;
-                    bytes[b] = i as _;
-                    from_ref(&BYTES[b])
-                } else {
-                    piece
-                };
-                total_len += piece.len();
-                piece
-            })
-            .collect();
-        Self {
-            vocabs,
-            total_len,
-            bytes,
+/// 收集词表时对词的分类。
+enum Piece<'s> {
+    /// 一般词的内容
+    Normal(Cow<'s, [u8]>),
+    /// 单字节词表示的字节
+    Byte(u8),
+}
+
+/// 词表中单字节词的定义问题。
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ByteTokenError {
+    /// 被同一字节的后续定义覆盖的词，记录为字节和被覆盖的词序号
+    pub duplicates: Vec<(u8, utok)>,
+    /// 词表定义了部分单字节词时，没有对应单字节词的字节
+    pub missing: Vec<u8>,
+}
+
+impl std::fmt::Display for ByteTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut sep = "";
+        for (b, token) in &self.duplicates {
+            write!(
+                f,
+                "{sep}byte 0x{b:02X} is redefined, token {token} is shadowed"
+            )?;
+            sep = "; ";
+        }
+        if !self.missing.is_empty() {
+            write!(f, "{sep}{} bytes have no byte token:", self.missing.len())?;
+            for b in &self.missing {
+                write!(f, " 0x{b:02X}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ByteTokenError {}
+
+/// 解析词表文件时发现的格式错误。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadError {
+    /// 文件中没有任何词
+    Empty,
+    /// 从这个字节位置开始的记录被截断
+    Truncated(usize),
+    /// 从这个字节位置开始的记录中的词不是合法的 utf-8
+    InvalidUtf8(usize),
+    /// 从这个字节位置开始的记录无法解析
+    MalformedRecord(usize),
+    /// 这一行（从 0 开始计数）不是合法的词
+    MalformedLine(usize),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "vocab is empty"),
+            Self::Truncated(offset) => write!(f, "record at byte {offset} is truncated"),
+            Self::InvalidUtf8(offset) => {
+                write!(f, "piece of record at byte {offset} is not valid utf-8")
+            }
+            Self::MalformedRecord(offset) => write!(f, "record at byte {offset} is malformed"),
+            Self::MalformedLine(line) => write!(f, "line {line} is not a quoted piece"),
         }
     }
 }
 
+impl std::error::Error for LoadError {}
+
 /// 利用词表中的重复部分压缩词表。
 pub(crate) struct CompressedVocab {
     pub vocabs: Pin<Box<[u8]>>,
     pub slices: Vec<(usize, usize)>,
+    pub stats: CompressStats,
+}
+
+/// 词表压缩的统计信息。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CompressStats {
+    /// 压缩前所有词的总字节数
+    pub total_len: usize,
+    /// 压缩后的字节数
+    pub compressed_len: usize,
+    /// 压缩耗时
+    pub elapsed: Duration,
 }
 
 impl CompressedVocab {
-    pub fn new(vocabs: &[&[u8]], total_len: usize) -> Self {
-        // 创建字符内容缓存
-        let mut slices = vec![(0usize, 0usize); vocabs.len()];
-        let mut text_buf = Vec::<u8>::with_capacity(total_len);
+    pub fn new(vocabs: &[impl AsRef<[u8]> + Sync], total_len: usize) -> Self {
+        let time = std::time::Instant::now();
         let mut indices = (0..vocabs.len()).collect::<Vec<_>>();
         // 对词按内容长度从长到短排序，因为短的内容有可能是长内容的子串，可以避免重复存储相同内容
-        indices.sort_unstable_by_key(|&i| -(vocabs[i].len() as isize));
-        for i in indices {
-            let v = vocabs[i];
+        indices.sort_unstable_by_key(|&i| -(vocabs[i].as_ref().len() as isize));
+
+        #[cfg(feature = "rayon")]
+        let (text_buf, slices) = compress_parallel(vocabs, &indices, total_len);
+        #[cfg(not(feature = "rayon"))]
+        let (text_buf, slices) = {
+            let (text_buf, sorted) = compress(vocabs, &indices, total_len);
+            let mut slices = vec![(0usize, 0usize); vocabs.len()];
+            for (i, slice) in zip(indices, sorted) {
+                slices[i] = slice;
+            }
+            (text_buf, slices)
+        };
+
+        Self {
+            stats: CompressStats {
+                total_len,
+                compressed_len: text_buf.len(),
+                elapsed: time.elapsed(),
+            },
+            // 锁定字符串内容的位置，以实现安全的自引用
+            vocabs: Pin::new(text_buf.into_boxed_slice()),
+            slices,
+        }
+    }
+}
+
+/// 从词的内容反查词序号的哈希表。
+///
+/// 启用 `forbid-unsafe` 特性时键复制词的内容，否则直接引用分词器的词表。
+#[cfg(not(feature = "forbid-unsafe"))]
+pub(crate) type ReverseTable<'a> = HashMap<&'a [u8], utok>;
+#[cfg(feature = "forbid-unsafe")]
+pub(crate) type ReverseTable<'a> = HashMap<Box<[u8]>, utok>;
+
+/// 从词的内容反查词序号的哈希表，首次使用时构造。
+///
+/// 表中的键直接引用分词器的词表内容，因此只能由持有该词表的分词器使用。
+#[derive(Default)]
+pub(crate) struct ReverseMap(OnceLock<ReverseTable<'static>>);
+
+impl ReverseMap {
+    /// 获取哈希表，首次调用时由 `init` 提供所有词，内容相同时后出现的词覆盖先出现的词。
+    ///
+    /// # Safety
+    ///
+    /// `init` 提供的词内容必须在 `self` 的整个生命周期内保持有效且不变。
+    #[cfg(not(feature = "forbid-unsafe"))]
+    pub unsafe fn get_or_init<'a, I>(&'a self, init: impl FnOnce() -> I) -> &'a ReverseTable<'a>
+    where
+        I: IntoIterator<Item = (&'a [u8], utok)>,
+    {
+        self.0.get_or_init(|| {
+            init()
+                .into_iter()
+                .map(|(piece, t)| (unsafe { &*(piece as *const [u8]) }, t))
+                .collect()
+        })
+    }
+
+    /// 获取哈希表，首次调用时由 `init` 提供所有词，内容相同时后出现的词覆盖先出现的词。
+    #[cfg(feature = "forbid-unsafe")]
+    pub fn get_or_init<'a, I>(&'a self, init: impl FnOnce() -> I) -> &'a ReverseTable<'a>
+    where
+        I: IntoIterator<Item = (&'a [u8], utok)>,
+    {
+        self.0.get_or_init(|| {
+            init()
+                .into_iter()
+                .map(|(piece, t)| (Box::from(piece), t))
+                .collect()
+        })
+    }
+
+    /// 反查表占用的字节数，尚未构造时为 0。
+    pub fn memory_size(&self) -> usize {
+        self.0.get().map_or(0, |map| {
+            let size = crate::memory::hash_map_size(map);
+            #[cfg(feature = "forbid-unsafe")]
+            let size = size + map.keys().map(|k| k.len()).sum::<usize>();
+            size
+        })
+    }
+}
+
+/// 将已知是合法 utf-8 的词转换为字符串。
+///
+/// 启用 `forbid-unsafe` 特性时检查内容，不合法时 panic。
+#[inline]
+pub(crate) fn as_str(piece: &[u8]) -> &str {
+    #[cfg(not(feature = "forbid-unsafe"))]
+    {
+        unsafe { std::str::from_utf8_unchecked(piece) }
+    }
+    #[cfg(feature = "forbid-unsafe")]
+    {
+        std::str::from_utf8(piece).expect("piece is not valid utf-8")
+    }
+}
+
+/// 按 `indices` 的顺序将词写入缓存，返回缓存和每个词在缓存中的位置，位置与 `indices` 一一对应。
+fn compress(
+    vocabs: &[impl AsRef<[u8]>],
+    indices: &[usize],
+    capacity: usize,
+) -> (Vec<u8>, Vec<(usize, usize)>) {
+    let mut text_buf = Vec::<u8>::with_capacity(capacity);
+    let slices = indices
+        .iter()
+        .map(|&i| {
+            let v = vocabs[i].as_ref();
             // 查找子串，若存在则复用，否则将新的内容追加到缓存
             let off = memchr::memmem::find(&text_buf, v).unwrap_or_else(|| {
                 let off = text_buf.len();
                 text_buf.extend(v);
                 off
             });
-            slices[i] = (off, v.len());
-        }
-        Self {
-            // 锁定字符串内容的位置，以实现安全的自引用
-            vocabs: unsafe { Pin::new_unchecked(text_buf.into_boxed_slice()) },
-            slices,
+            (off, v.len())
+        })
+        .collect();
+    (text_buf, slices)
+}
+
+/// 将词轮流分配到多个分块并行压缩，再拼接各分块的缓存。
+///
+/// 每个分块中的词都保持从长到短的顺序，因此各分块的压缩率接近单线程压缩，
+/// 但不同分块之间的重复内容不会被复用。
+#[cfg(feature = "rayon")]
+fn compress_parallel(
+    vocabs: &[impl AsRef<[u8]> + Sync],
+    indices: &[usize],
+    total_len: usize,
+) -> (Vec<u8>, Vec<(usize, usize)>) {
+    use rayon::prelude::*;
+
+    // 词表较小时并行的开销大于收益
+    const MIN_CHUNK_SIZE: usize = 4096;
+    let n = rayon::current_num_threads()
+        .min(vocabs.len() / MIN_CHUNK_SIZE)
+        .max(1);
+
+    let chunks = (0..n)
+        .into_par_iter()
+        .map(|k| {
+            let indices = indices
+                .iter()
+                .copied()
+                .skip(k)
+                .step_by(n)
+                .collect::<Vec<_>>();
+            let (text_buf, slices) = compress(vocabs, &indices, total_len / n);
+            (indices, text_buf, slices)
+        })
+        .collect::<Vec<_>>();
+
+    // 合并各分块，将分块内的偏移转换为全局偏移
+    let mut text_buf = Vec::with_capacity(chunks.iter().map(|(_, buf, _)| buf.len()).sum());
+    let mut slices = vec![(0usize, 0usize); vocabs.len()];
+    for (indices, buf, chunk_slices) in chunks {
+        let base = text_buf.len();
+        for (i, (off, len)) in zip(indices, chunk_slices) {
+            slices[i] = (base + off, len);
         }
+        text_buf.extend(buf);
     }
+    (text_buf, slices)
 }
 
-const BYTES: [u8; 256] = {
+/// 全部 256 个字节按值排列，单字节词可以直接引用其中的元素作为内容。
+pub const BYTES: [u8; 256] = {
     let mut bytes = [0u8; 256];
     let mut i = 0usize;
     while i < 256 {
@@ -123,23 +419,152 @@ const BYTES: [u8; 256] = {
     bytes
 };
 
-const fn as_byte_token(piece: &[u8]) -> Option<u8> {
+/// 单字节词的转义文本，形如 `<0xAB>`，与 [`as_byte_token`] 互逆。
+pub const BYTE_ESCAPES: [[u8; 6]; 256] = {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut escapes = [[0u8; 6]; 256];
+    let mut i = 0usize;
+    while i < 256 {
+        escapes[i] = [b'<', b'0', b'x', HEX[i >> 4], HEX[i & 0xf], b'>'];
+        i += 1;
+    }
+    escapes
+};
+
+/// 解析形如 `<0xAB>` 的单字节词，十六进制数字不区分大小写，其他词返回 `None`。
+pub const fn as_byte_token(piece: &[u8]) -> Option<u8> {
     // 按结构分解并转换
     match piece {
-        &[b'<', b'0', b'x', a, b, b'>'] if a.is_ascii_hexdigit() && b.is_ascii_hexdigit() => {
-            // ascii 转数字
-            #[inline(always)]
-            const fn to_num(c: u8) -> u8 {
-                match c {
-                    b'0'..=b'9' => c - b'0',
-                    b'a'..=b'f' => c - b'a' + 10,
-                    b'A'..=b'F' => c - b'A' + 10,
-                    _ => unreachable!(),
-                }
-            }
+        &[b'<', b'0', b'x', a, b, b'>'] => hex_byte(a, b),
+        _ => None,
+    }
+}
+
+/// 解析形如 `[0xAB]` 的单字节词，十六进制数字不区分大小写，其他词返回 `None`。
+pub const fn as_bracket_byte_token(piece: &[u8]) -> Option<u8> {
+    match piece {
+        &[b'[', b'0', b'x', a, b, b']'] => hex_byte(a, b),
+        _ => None,
+    }
+}
+
+/// 解析字节级词表（如 GPT-2）中由单个字符表示的字节，其他词返回 `None`。
+///
+/// 这类词表将可见字节映射为同码点的字符，其余 68 个字节按顺序映射为从 `U+0100` 开始的字符。
+pub fn as_byte_level_token(piece: &[u8]) -> Option<u8> {
+    const fn visible(b: u32) -> bool {
+        matches!(b, 0x21..=0x7e | 0xa1..=0xac | 0xae..=0xff)
+    }
+
+    let mut chars = std::str::from_utf8(piece).ok()?.chars();
+    let c = chars.next()? as u32;
+    if chars.next().is_some() {
+        return None;
+    }
+    match c {
+        c if visible(c) => Some(c as u8),
+        0x100.. => (0..256)
+            .filter(|&b| !visible(b))
+            .nth((c - 0x100) as usize)
+            .map(|b| b as u8),
+        _ => None,
+    }
+}
+
+/// 将 sentencepiece 词表中表示空格的 `▁`（U+2581）替换为空格，可用作收集词表时的转换。
+pub fn replace_metaspace(piece: &[u8]) -> Cow<'_, [u8]> {
+    const METASPACE: &[u8] = "▁".as_bytes();
+    let mut matches = memchr::memmem::find_iter(piece, METASPACE).peekable();
+    if matches.peek().is_none() {
+        return Cow::Borrowed(piece);
+    }
+    let mut ans = Vec::with_capacity(piece.len());
+    let mut last = 0;
+    for i in matches {
+        ans.extend_from_slice(&piece[last..i]);
+        ans.push(b' ');
+        last = i + METASPACE.len();
+    }
+    ans.extend_from_slice(&piece[last..]);
+    Cow::Owned(ans)
+}
 
-            Some(to_num(a) * 16 + to_num(b))
+/// 将两个十六进制数字转换为字节。
+const fn hex_byte(a: u8, b: u8) -> Option<u8> {
+    // ascii 转数字
+    #[inline(always)]
+    const fn to_num(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None,
         }
+    }
+
+    match (to_num(a), to_num(b)) {
+        (Some(a), Some(b)) => Some(a * 16 + b),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod vocab_tests {
+    use super::*;
+
+    #[test]
+    fn test_compress() {
+        let pieces = (0..10000).map(|i| format!("{i:x}")).collect::<Vec<_>>();
+        let vocabs = pieces.iter().map(|s| s.as_bytes()).collect::<Vec<_>>();
+        let total_len = vocabs.iter().map(|v| v.len()).sum();
+
+        let CompressedVocab {
+            vocabs: buf,
+            slices,
+            stats,
+        } = CompressedVocab::new(&vocabs, total_len);
+        assert_eq!(stats.compressed_len, buf.len());
+        assert!(stats.compressed_len < total_len);
+        for (v, (off, len)) in zip(vocabs, slices) {
+            assert_eq!(&buf[off..][..len], v);
+        }
+    }
+
+    #[test]
+    fn test_byte_escapes() {
+        for b in 0..=255u8 {
+            assert_eq!(as_byte_token(&BYTE_ESCAPES[b as usize]), Some(b));
+            assert_eq!(BYTES[b as usize], b);
+        }
+        assert_eq!(as_byte_token(b"<0xab>"), Some(0xab));
+        assert_eq!(as_byte_token(b"<0xg0>"), None);
+        assert_eq!(as_byte_token(b"a"), None);
+
+        assert_eq!(as_bracket_byte_token(b"[0x0A]"), Some(0x0a));
+        assert_eq!(as_bracket_byte_token(b"<0x0A>"), None);
+
+        assert_eq!(as_byte_level_token("a".as_bytes()), Some(b'a'));
+        assert_eq!(as_byte_level_token("Ġ".as_bytes()), Some(b' '));
+        assert_eq!(as_byte_level_token("Ā".as_bytes()), Some(0));
+        assert_eq!(as_byte_level_token("Ń".as_bytes()), Some(0xad));
+        assert_eq!(as_byte_level_token("Ņ".as_bytes()), None);
+        assert_eq!(as_byte_level_token("ab".as_bytes()), None);
+    }
+
+    #[test]
+    fn test_byte_error() {
+        let collected = CollectedVocab::collect(
+            ["<unk>", "<0x00>", "<0x01>", "<0x00>"].map(str::as_bytes),
+            Some(0),
+        );
+        assert_eq!(collected.bytes[0], 3);
+        let err = collected.byte_error.unwrap();
+        assert_eq!(err.duplicates, [(0, 1)]);
+        assert_eq!(err.missing, (2..=255).collect::<Vec<u8>>());
+        assert!(
+            CollectedVocab::collect(["<unk>", "a"].map(str::as_bytes), Some(0))
+                .byte_error
+                .is_none()
+        );
+    }
+}